@@ -0,0 +1,56 @@
+use std::hint::black_box;
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use emulator::chip8::Chip8;
+use emulator::isa::OpCode;
+
+// One word for each kind of instruction decode has to tell apart
+const WORDS: [u16; 12] = [
+    0x00E0, 0x00EE, 0x1234, 0x2345, 0x3A12, 0x6B34, 0x8124, 0x8AB6, 0xA456, 0xD125, 0xE59E, 0xF365,
+];
+
+fn bench_from_u16(c: &mut Criterion) {
+    c.bench_function("OpCode::from_u16", |b| {
+        b.iter(|| {
+            for word in WORDS {
+                black_box(OpCode::from_u16(black_box(word)));
+            }
+        })
+    });
+}
+
+fn bench_execute(c: &mut Criterion) {
+    // Register and memory instructions only, so the state stays valid between iterations
+    let ops = [
+        OpCode::LD_vx_byte { x: 1, kk: 0x42 },
+        OpCode::ADD_vx_byte { x: 1, kk: 0x01 },
+        OpCode::ADD_vx_vy { x: 2, y: 1 },
+        OpCode::SUB_vx_vy { x: 2, y: 1 },
+        OpCode::SHL_vx_vy { x: 3, y: 1 },
+        OpCode::XOR_vx_vy { x: 4, y: 2 },
+        OpCode::SE_vx_byte { x: 1, kk: 0x00 },
+        OpCode::LD_I_addr { addr: 0x300 },
+        OpCode::LD_B_vx { x: 2 },
+        OpCode::LD_vx_I { x: 2 },
+    ];
+    let mut chip8 = Chip8::new();
+    c.bench_function("Chip8::execute", |b| {
+        b.iter(|| {
+            for op in ops {
+                chip8.execute(black_box(op)).unwrap();
+            }
+        })
+    });
+}
+
+fn bench_draw_sprite(c: &mut Criterion) {
+    let mut chip8 = Chip8::new();
+    // 15 rows of font data straddling the right edge, so clipping is exercised too
+    chip8.i = 0x000;
+    c.bench_function("Chip8::draw_sprite", |b| {
+        b.iter(|| chip8.draw_sprite(black_box(60), black_box(10), black_box(15)))
+    });
+}
+
+criterion_group!(benches, bench_from_u16, bench_execute, bench_draw_sprite);
+criterion_main!(benches);
@@ -0,0 +1,43 @@
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use emulator::chip8::Chip8;
+
+const FRAMES: u32 = 60;
+
+fn load(rom: &str, decode_cache: bool) -> Chip8 {
+    let path = format!("{}/roms/{}", env!("CARGO_MANIFEST_DIR"), rom);
+    let data = std::fs::read(&path).expect("missing ROM");
+    let mut chip8 = Chip8::new();
+    chip8.set_decode_cache(decode_cache);
+    chip8.ROM_loader(&data).unwrap();
+    chip8
+}
+
+// One second of emulated time at the given instructions per frame
+fn run(chip8: &mut Chip8, ipf: u32) {
+    for _ in 0..FRAMES {
+        for _ in 0..ipf {
+            chip8.step().unwrap();
+        }
+        chip8.vblank();
+    }
+}
+
+fn bench_decode_cache(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode_cache");
+    for ipf in [1_000, 5_000] {
+        for decode_cache in [false, true] {
+            let name = if decode_cache { "cached" } else { "uncached" };
+            group.bench_with_input(BenchmarkId::new(name, ipf), &ipf, |b, &ipf| {
+                b.iter_batched(
+                    || load("INVADERS", decode_cache),
+                    |mut chip8| run(&mut chip8, ipf),
+                    criterion::BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_decode_cache);
+criterion_main!(benches);
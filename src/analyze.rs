@@ -0,0 +1,998 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::Write;
+
+use crate::chip8::ROM_START;
+use crate::disasm;
+use crate::isa::OpCode;
+use crate::quirks::{PROFILES, Quirks};
+
+// Static analysis of a ROM without running it. Code is found by following control flow
+// from 0x200 through jumps, skips and calls, then a dataflow pass over the result tracks
+// which registers have been set, what I points at and where quirks make a difference.
+
+// How many places the report shows for each quirk a ROM depends on
+const EXAMPLES: usize = 3;
+
+// Something worth a look, at the address of the instruction it's about
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Finding {
+    pub addr: u16,
+    pub message: String,
+}
+
+// The quirks that change what a program computes. Display wait and sprite wrapping only
+// change timing and what's on screen at the edges, so they're listed but not judged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Quirk {
+    VfReset,
+    ShiftVy,
+    MemoryIncrement,
+    JumpVx,
+}
+
+impl Quirk {
+    fn name(self) -> &'static str {
+        match self {
+            Quirk::VfReset => "vf reset",
+            Quirk::ShiftVy => "shift vy",
+            Quirk::MemoryIncrement => "memory increment",
+            Quirk::JumpVx => "jump vx",
+        }
+    }
+
+    fn of(self, quirks: &Quirks) -> bool {
+        match self {
+            Quirk::VfReset => quirks.vf_reset,
+            Quirk::ShiftVy => quirks.shift_vy,
+            Quirk::MemoryIncrement => quirks.memory_increment,
+            Quirk::JumpVx => quirks.jump_vx,
+        }
+    }
+}
+
+// A place where the result depends on a quirk, and which setting it seems to expect, if
+// that can be told
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Evidence {
+    quirk: Quirk,
+    needs: Option<bool>,
+    addr: u16,
+    why: String,
+}
+
+// A subroutine, or the main program at 0x200
+#[derive(Default)]
+struct Procedure {
+    body: BTreeSet<u16>,
+    returns: bool,
+    // The CALL instructions that go here
+    callers: BTreeSet<u16>,
+}
+
+// What I holds, as far as the analysis can tell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pointer {
+    Unset,
+    Addr(u16),
+    Font,
+    Unknown,
+}
+
+// What's known before an instruction runs, on every path that gets there
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Facts {
+    // Registers that have been set, V0-VF then I
+    set: u32,
+    i: Pointer,
+    // I was last changed by LD [I], Vx or LD Vx, [I], so depends on memory increment
+    i_moved: bool,
+    // VF was last changed by OR, AND or XOR, so depends on vf reset
+    vf_logic: bool,
+}
+
+const I_BIT: u32 = 1 << 16;
+
+impl Facts {
+    const START: Facts = Facts {
+        set: 0,
+        i: Pointer::Unset,
+        i_moved: false,
+        vf_logic: false,
+    };
+
+    // After a CALL to a subroutine that leaves `summary` behind
+    fn after_call(self, summary: Facts) -> Facts {
+        let i = summary.set & I_BIT != 0;
+        let vf = summary.set & v(0xF) != 0;
+        Facts {
+            set: self.set | summary.set,
+            i: if i { summary.i } else { self.i },
+            i_moved: if i { summary.i_moved } else { self.i_moved },
+            vf_logic: if vf { summary.vf_logic } else { self.vf_logic },
+        }
+    }
+
+    fn meet(self, other: Facts) -> Facts {
+        Facts {
+            set: self.set & other.set,
+            i: if self.i == other.i {
+                self.i
+            } else {
+                Pointer::Unknown
+            },
+            i_moved: self.i_moved || other.i_moved,
+            vf_logic: self.vf_logic || other.vf_logic,
+        }
+    }
+}
+
+fn v(x: u8) -> u32 {
+    1 << x
+}
+
+// V0 up to Vx
+fn v_through(x: u8) -> u32 {
+    (1u32 << (x + 1)) - 1
+}
+
+// Which registers an instruction reads and writes, as V0-VF and I bits
+fn registers(op: OpCode) -> (u32, u32) {
+    let vf = v(0xF);
+    match op {
+        OpCode::Cls | OpCode::Ret | OpCode::Jp { .. } | OpCode::Call { .. } => (0, 0),
+        OpCode::SE_vx_byte { x, .. } | OpCode::SNE_vx_byte { x, .. } => (v(x), 0),
+        OpCode::SE_vx_vy { x, y } | OpCode::SNE_vx_vy { x, y } => (v(x) | v(y), 0),
+        OpCode::LD_vx_byte { x, .. } | OpCode::RND_vx_byte { x, .. } => (0, v(x)),
+        OpCode::ADD_vx_byte { x, .. } => (v(x), v(x)),
+        OpCode::LD_vx_vy { x, y } => (v(y), v(x)),
+        // The usual way to clear a register, which doesn't depend on what was in it
+        OpCode::XOR_vx_vy { x, y } | OpCode::SUB_vx_vy { x, y } if x == y => (0, v(x) | vf),
+        OpCode::OR_vx_vy { x, y }
+        | OpCode::AND_vx_vy { x, y }
+        | OpCode::XOR_vx_vy { x, y }
+        | OpCode::ADD_vx_vy { x, y }
+        | OpCode::SUB_vx_vy { x, y }
+        | OpCode::SUBN_vx_vy { x, y } => (v(x) | v(y), v(x) | vf),
+        // Vy as well with shift vy, which is judged separately
+        OpCode::SHR_vx_vy { x, .. } | OpCode::SHL_vx_vy { x, .. } => (v(x), v(x) | vf),
+        OpCode::LD_I_addr { .. } => (0, I_BIT),
+        OpCode::JP_v0_addr { .. } => (v(0), 0),
+        OpCode::DRW_x_y_nibble { x, y, .. } => (v(x) | v(y) | I_BIT, vf),
+        OpCode::SKP_vx { x }
+        | OpCode::SKNP_vx { x }
+        | OpCode::LD_dt_vx { x }
+        | OpCode::LD_st_vx { x } => (v(x), 0),
+        OpCode::LD_vx_dt { x } | OpCode::LD_vx_k { x } => (0, v(x)),
+        OpCode::ADD_I_vx { x } => (v(x) | I_BIT, I_BIT),
+        OpCode::LD_F_vx { x } => (v(x), I_BIT),
+        OpCode::LD_B_vx { x } => (v(x) | I_BIT, 0),
+        OpCode::LD_I_vx { x } => (v_through(x) | I_BIT, I_BIT),
+        OpCode::LD_vx_I { x } => (I_BIT, v_through(x) | I_BIT),
+    }
+}
+
+fn register_names(bits: u32) -> Vec<String> {
+    let mut names: Vec<String> = (0..16)
+        .filter(|&r| bits & v(r) != 0)
+        .map(|r| format!("V{:X}", r))
+        .collect();
+    if bits & I_BIT != 0 {
+        names.push("I".to_string());
+    }
+    names
+}
+
+fn skips(op: OpCode) -> bool {
+    matches!(
+        op,
+        OpCode::SE_vx_byte { .. }
+            | OpCode::SNE_vx_byte { .. }
+            | OpCode::SE_vx_vy { .. }
+            | OpCode::SNE_vx_vy { .. }
+            | OpCode::SKP_vx { .. }
+            | OpCode::SKNP_vx { .. }
+    )
+}
+
+pub struct Analysis {
+    rom: Vec<u8>,
+    // Every instruction some path reaches
+    ops: BTreeMap<u16, OpCode>,
+    // Where each instruction can go next, with a CALL going on to the instruction after it
+    flow: BTreeMap<u16, Vec<u16>>,
+    procedures: BTreeMap<u16, Procedure>,
+    findings: BTreeSet<Finding>,
+    evidence: BTreeSet<Evidence>,
+}
+
+impl Analysis {
+    pub fn new(rom: &[u8]) -> Self {
+        let mut analysis = Self {
+            rom: rom.to_vec(),
+            ops: BTreeMap::new(),
+            flow: BTreeMap::new(),
+            procedures: BTreeMap::new(),
+            findings: BTreeSet::new(),
+            evidence: BTreeSet::new(),
+        };
+        analysis.find_code();
+        analysis.check_overlaps();
+        analysis.find_unreachable();
+        analysis.dataflow();
+        analysis
+    }
+
+    fn note(&mut self, addr: u16, message: String) {
+        self.findings.insert(Finding { addr, message });
+    }
+
+    fn in_rom(&self, addr: u16) -> bool {
+        addr >= ROM_START && ((addr - ROM_START) as usize) < self.rom.len()
+    }
+
+    fn word(&self, addr: u16) -> Option<u16> {
+        let offset = addr.checked_sub(ROM_START)? as usize;
+        let bytes = self.rom.get(offset..offset + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    // Where control can go after the instruction at `pc`, with a CALL going on to the next
+    // instruction as if it had returned
+    fn successors(&mut self, pc: u16, op: OpCode) -> Vec<u16> {
+        let next = pc + 2;
+        match op {
+            OpCode::Jp { addr } => vec![addr],
+            OpCode::Ret => vec![],
+            OpCode::JP_v0_addr { addr } => {
+                // Usually a table of jumps, one for each value of V0
+                let mut targets = vec![addr];
+                let jump = |addr| {
+                    matches!(
+                        self.word(addr).and_then(OpCode::decode),
+                        Some(OpCode::Jp { .. })
+                    )
+                };
+                if jump(addr) {
+                    let mut entry = addr + 2;
+                    while jump(entry) {
+                        targets.push(entry);
+                        entry += 2;
+                    }
+                } else {
+                    self.note(
+                        pc,
+                        "jumps to a computed address, so code it reaches may look unreachable"
+                            .to_string(),
+                    );
+                }
+                targets
+            }
+            op if skips(op) => vec![next, next + 2],
+            _ => vec![next],
+        }
+    }
+
+    // Follows control flow from 0x200 and from every subroutine that gets called, each as
+    // its own procedure so calls and returns can be matched up
+    fn find_code(&mut self) {
+        let mut pending = VecDeque::from([ROM_START]);
+        let mut odd = Vec::new();
+        self.procedures.insert(ROM_START, Procedure::default());
+        while let Some(entry) = pending.pop_front() {
+            let mut body = BTreeSet::new();
+            let mut returns = false;
+            let mut stack = vec![entry];
+            while let Some(pc) = stack.pop() {
+                if !body.insert(pc) {
+                    continue;
+                }
+                if !self.in_rom(pc) || !self.in_rom(pc + 1) {
+                    self.note(pc, "control flow leaves the ROM here".to_string());
+                    continue;
+                }
+                let Some(op) = self.word(pc).and_then(OpCode::decode) else {
+                    self.note(pc, "can be reached but isn't an instruction".to_string());
+                    continue;
+                };
+                self.ops.insert(pc, op);
+                if let OpCode::Call { addr } = op {
+                    let callee = self.procedures.entry(addr).or_default();
+                    if callee.callers.is_empty() && addr != ROM_START {
+                        pending.push_back(addr);
+                    }
+                    callee.callers.insert(pc);
+                    if addr % 2 == 1 {
+                        odd.push((pc, format!("calls 0x{:03X}, an odd address", addr)));
+                    }
+                }
+                if let OpCode::Jp { addr } = op
+                    && addr % 2 == 1
+                {
+                    odd.push((pc, format!("jumps to 0x{:03X}, an odd address", addr)));
+                }
+                returns |= matches!(op, OpCode::Ret);
+                let next = self.successors(pc, op);
+                stack.extend(next.iter().copied());
+                self.flow.entry(pc).or_default().extend(next);
+            }
+            let procedure = self.procedures.get_mut(&entry).unwrap();
+            procedure.body = body;
+            procedure.returns = returns;
+        }
+
+        // A few are likely mistakes, but lots mean the code was laid out that way
+        odd.sort();
+        if odd.len() > 3 {
+            let (pc, message) = &odd[0];
+            let more = format!(
+                "{}, as do {} other jumps and calls, so the code may have been placed at odd addresses",
+                message,
+                odd.len() - 1
+            );
+            odd = vec![(*pc, more)];
+        }
+        let mut notes = odd;
+
+        // Calls and returns that don't pair up
+        for (&entry, procedure) in &self.procedures {
+            if entry == ROM_START && procedure.returns {
+                for &pc in &procedure.body {
+                    if let Some(OpCode::Ret) = self.ops.get(&pc) {
+                        notes.push((pc, "RET with nothing to return to".to_string()));
+                    }
+                }
+            } else if entry != ROM_START && !procedure.returns {
+                let callers: Vec<String> = procedure
+                    .callers
+                    .iter()
+                    .map(|pc| format!("0x{:03X}", pc))
+                    .collect();
+                notes.push((
+                    entry,
+                    format!(
+                        "called from {} but never returns, so the stack only grows",
+                        callers.join(", ")
+                    ),
+                ));
+            }
+        }
+        for (addr, message) in notes {
+            self.note(addr, message);
+        }
+    }
+
+    // Code at odd addresses is fine by itself, but not when it's also the second half of
+    // another instruction
+    fn check_overlaps(&mut self) {
+        let overlaps: Vec<u16> = self
+            .ops
+            .keys()
+            .copied()
+            .filter(|&pc| self.ops.contains_key(&(pc + 1)))
+            .collect();
+        for pc in overlaps {
+            self.note(
+                pc,
+                format!(
+                    "runs as an instruction, and so does 0x{:03X} in the middle of it",
+                    pc + 1
+                ),
+            );
+        }
+    }
+
+    // Stretches of ROM that nothing reaches but that decode as instructions throughout,
+    // leaving out anything LD I points into, since that's data
+    fn find_unreachable(&mut self) {
+        let data: BTreeSet<u16> = self
+            .ops
+            .values()
+            .filter_map(|op| match op {
+                OpCode::LD_I_addr { addr } => Some(*addr),
+                _ => None,
+            })
+            .collect();
+        let covered = |addr: u16| {
+            self.ops.contains_key(&addr) || self.ops.contains_key(&addr.wrapping_sub(1))
+        };
+        let end = ROM_START + self.rom.len() as u16;
+        let mut stretches: Vec<(u16, u16)> = Vec::new();
+        let mut addr = ROM_START;
+        while addr < end {
+            let from = addr;
+            while addr < end && !covered(addr) {
+                addr += 1;
+            }
+            if addr > from {
+                stretches.push((from, addr));
+            }
+            addr += 1;
+        }
+        for (from, to) in stretches {
+            if data.range(from..to).next().is_some() {
+                continue;
+            }
+            let words: Vec<u16> = (from..to - 1)
+                .step_by(2)
+                .filter_map(|addr| self.word(addr))
+                .collect();
+            let code = words.len() >= 2
+                && words.iter().any(|&word| word != 0)
+                && words.iter().all(|&word| OpCode::decode(word).is_some());
+            if code {
+                let message = format!(
+                    "0x{:03X}-0x{:03X} never runs, though it decodes as {} instructions",
+                    from,
+                    to - 1,
+                    words.len()
+                );
+                self.note(from, message);
+            }
+        }
+    }
+
+    // Where flow goes inside a procedure, stepping over calls with what the subroutine is
+    // sure to do, and stopping at RET. Gives the facts before each instruction.
+    fn solve(
+        &self,
+        entry: u16,
+        start: Facts,
+        summaries: &BTreeMap<u16, Facts>,
+    ) -> BTreeMap<u16, Facts> {
+        let mut facts = BTreeMap::from([(entry, start)]);
+        let mut pending = VecDeque::from([entry]);
+        while let Some(pc) = pending.pop_front() {
+            let Some(&op) = self.ops.get(&pc) else {
+                continue;
+            };
+            let after = match op {
+                OpCode::Call { addr } => match summaries.get(&addr) {
+                    Some(&summary) => facts[&pc].after_call(summary),
+                    None => facts[&pc],
+                },
+                op => transfer(facts[&pc], op),
+            };
+            for &next in self.flow.get(&pc).into_iter().flatten() {
+                let merged = match facts.get(&next) {
+                    Some(&old) => old.meet(after),
+                    None => after,
+                };
+                if facts.get(&next) != Some(&merged) {
+                    facts.insert(next, merged);
+                    pending.push_back(next);
+                }
+            }
+        }
+        facts
+    }
+
+    fn dataflow(&mut self) {
+        // Limits rounds for recursion that keeps the summaries changing
+        let rounds = self.procedures.len() + 2;
+
+        // What each subroutine leaves behind, from running it with nothing set
+        let mut summaries: BTreeMap<u16, Facts> = BTreeMap::new();
+        for _ in 0..rounds {
+            let mut changed = false;
+            for (&entry, procedure) in &self.procedures {
+                if entry == ROM_START || !procedure.returns {
+                    continue;
+                }
+                let facts = self.solve(entry, Facts::START, &summaries);
+                let exit = facts
+                    .iter()
+                    .filter(|(pc, _)| matches!(self.ops.get(pc), Some(OpCode::Ret)))
+                    .map(|(_, &facts)| facts)
+                    .reduce(Facts::meet);
+                if let Some(exit) = exit
+                    && summaries.get(&entry) != Some(&exit)
+                {
+                    summaries.insert(entry, exit);
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        // Then each procedure with what's known at the calls to it
+        let mut entries = BTreeMap::from([(ROM_START, Facts::START)]);
+        let mut solved: BTreeMap<u16, BTreeMap<u16, Facts>> = BTreeMap::new();
+        for _ in 0..rounds {
+            for (&entry, &start) in &entries {
+                solved.insert(entry, self.solve(entry, start, &summaries));
+            }
+            let mut calls: BTreeMap<u16, Facts> = BTreeMap::from([(ROM_START, Facts::START)]);
+            for facts in solved.values() {
+                for (pc, &before) in facts {
+                    if let Some(OpCode::Call { addr }) = self.ops.get(pc) {
+                        let merged = match calls.get(addr) {
+                            Some(&old) => old.meet(before),
+                            None => before,
+                        };
+                        calls.insert(*addr, merged);
+                    }
+                }
+            }
+            if calls == entries {
+                break;
+            }
+            entries = calls;
+        }
+
+        let mut facts: BTreeMap<u16, Facts> = BTreeMap::new();
+        for (&pc, &before) in solved.values().flatten() {
+            let merged = match facts.get(&pc) {
+                Some(&old) => old.meet(before),
+                None => before,
+            };
+            facts.insert(pc, merged);
+        }
+        for (&pc, &op) in &self.ops.clone() {
+            if let Some(&before) = facts.get(&pc) {
+                self.check(pc, op, before);
+            }
+        }
+    }
+
+    fn check(&mut self, pc: u16, op: OpCode, facts: Facts) {
+        let (reads, _) = registers(op);
+        let unset = reads & !facts.set;
+        if unset != 0 {
+            self.note(
+                pc,
+                format!(
+                    "reads {}, which may not have been set yet",
+                    register_names(unset).join(", ")
+                ),
+            );
+        }
+
+        if let OpCode::DRW_x_y_nibble { n, .. } = op
+            && let Pointer::Addr(addr) = facts.i
+        {
+            let end = addr + n.max(1) as u16 - 1;
+            if !self.in_rom(addr) || !self.in_rom(end) {
+                self.note(
+                    pc,
+                    format!(
+                        "draws a sprite from 0x{:03X}-0x{:03X}, outside the ROM",
+                        addr, end
+                    ),
+                );
+            }
+        }
+
+        let mut evidence = |quirk, needs, why: String| {
+            self.evidence.insert(Evidence {
+                quirk,
+                needs,
+                addr: pc,
+                why,
+            });
+        };
+        match op {
+            OpCode::SHR_vx_vy { x, y } | OpCode::SHL_vx_vy { x, y } if x != y => {
+                if facts.set & v(y) == 0 {
+                    let why = format!("shifts V{:X} into V{:X}, but V{:X} is never set", y, x, y);
+                    evidence(Quirk::ShiftVy, Some(false), why);
+                } else {
+                    let why = format!("shifts V{:X} or V{:X} depending on shift vy", x, y);
+                    evidence(Quirk::ShiftVy, None, why);
+                }
+            }
+            OpCode::JP_v0_addr { addr } if addr >> 8 & 0xF != 0 => {
+                let x = (addr >> 8 & 0xF) as u8;
+                if facts.set & v(x) == 0 {
+                    let why = format!("jumps by V{:X} with jump vx, but it's never set", x);
+                    evidence(Quirk::JumpVx, Some(false), why);
+                } else if facts.set & v(0) == 0 {
+                    let why = "jumps by V0 without jump vx, but it's never set".to_string();
+                    evidence(Quirk::JumpVx, Some(true), why);
+                } else {
+                    let why = format!("jumps by V0 or V{:X} depending on jump vx", x);
+                    evidence(Quirk::JumpVx, None, why);
+                }
+            }
+            _ => {}
+        }
+        if reads & I_BIT != 0 && facts.i_moved {
+            let why = "uses I after LD [I] or LD Vx, [I] moved it, or not".to_string();
+            evidence(Quirk::MemoryIncrement, None, why);
+        }
+        if reads & v(0xF) != 0 && facts.vf_logic {
+            let why = "reads VF after OR, AND or XOR, which may have reset it".to_string();
+            evidence(Quirk::VfReset, None, why);
+        }
+    }
+
+    pub fn findings(&self) -> impl Iterator<Item = &Finding> {
+        self.findings.iter()
+    }
+
+    // Basic blocks, by their first instruction: straight-line code that's only entered at
+    // the top
+    fn blocks(&self) -> BTreeMap<u16, Vec<u16>> {
+        let mut leaders: BTreeSet<u16> = self.procedures.keys().copied().collect();
+        let mut predecessors: BTreeMap<u16, usize> = BTreeMap::new();
+        for (&pc, next) in &self.flow {
+            for &to in next {
+                *predecessors.entry(to).or_default() += 1;
+                if to != pc + 2 || next.len() > 1 {
+                    leaders.insert(to);
+                }
+            }
+            if next.len() != 1 || matches!(self.ops.get(&pc), Some(OpCode::Call { .. })) {
+                leaders.extend(next);
+            }
+        }
+        leaders.extend(
+            predecessors
+                .iter()
+                .filter(|(_, n)| **n > 1)
+                .map(|(pc, _)| *pc),
+        );
+
+        let mut blocks = BTreeMap::new();
+        for &start in leaders.iter().filter(|pc| self.ops.contains_key(pc)) {
+            let mut body = vec![start];
+            let mut pc = start;
+            while let Some([next]) = self.flow.get(&pc).map(Vec::as_slice) {
+                if *next != pc + 2 || leaders.contains(next) || !self.ops.contains_key(next) {
+                    break;
+                }
+                if matches!(self.ops.get(&pc), Some(OpCode::Call { .. })) {
+                    break;
+                }
+                pc = *next;
+                body.push(pc);
+            }
+            blocks.insert(start, body);
+        }
+        blocks
+    }
+
+    // The control-flow graph for Graphviz: one node per basic block, with calls dashed
+    pub fn dot(&self, labels: &BTreeMap<u16, String>) -> String {
+        let blocks = self.blocks();
+        let mut out = String::from("digraph rom {\n    node [shape=box fontname=monospace];\n");
+        for (start, body) in &blocks {
+            let mut text = format!("{}\\l", disasm::symbolize(labels, *start));
+            for pc in body {
+                let op = disasm::format_op(self.ops[pc], labels);
+                write!(text, "0x{:03X}  {}\\l", pc, op.replace('"', "\\\"")).unwrap();
+            }
+            writeln!(out, "    b{:03X} [label=\"{}\"];", start, text).unwrap();
+        }
+        for (start, body) in &blocks {
+            let last = *body.last().unwrap();
+            for to in self.flow.get(&last).into_iter().flatten() {
+                if blocks.contains_key(to) {
+                    writeln!(out, "    b{:03X} -> b{:03X};", start, to).unwrap();
+                }
+            }
+            if let Some(OpCode::Call { addr }) = self.ops.get(&last)
+                && blocks.contains_key(addr)
+            {
+                writeln!(out, "    b{:03X} -> b{:03X} [style=dashed];", start, addr).unwrap();
+            }
+        }
+        out + "}\n"
+    }
+
+    // Reachable instructions whose behaviour depends on a quirk, by quirk
+    fn quirky_instructions(&self) -> BTreeMap<&'static str, Vec<u16>> {
+        let mut quirky: BTreeMap<&'static str, Vec<u16>> = BTreeMap::new();
+        for (&pc, op) in &self.ops {
+            let quirks: &[&str] = match op {
+                OpCode::SHR_vx_vy { .. } | OpCode::SHL_vx_vy { .. } => &["shift vy"],
+                OpCode::OR_vx_vy { .. } | OpCode::AND_vx_vy { .. } | OpCode::XOR_vx_vy { .. } => {
+                    &["vf reset"]
+                }
+                OpCode::LD_I_vx { .. } | OpCode::LD_vx_I { .. } => &["memory increment"],
+                OpCode::JP_v0_addr { .. } => &["jump vx"],
+                OpCode::DRW_x_y_nibble { .. } => &["display wait", "wrap sprites"],
+                _ => &[],
+            };
+            for quirk in quirks {
+                quirky.entry(quirk).or_default().push(pc);
+            }
+        }
+        quirky
+    }
+
+    // How each quirk profile is likely to get on with the ROM
+    fn compatibility(&self) -> Vec<(&'static str, String)> {
+        PROFILES
+            .iter()
+            .map(|(name, quirks)| {
+                let mut conflicts: Vec<String> = self
+                    .evidence
+                    .iter()
+                    .filter(|e| e.needs.is_some_and(|needs| needs != e.quirk.of(quirks)))
+                    .map(|e| format!("{} {}", e.quirk.name(), on_off(e.needs.unwrap())))
+                    .collect();
+                conflicts.dedup();
+                let mut unsure: Vec<String> = self
+                    .evidence
+                    .iter()
+                    .filter(|e| e.needs.is_none())
+                    .map(|e| format!("{} {}", e.quirk.name(), on_off(!e.quirk.of(quirks))))
+                    .collect();
+                unsure.dedup();
+                let verdict = if !conflicts.is_empty() {
+                    format!("unlikely, it needs {}", conflicts.join(" and "))
+                } else if !unsure.is_empty() {
+                    format!("likely, unless it expects {}", unsure.join(" or "))
+                } else {
+                    "likely".to_string()
+                };
+                (*name, verdict)
+            })
+            .collect()
+    }
+
+    pub fn report(&self, labels: &BTreeMap<u16, String>) -> String {
+        let mut out = String::new();
+        let subroutines = self.procedures.len() - 1;
+        writeln!(
+            out,
+            "{} reachable instructions in {} blocks, {} subroutine{}",
+            self.ops.len(),
+            self.blocks().len(),
+            subroutines,
+            if subroutines == 1 { "" } else { "s" }
+        )
+        .unwrap();
+
+        let place = |addr: u16| format!("0x{:03X}  {:<16}", addr, disasm::symbolize(labels, addr));
+        if !self.findings.is_empty() {
+            writeln!(out, "\nWarnings").unwrap();
+            for finding in &self.findings {
+                writeln!(out, "  {}  {}", place(finding.addr), finding.message).unwrap();
+            }
+        }
+
+        writeln!(out, "\nQuirk-dependent instructions").unwrap();
+        let quirky = self.quirky_instructions();
+        if quirky.is_empty() {
+            writeln!(out, "  none").unwrap();
+        }
+        for (quirk, places) in &quirky {
+            let mut addrs: Vec<String> = places
+                .iter()
+                .take(6)
+                .map(|pc| format!("0x{:03X}", pc))
+                .collect();
+            if places.len() > addrs.len() {
+                addrs.push("...".to_string());
+            }
+            writeln!(
+                out,
+                "  {:<17} {:>4}  {}",
+                quirk,
+                places.len(),
+                addrs.join(" ")
+            )
+            .unwrap();
+        }
+
+        writeln!(out, "\nQuirk profiles").unwrap();
+        // A few places for each quirk are enough to go and look at
+        let evidence: Vec<&Evidence> = self.evidence.iter().collect();
+        for group in evidence.chunk_by(|a, b| a.quirk == b.quirk) {
+            for evidence in group.iter().take(EXAMPLES) {
+                writeln!(out, "  {}  {}", place(evidence.addr), evidence.why).unwrap();
+            }
+            if group.len() > EXAMPLES {
+                let more = group.len() - EXAMPLES;
+                writeln!(
+                    out,
+                    "  ... and {} more depending on {}",
+                    more,
+                    group[0].quirk.name()
+                )
+                .unwrap();
+            }
+        }
+        for (name, verdict) in self.compatibility() {
+            writeln!(out, "  {:<8} {}", name, verdict).unwrap();
+        }
+        out
+    }
+}
+
+fn on_off(on: bool) -> &'static str {
+    if on { "on" } else { "off" }
+}
+
+// The facts after an instruction, given the ones before it
+fn transfer(before: Facts, op: OpCode) -> Facts {
+    let (_, writes) = registers(op);
+    let mut after = before;
+    after.set |= writes;
+    if writes & I_BIT != 0 {
+        after.i_moved = false;
+        after.i = match op {
+            OpCode::LD_I_addr { addr } => Pointer::Addr(addr),
+            OpCode::LD_F_vx { .. } => Pointer::Font,
+            _ => Pointer::Unknown,
+        };
+    }
+    if let OpCode::LD_I_vx { .. } | OpCode::LD_vx_I { .. } = op {
+        after.i_moved = true;
+    }
+    if writes & v(0xF) != 0 {
+        after.vf_logic = matches!(
+            op,
+            OpCode::OR_vx_vy { .. } | OpCode::AND_vx_vy { .. } | OpCode::XOR_vx_vy { .. }
+        );
+    }
+    after
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_mistakes_and_quirks() {
+        // 0x200: LD I, 0x300  0x202: DRW V0, V1, 5  0x204: SHR V2, V3  0x206: CALL 0x20E
+        // 0x208: SE V2, 0  0x20A: RET  0x20C: JP 0x209
+        // 0x20E: LD V4, 1  0x210: JP 0x210  0x212: CLS  0x214: CLS (never run)
+        let rom = [
+            0xA3, 0x00, 0xD0, 0x15, 0x82, 0x36, 0x22, 0x0E, 0x32, 0x00, 0x00, 0xEE, 0x12, 0x09,
+            0x64, 0x01, 0x12, 0x10, 0x00, 0xE0, 0x00, 0xE0,
+        ];
+        let analysis = Analysis::new(&rom);
+        let findings: Vec<(u16, &str)> = analysis
+            .findings()
+            .map(|f| (f.addr, f.message.as_str()))
+            .collect();
+        assert_eq!(
+            findings,
+            [
+                (0x202, "draws a sprite from 0x300-0x304, outside the ROM"),
+                (0x202, "reads V0, V1, which may not have been set yet"),
+                (0x204, "reads V2, which may not have been set yet"),
+                (0x209, "can be reached but isn't an instruction"),
+                (0x20A, "RET with nothing to return to"),
+                (0x20C, "jumps to 0x209, an odd address"),
+                (
+                    0x20E,
+                    "called from 0x206 but never returns, so the stack only grows"
+                ),
+                (
+                    0x212,
+                    "0x212-0x215 never runs, though it decodes as 2 instructions"
+                ),
+            ]
+        );
+
+        let verdicts = analysis.compatibility();
+        assert_eq!(verdicts[0], ("default", "likely".to_string()));
+        assert_eq!(
+            verdicts[1],
+            ("vip", "unlikely, it needs shift vy off".to_string())
+        );
+        let report = analysis.report(&BTreeMap::new());
+        assert!(report.contains("shifts V3 into V2, but V3 is never set"));
+    }
+
+    fn messages(analysis: &Analysis) -> Vec<(u16, &str)> {
+        analysis
+            .findings()
+            .map(|f| (f.addr, f.message.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn follows_jump_tables() {
+        // 0x200: LD V0, 2  0x202: JP V0, 0x206  0x204: CLS (never run)
+        // 0x206: JP 0x20C  0x208: JP 0x210, the end of the table  0x20A: LD V1, 1
+        // 0x20C: CLS  0x20E: JP 0x20E  0x210: CLS  0x212: JP 0x212
+        let rom = [
+            0x60, 0x02, 0xB2, 0x06, 0x00, 0xE0, 0x12, 0x0C, 0x12, 0x10, 0x61, 0x01, 0x00, 0xE0,
+            0x12, 0x0E, 0x00, 0xE0, 0x12, 0x12,
+        ];
+        let analysis = Analysis::new(&rom);
+        assert_eq!(analysis.flow[&0x202], [0x206, 0x208]);
+        assert!(analysis.ops.contains_key(&0x20C) && analysis.ops.contains_key(&0x210));
+        assert!(!analysis.ops.contains_key(&0x20A));
+        // One stray instruction is too little to call dead code, and the table
+        // means there's nothing to say about a computed jump
+        assert!(messages(&analysis).is_empty());
+
+        // Without a table there's no telling where it goes
+        let analysis = Analysis::new(&[0x60, 0x02, 0xB2, 0x00]);
+        assert_eq!(
+            messages(&analysis),
+            [(
+                0x202,
+                "jumps to a computed address, so code it reaches may look unreachable"
+            )]
+        );
+    }
+
+    #[test]
+    fn notices_control_flow_leaving_the_rom() {
+        // 0x200: SE V0, 0  0x202: JP 0x400, past the end  0x204: CLS, then off the end
+        let analysis = Analysis::new(&[0x30, 0x00, 0x14, 0x00, 0x00, 0xE0]);
+        assert_eq!(
+            messages(&analysis),
+            [
+                (0x200, "reads V0, which may not have been set yet"),
+                (0x206, "control flow leaves the ROM here"),
+                (0x400, "control flow leaves the ROM here"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rates_profiles_by_vf_reset_memory_increment_and_jump_vx() {
+        // 0x200: LD V0, 1  0x202: LD V1, 2  0x204: OR V0, V1  0x206: SE VF, 0
+        // 0x208: LD I, 0x300  0x20A: LD [I], V1  0x20C: LD V2, [I]  0x20E: JP 0x20E
+        let rom = [
+            0x60, 0x01, 0x61, 0x02, 0x80, 0x11, 0x3F, 0x00, 0xA3, 0x00, 0xF1, 0x55, 0xF2, 0x65,
+            0x12, 0x0E,
+        ];
+        let verdicts = Analysis::new(&rom).compatibility();
+        assert_eq!(
+            verdicts,
+            [
+                (
+                    "default",
+                    "likely, unless it expects vf reset off or memory increment off".to_string()
+                ),
+                (
+                    "vip",
+                    "likely, unless it expects vf reset off or memory increment off".to_string()
+                ),
+                (
+                    "schip",
+                    "likely, unless it expects vf reset on or memory increment on".to_string()
+                ),
+            ]
+        );
+
+        // 0x200: LD V0, 4  0x202: JP V0, 0x300, but V3 is never set, so not with jump vx
+        let verdicts = Analysis::new(&[0x60, 0x04, 0xB3, 0x00]).compatibility();
+        assert_eq!(verdicts[1], ("vip", "likely".to_string()));
+        assert_eq!(
+            verdicts[2],
+            ("schip", "unlikely, it needs jump vx off".to_string())
+        );
+        // 0x200: LD V3, 4  0x202: JP V0, 0x300, but V0 is never set, so only with jump vx
+        let verdicts = Analysis::new(&[0x63, 0x04, 0xB3, 0x00]).compatibility();
+        assert_eq!(
+            verdicts[1],
+            ("vip", "unlikely, it needs jump vx on".to_string())
+        );
+        assert_eq!(verdicts[2], ("schip", "likely".to_string()));
+    }
+
+    #[test]
+    fn draws_the_graph_for_graphviz() {
+        // 0x200: CALL 0x206  0x202: SE V0, 0  0x204: JP 0x200  0x206: RET
+        let rom = [0x22, 0x06, 0x30, 0x00, 0x12, 0x00, 0x00, 0xEE];
+        let labels = BTreeMap::from([(0x206, "sub".to_string())]);
+        let dot = Analysis::new(&rom).dot(&labels);
+        assert_eq!(
+            dot,
+            r#"digraph rom {
+    node [shape=box fontname=monospace];
+    b200 [label="0x200\l0x200  CALL sub\l"];
+    b202 [label="0x202\l0x202  SE V0, 0x00\l"];
+    b204 [label="0x204\l0x204  JP 0x200\l"];
+    b206 [label="sub\l0x206  RET\l"];
+    b200 -> b202;
+    b200 -> b206 [style=dashed];
+    b202 -> b204;
+    b202 -> b206;
+    b204 -> b200;
+}
+"#
+        );
+    }
+}
@@ -0,0 +1,377 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::chip8::ROM_START;
+use crate::isa::OpCode;
+
+// Assembles the same syntax the disassembler prints:
+//
+//   ; comments run to the end of the line
+//   loop:               labels end with a colon
+//       LD V0, 0x05     numbers are decimal, 0x/$ hex or 0b binary
+//       DRW V0, V1, 5   registers are V0-VF (V10-V15 works too)
+//       JP loop
+//   sprite:
+//       DB 0xF0, 0x90   raw bytes (DW for 16-bit words)
+
+#[derive(Debug)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    V(u8),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    B,
+    Number(u16),
+    Label(String),
+}
+
+struct Statement {
+    line: usize,
+    mnemonic: String,
+    operands: Vec<Operand>,
+}
+
+// Which source line each instruction came from, so debuggers can work in terms of lines
+#[derive(Debug, Default, Clone)]
+pub struct SourceMap {
+    lines: BTreeMap<u16, usize>,
+    pub labels: HashMap<String, u16>,
+}
+
+impl SourceMap {
+    pub fn line(&self, addr: u16) -> Option<usize> {
+        self.lines.get(&addr).copied()
+    }
+
+    // The first instruction on or after a line, and the line it's actually on
+    pub fn address(&self, line: usize) -> Option<(u16, usize)> {
+        self.lines
+            .iter()
+            .filter(|&(_, &l)| l >= line)
+            .min_by_key(|&(&addr, &l)| (l, addr))
+            .map(|(&addr, &l)| (addr, l))
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    assemble_with_map(source).map(|(rom, _)| rom)
+}
+
+pub fn assemble_with_map(source: &str) -> Result<(Vec<u8>, SourceMap), AsmError> {
+    // First pass: find where every label ends up
+    let mut labels = HashMap::new();
+    let mut statements = Vec::new();
+    let mut addr = ROM_START;
+
+    for (n, raw) in source.lines().enumerate() {
+        let line = n + 1;
+        let err = |message: String| AsmError { line, message };
+        let mut text = raw.split(';').next().unwrap().trim();
+
+        while let Some((label, rest)) = text.split_once(':') {
+            let label = label.trim();
+            if !is_identifier(label) {
+                break;
+            }
+            if labels.insert(label.to_string(), addr).is_some() {
+                return Err(err(format!("label '{}' is defined twice", label)));
+            }
+            text = rest.trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let mnemonic = mnemonic.to_ascii_uppercase();
+        let operands = rest
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(parse_operand)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(err)?;
+
+        addr += match mnemonic.as_str() {
+            "DB" => operands.len() as u16,
+            "DW" => 2 * operands.len() as u16,
+            _ => 2,
+        };
+        statements.push(Statement {
+            line,
+            mnemonic,
+            operands,
+        });
+    }
+
+    // Second pass: encode with every label known
+    let mut out = Vec::new();
+    let mut lines = BTreeMap::new();
+    for statement in statements {
+        let err = |message: String| AsmError {
+            line: statement.line,
+            message,
+        };
+        let operands = statement
+            .operands
+            .iter()
+            .map(|op| resolve(op, &labels))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(err)?;
+
+        match statement.mnemonic.as_str() {
+            "DB" => {
+                for op in &operands {
+                    out.push(number(op, 0xFF).map_err(err)? as u8);
+                }
+            }
+            "DW" => {
+                for op in &operands {
+                    out.extend_from_slice(&number(op, 0xFFFF).map_err(err)?.to_be_bytes());
+                }
+            }
+            mnemonic => {
+                let op = encode(mnemonic, &operands).map_err(err)?;
+                lines.insert(ROM_START + out.len() as u16, statement.line);
+                out.extend_from_slice(&op.encode().to_be_bytes());
+            }
+        }
+    }
+
+    if out.len() > 4096 - ROM_START as usize {
+        return Err(AsmError {
+            line: source.lines().count(),
+            message: format!(
+                "program is {} bytes, which doesn't fit in memory",
+                out.len()
+            ),
+        });
+    }
+    Ok((out, SourceMap { lines, labels }))
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_number(s: &str) -> Option<u16> {
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(hex) = s.strip_prefix('$') {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = s.strip_prefix("0b") {
+        u16::from_str_radix(bin, 2).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+fn parse_register(s: &str) -> Option<u8> {
+    let rest = s.strip_prefix(['V', 'v'])?;
+    match rest.parse::<u8>() {
+        Ok(n) if n < 16 => Some(n),
+        Ok(_) => None,
+        Err(_) if rest.len() == 1 => u8::from_str_radix(rest, 16).ok(),
+        Err(_) => None,
+    }
+}
+
+fn parse_operand(s: &str) -> Result<Operand, String> {
+    if let Some(x) = parse_register(s) {
+        return Ok(Operand::V(x));
+    }
+    if s.len() > 1 && s[1..].chars().all(|c| c.is_ascii_digit()) && s.starts_with(['V', 'v']) {
+        return Err(format!("there is no register {}", s));
+    }
+    let operand = match s.to_ascii_uppercase().as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::Dt,
+        "ST" => Operand::St,
+        "K" => Operand::K,
+        "F" => Operand::F,
+        "B" => Operand::B,
+        _ => {
+            if let Some(n) = parse_number(s) {
+                Operand::Number(n)
+            } else if is_identifier(s) {
+                Operand::Label(s.to_string())
+            } else {
+                return Err(format!("can't parse operand '{}'", s));
+            }
+        }
+    };
+    Ok(operand)
+}
+
+fn resolve(op: &Operand, labels: &HashMap<String, u16>) -> Result<Operand, String> {
+    match op {
+        Operand::Label(name) => labels
+            .get(name)
+            .map(|&addr| Operand::Number(addr))
+            .ok_or_else(|| format!("unknown label '{}'", name)),
+        other => Ok(other.clone()),
+    }
+}
+
+fn number(op: &Operand, max: u16) -> Result<u16, String> {
+    match op {
+        Operand::Number(n) if *n <= max => Ok(*n),
+        Operand::Number(n) => Err(format!("0x{:X} doesn't fit in 0x{:X}", n, max)),
+        other => Err(format!("expected a number, found {:?}", other)),
+    }
+}
+
+fn encode(mnemonic: &str, operands: &[Operand]) -> Result<OpCode, String> {
+    use Operand::*;
+
+    let addr = |op: &Operand| number(op, 0xFFF);
+    let byte = |op: &Operand| number(op, 0xFF).map(|n| n as u8);
+
+    let op = match (mnemonic, operands) {
+        ("CLS", []) => OpCode::Cls,
+        ("RET", []) => OpCode::Ret,
+        ("JP", [V(0), a]) => OpCode::JP_v0_addr { addr: addr(a)? },
+        ("JP", [a]) => OpCode::Jp { addr: addr(a)? },
+        ("CALL", [a]) => OpCode::Call { addr: addr(a)? },
+        ("SE", [V(x), V(y)]) => OpCode::SE_vx_vy { x: *x, y: *y },
+        ("SE", [V(x), kk]) => OpCode::SE_vx_byte {
+            x: *x,
+            kk: byte(kk)?,
+        },
+        ("SNE", [V(x), V(y)]) => OpCode::SNE_vx_vy { x: *x, y: *y },
+        ("SNE", [V(x), kk]) => OpCode::SNE_vx_byte {
+            x: *x,
+            kk: byte(kk)?,
+        },
+        ("LD", [V(x), V(y)]) => OpCode::LD_vx_vy { x: *x, y: *y },
+        ("LD", [V(x), Dt]) => OpCode::LD_vx_dt { x: *x },
+        ("LD", [V(x), K]) => OpCode::LD_vx_k { x: *x },
+        ("LD", [V(x), I | IndirectI]) => OpCode::LD_vx_I { x: *x },
+        ("LD", [V(x), kk]) => OpCode::LD_vx_byte {
+            x: *x,
+            kk: byte(kk)?,
+        },
+        ("LD", [I | IndirectI, V(x)]) => OpCode::LD_I_vx { x: *x },
+        ("LD", [I, a]) => OpCode::LD_I_addr { addr: addr(a)? },
+        ("LD", [Dt, V(x)]) => OpCode::LD_dt_vx { x: *x },
+        ("LD", [St, V(x)]) => OpCode::LD_st_vx { x: *x },
+        ("LD", [F, V(x)]) => OpCode::LD_F_vx { x: *x },
+        ("LD", [B, V(x)]) => OpCode::LD_B_vx { x: *x },
+        ("ADD", [I, V(x)]) => OpCode::ADD_I_vx { x: *x },
+        ("ADD", [V(x), V(y)]) => OpCode::ADD_vx_vy { x: *x, y: *y },
+        ("ADD", [V(x), kk]) => OpCode::ADD_vx_byte {
+            x: *x,
+            kk: byte(kk)?,
+        },
+        ("OR", [V(x), V(y)]) => OpCode::OR_vx_vy { x: *x, y: *y },
+        ("AND", [V(x), V(y)]) => OpCode::AND_vx_vy { x: *x, y: *y },
+        ("XOR", [V(x), V(y)]) => OpCode::XOR_vx_vy { x: *x, y: *y },
+        ("SUB", [V(x), V(y)]) => OpCode::SUB_vx_vy { x: *x, y: *y },
+        ("SUBN", [V(x), V(y)]) => OpCode::SUBN_vx_vy { x: *x, y: *y },
+        ("SHR", [V(x)]) => OpCode::SHR_vx_vy { x: *x, y: *x },
+        ("SHR", [V(x), V(y)]) => OpCode::SHR_vx_vy { x: *x, y: *y },
+        ("SHL", [V(x)]) => OpCode::SHL_vx_vy { x: *x, y: *x },
+        ("SHL", [V(x), V(y)]) => OpCode::SHL_vx_vy { x: *x, y: *y },
+        ("RND", [V(x), kk]) => OpCode::RND_vx_byte {
+            x: *x,
+            kk: byte(kk)?,
+        },
+        ("DRW", [V(x), V(y), n]) => OpCode::DRW_x_y_nibble {
+            x: *x,
+            y: *y,
+            n: number(n, 0xF)? as u8,
+        },
+        ("SKP", [V(x)]) => OpCode::SKP_vx { x: *x },
+        ("SKNP", [V(x)]) => OpCode::SKNP_vx { x: *x },
+        _ => {
+            return Err(format!(
+                "no {} instruction takes {} operand(s) like these",
+                mnemonic,
+                operands.len()
+            ));
+        }
+    };
+    Ok(op)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_instruction_round_trips() {
+        // Every word that decodes, so every OpCode variant and every operand
+        for word in 0..=u16::MAX {
+            let Some(op) = OpCode::decode(word) else {
+                continue;
+            };
+            assert_eq!(op.encode(), word, "{}", op);
+            let rom = assemble(&op.to_string()).unwrap();
+            assert_eq!(rom, word.to_be_bytes(), "{}", op);
+        }
+    }
+
+    #[test]
+    fn resolves_labels_and_maps_lines() {
+        let source = "\
+; forward and backward references
+start:
+    CALL draw
+    JP start
+draw: LD I, sprite
+    RET
+sprite:
+    DB 0xF0, 0x90
+";
+        let (rom, map) = assemble_with_map(source).unwrap();
+        assert_eq!(
+            rom,
+            [0x22, 0x04, 0x12, 0x00, 0xA2, 0x08, 0x00, 0xEE, 0xF0, 0x90]
+        );
+        assert_eq!(map.labels["draw"], 0x204);
+        assert_eq!(map.labels["sprite"], 0x208);
+        assert_eq!(map.line(0x204), Some(5));
+        assert_eq!(map.address(4), Some((0x202, 4)));
+        assert_eq!(map.address(7), None);
+    }
+
+    #[test]
+    fn reports_the_line_of_an_error() {
+        let error = |source: &str| assemble(source).unwrap_err().to_string();
+        assert_eq!(
+            error("CLS\n\n    JP nowhere\n"),
+            "line 3: unknown label 'nowhere'"
+        );
+        assert_eq!(
+            error("a:\nCLS\na: RET\n"),
+            "line 3: label 'a' is defined twice"
+        );
+        assert_eq!(error("LD V16, 1"), "line 1: there is no register V16");
+        assert_eq!(
+            error("CLS\nADD V0, 256"),
+            "line 2: 0x100 doesn't fit in 0xFF"
+        );
+        assert_eq!(
+            error("CLS\nRET\nDRW V0, V1"),
+            "line 3: no DRW instruction takes 2 operand(s) like these"
+        );
+    }
+}
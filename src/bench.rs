@@ -0,0 +1,47 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::chip8::Chip8;
+use crate::headless;
+
+// Collects the ROMs to benchmark: the file itself, or every file in a directory
+fn rom_paths(path: &Path) -> io::Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut paths: Vec<PathBuf> = fs::read_dir(path)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| p.is_file())
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+pub fn run(path: &Path, frames: u32, ipf: u32, decode_cache: bool) -> io::Result<()> {
+    println!(
+        "{:<12} {:>14} {:>10} {:>10}",
+        "ROM", "instr/s", "fps", "time"
+    );
+    for rom_path in rom_paths(path)? {
+        let name = rom_path.file_name().unwrap_or_default().to_string_lossy();
+        let rom = fs::read(&rom_path)?;
+        let mut chip8 = Chip8::new();
+        chip8.set_decode_cache(decode_cache);
+        if let Err(e) = chip8.ROM_loader(&rom) {
+            println!("{:<12} {}", name, e);
+            continue;
+        }
+        match headless::run_frames(&mut chip8, frames, ipf) {
+            Ok(stats) => println!(
+                "{:<12} {:>14.0} {:>10.0} {:>9.3}s",
+                name,
+                stats.instructions_per_second(),
+                stats.frames_per_second(),
+                stats.elapsed.as_secs_f64()
+            ),
+            Err(e) => println!("{:<12} {} at PC 0x{:03X}", name, e, chip8.pc),
+        }
+    }
+    Ok(())
+}
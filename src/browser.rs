@@ -0,0 +1,328 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::{
+    DefaultTerminal, Frame,
+    layout::{Constraint, Layout},
+    style::{Style, Stylize},
+    symbols::border,
+    text::{Line, Span, Text},
+    widgets::{Block, List, ListState, Paragraph, Wrap},
+};
+
+use crate::chip8::Chip8;
+use crate::framebuffer::{H, W};
+use crate::headless;
+use crate::isa::OpCode;
+use crate::rom;
+use crate::romdb::RomDatabase;
+use crate::scheduler::FrameScheduler;
+use crate::settings::Settings;
+
+// The preview restarts the ROM after this many frames
+const PREVIEW_FRAMES: u32 = 300;
+
+// Files the emulator and its tools keep next to ROMs: symbols, sources and listings,
+// coverage reports, save states and configs
+const NOT_ROMS: [&str; 7] = ["sym", "asm", "lst", "info", "lcov", "state", "toml"];
+
+pub struct Entry {
+    pub path: PathBuf,
+    pub rom: Vec<u8>,
+    name: String,
+    details: Vec<String>,
+}
+
+// Launcher listing the ROMs in a directory. The selected ROM runs in a preview pane so
+// you can see what it is before starting it.
+pub struct Browser<'a> {
+    dir: PathBuf,
+    entries: Vec<Entry>,
+    state: ListState,
+    // Settings a ROM would be started with, command line flags included
+    settings_for: &'a dyn Fn(&[u8]) -> Settings,
+    preview: Option<Preview>,
+    exit: bool,
+    chosen: bool,
+}
+
+struct Preview {
+    chip8: Chip8,
+    settings: Settings,
+    frames: u32,
+    // Set once the ROM hits an error, like a RET with nothing on the stack
+    crashed: bool,
+}
+
+impl<'a> Browser<'a> {
+    pub fn new(
+        dir: &Path,
+        database: &RomDatabase,
+        settings_for: &'a dyn Fn(&[u8]) -> Settings,
+    ) -> Self {
+        let entries = list_roms(dir, database);
+        let mut state = ListState::default();
+        if !entries.is_empty() {
+            state.select(Some(0));
+        }
+        Self {
+            dir: dir.to_path_buf(),
+            entries,
+            state,
+            settings_for,
+            preview: None,
+            exit: false,
+            chosen: false,
+        }
+    }
+
+    // Shows the browser until a ROM is picked, or returns None if the user quit
+    pub fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<Option<&Entry>> {
+        let mut scheduler = FrameScheduler::new();
+        self.exit = false;
+        self.chosen = false;
+        self.preview = None;
+
+        while !self.exit && !self.chosen {
+            self.handle_events()?;
+            self.run_preview();
+            terminal.draw(|frame| self.draw(frame))?;
+            scheduler.wait_for_next_frame();
+        }
+        if self.exit {
+            return Ok(None);
+        }
+        Ok(self.state.selected().map(|i| &self.entries[i]))
+    }
+
+    fn handle_events(&mut self) -> io::Result<()> {
+        while event::poll(Duration::ZERO)? {
+            let Event::Key(KeyEvent {
+                code,
+                kind: KeyEventKind::Press,
+                ..
+            }) = event::read()?
+            else {
+                continue;
+            };
+            match code {
+                KeyCode::Esc | KeyCode::Char('q') => self.exit = true,
+                KeyCode::Enter if self.state.selected().is_some() => self.chosen = true,
+                KeyCode::Up | KeyCode::Char('k') => self.select_by(-1),
+                KeyCode::Down | KeyCode::Char('j') => self.select_by(1),
+                KeyCode::PageUp => self.select_by(-10),
+                KeyCode::PageDown => self.select_by(10),
+                KeyCode::Home => self.select_by(-(self.entries.len() as isize)),
+                KeyCode::End => self.select_by(self.entries.len() as isize),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn select_by(&mut self, delta: isize) {
+        let Some(selected) = self.state.selected() else {
+            return;
+        };
+        let last = self.entries.len() as isize - 1;
+        let next = (selected as isize + delta).clamp(0, last) as usize;
+        if next != selected {
+            self.state.select(Some(next));
+            self.preview = None;
+        }
+    }
+
+    // Runs one frame of the selected ROM, starting it over every PREVIEW_FRAMES
+    fn run_preview(&mut self) {
+        let Some(selected) = self.state.selected() else {
+            return;
+        };
+        if self
+            .preview
+            .as_ref()
+            .is_none_or(|p| p.frames >= PREVIEW_FRAMES)
+        {
+            let rom = &self.entries[selected].rom;
+            let settings = (self.settings_for)(rom);
+            self.preview = settings.build_chip8(rom).ok().map(|chip8| Preview {
+                chip8,
+                settings,
+                frames: 0,
+                crashed: false,
+            });
+        }
+        if let Some(preview) = &mut self.preview
+            && !preview.crashed
+        {
+            // A ROM that crashes just freezes on its last frame
+            match headless::run_frames(&mut preview.chip8, 1, preview.settings.ipf) {
+                Ok(_) => preview.frames += 1,
+                Err(_) => preview.crashed = true,
+            }
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let main_block = Block::bordered()
+            .title(Line::from(" Chip8 - pick a ROM ".bold()).centered())
+            .title_bottom(
+                Line::from(" Enter play  Esc quit  F4 in game comes back here ").centered(),
+            )
+            .border_set(border::THICK);
+        let inner_area = main_block.inner(frame.area());
+        frame.render_widget(main_block, frame.area());
+
+        if self.entries.is_empty() {
+            let message = format!("No ROMs found in {}", self.dir.display());
+            frame.render_widget(Paragraph::new(message).centered(), inner_area);
+            return;
+        }
+
+        let [list_area, right_area] =
+            Layout::horizontal([Constraint::Length(28), Constraint::Fill(1)]).areas(inner_area);
+        let [preview_area, details_area] =
+            Layout::vertical([Constraint::Length(H as u16 / 2 + 2), Constraint::Fill(1)])
+                .areas(right_area);
+
+        let list = List::new(self.entries.iter().map(|e| e.name.as_str()))
+            .block(
+                Block::bordered()
+                    .title(Line::from(" ROMs ".bold()).centered())
+                    .border_set(border::THICK),
+            )
+            .highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(list, list_area, &mut self.state);
+
+        let preview_block = Block::bordered()
+            .title(Line::from(" Preview ".bold()).centered())
+            .border_set(border::THICK);
+        let preview_text = self.preview.as_ref().map(preview_text).unwrap_or_default();
+        frame.render_widget(
+            Paragraph::new(preview_text).block(preview_block).centered(),
+            preview_area,
+        );
+
+        if let Some(selected) = self.state.selected() {
+            let details =
+                Text::from_iter(self.entries[selected].details.iter().map(String::as_str));
+            let details_block = Block::bordered()
+                .title(Line::from(" Details ".bold()).centered())
+                .border_set(border::THICK);
+            frame.render_widget(
+                Paragraph::new(details)
+                    .block(details_block)
+                    .wrap(Wrap { trim: true }),
+                details_area,
+            );
+        }
+    }
+}
+
+// Two pixel rows per terminal row using half blocks, so the whole screen fits in 64x16
+fn preview_text(preview: &Preview) -> Text<'static> {
+    let theme = preview.settings.theme;
+    let mode = preview.settings.color_mode;
+    let style = Style::new()
+        .fg(theme.color(1, mode))
+        .bg(theme.color(0, mode));
+    let fb = &preview.chip8.fb;
+    Text::from_iter((0..H).step_by(2).map(|y| {
+        let row: String = (0..W)
+            .map(|x| match (fb.get(x, y), fb.get(x, y + 1)) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            })
+            .collect();
+        Line::from(Span::styled(row, style))
+    }))
+}
+
+fn list_roms(dir: &Path, database: &RomDatabase) -> Vec<Entry> {
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = read_dir
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.is_file())
+        .filter(|path| !path.file_name().unwrap().to_string_lossy().starts_with('.'))
+        .filter(|path| {
+            !path
+                .extension()
+                .is_some_and(|ext| NOT_ROMS.iter().any(|not| ext.eq_ignore_ascii_case(not)))
+        })
+        .collect();
+    paths.sort();
+
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let rom = fs::read(&path).ok()?;
+            if rom.is_empty() || rom.len() > rom::MAX_SIZE {
+                return None;
+            }
+            // Whatever else it is, it isn't a ROM if there's no instruction at 0x200
+            let first = u16::from_be_bytes([rom[0], *rom.get(1)?]);
+            OpCode::decode(first)?;
+            let file_name = path.file_name().unwrap().to_string_lossy().into_owned();
+            let mut details = vec![format!("File:      {}", path.display())];
+            details.push(format!("Size:      {} bytes", rom.len()));
+            let name = match database.lookup(&rom) {
+                Some(game) => {
+                    details.insert(0, format!("Title:     {}", game.program.title));
+                    if !game.program.authors.is_empty() {
+                        details.push(format!("Authors:   {}", game.program.authors.join(", ")));
+                    }
+                    if let Some(release) = &game.program.release {
+                        details.push(format!("Released:  {}", release));
+                    }
+                    if let Some(platform) = game.platform {
+                        details.push(format!("Platform:  {}", platform.name));
+                    }
+                    if let Some(description) = &game.program.description {
+                        details.push(String::new());
+                        details.push(description.clone());
+                    }
+                    game.program.title.clone()
+                }
+                None => file_name,
+            };
+            Some(Entry {
+                path,
+                rom,
+                name,
+                details,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_only_roms() {
+        let dir = std::env::temp_dir().join(format!("browser-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let rom = [0x00, 0xE0, 0x12, 0x02];
+        for name in ["b.ch8", "a.rom", "b.sym", "b.state", "b.toml", "b.LST"] {
+            fs::write(dir.join(name), rom).unwrap();
+        }
+        // Data with no instruction at 0x200, and an odd single byte
+        fs::write(dir.join("notes.txt"), [0xFF, 0xFF, 0x00, 0xE0]).unwrap();
+        fs::write(dir.join("byte.bin"), [0x00]).unwrap();
+
+        let names: Vec<_> = list_roms(&dir, &RomDatabase::parse("{}", "[]", "[]").unwrap())
+            .iter()
+            .map(|entry| entry.name.clone())
+            .collect();
+        assert_eq!(names, ["a.rom", "b.ch8"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,8 +1,11 @@
+use crate::framebuffer::{self, Framebuffer, H, Row, W};
 use crate::isa::OpCode;
+use crate::quirks::Quirks;
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use std::fmt;
 
-const ROM_START: u16 = 0x200;
-const W: usize = 64;
-const H: usize = 32;
+pub const ROM_START: u16 = 0x200;
 
 // CHIP-8 font set: 16 characters (0-F), each 5 bytes, stored at 0x000-0x04F
 const FONT_SET: [u8; 80] = [
@@ -24,6 +27,17 @@ const FONT_SET: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+// How the program has used each byte of memory, for spotting self-modifying code
+const EXECUTED: u8 = 1;
+const WRITTEN: u8 = 2;
+// Each warning is given once per address
+const WARNED_OVERWRITE: u8 = 4;
+const WARNED_RUN: u8 = 8;
+
+// "C8" plus a format version, so old or foreign files are rejected
+const STATE_MAGIC: &[u8] = b"C8\x00\x01";
+const STATE_SIZE: usize = 4 + 16 + 2 + 2 + 4 + 2 * 16 + 4096 + 8 * H;
+
 pub struct Chip8 {
     pub registers: [u8; 16],
     pub i: u16,
@@ -31,10 +45,54 @@ pub struct Chip8 {
     pub sp: u8,
     dt: u8,
     st: u8,
-    pub fb: [[bool; W]; H],
+    pub fb: Framebuffer,
     stack: [u16; 16],
     memory: [u8; 4096],
-    pub keypad: [bool; 16]
+    pub keypad: [bool; 16],
+    pub quirks: Quirks,
+    waiting_vblank: bool,
+    // Decoded instructions by address, filled in lazily when the cache is enabled
+    decode_cache: Option<Vec<Option<OpCode>>>,
+    rng: StdRng,
+    // EXECUTED, WRITTEN and friends by address
+    usage: [u8; 4096],
+    warnings: Vec<Warning>,
+}
+
+// Self-modifying code, which ROMs like KALEID and VERS rely on but is usually a bug
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Warning {
+    // The instruction at `pc` wrote over code that had already run
+    CodeOverwritten { pc: u16, addr: u16 },
+    // An instruction ran from bytes the program had written itself
+    WrittenCodeRun { pc: u16 },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::CodeOverwritten { pc, addr } => {
+                write!(f, "0x{:03X} wrote over code at 0x{:03X}", pc, addr)
+            }
+            Warning::WrittenCodeRun { pc } => {
+                write!(f, "running code at 0x{:03X} that the program wrote", pc)
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Snapshot {
+    state: Vec<u8>,
+    rng: StdRng,
+    keypad: [bool; 16],
+    usage: [u8; 4096],
+}
+
+impl Default for Chip8 {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Chip8 {
@@ -50,13 +108,20 @@ impl Chip8 {
             sp: 0x00,
             dt: 0x00,
             st: 0x00,
-            fb: [[false; W]; H],
+            fb: Framebuffer::new(),
             stack: [0x00; 16],
             memory,
-            keypad: [false; 16]
+            keypad: [false; 16],
+            quirks: Quirks::default(),
+            waiting_vblank: false,
+            decode_cache: None,
+            rng: StdRng::from_entropy(),
+            usage: [0; 4096],
+            warnings: Vec::new(),
         }
     }
 
+    #[allow(non_snake_case)]
     pub fn ROM_loader(&mut self, rom: &[u8]) -> Result<(), &'static str> {
         // takes all the instructions and puts them in memory starts at 0x200 (for some fun reason). all instructionsgit .
         let end = ROM_START as usize + rom.len();
@@ -65,9 +130,218 @@ impl Chip8 {
         }
         self.memory[ROM_START as usize..end].copy_from_slice(rom);
         self.pc = ROM_START;
+        if let Some(cache) = &mut self.decode_cache {
+            cache.fill(None);
+        }
+        Ok(())
+    }
+
+    // Swaps in a new build of the program without touching registers, timers, the stack or
+    // the screen, so a running game picks up the change where it is. Whatever the old ROM
+    // had past the end of the new one is cleared.
+    pub fn swap_rom(&mut self, old_len: usize, rom: &[u8]) -> Result<(), &'static str> {
+        let start = ROM_START as usize;
+        if start + rom.len() > self.memory.len() {
+            return Err("Your program is too damn long");
+        }
+        let end = (start + old_len.max(rom.len())).min(self.memory.len());
+        self.memory[start..end].fill(0);
+        self.memory[start..start + rom.len()].copy_from_slice(rom);
+        if let Some(cache) = &mut self.decode_cache {
+            cache.fill(None);
+        }
+        // It's all new code
+        self.usage = [0; 4096];
         Ok(())
     }
 
+    // Snapshot of the machine for save states. Quirks and the RNG aren't included; they
+    // come from the settings the game was started with.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(STATE_SIZE);
+        out.extend_from_slice(STATE_MAGIC);
+        out.extend_from_slice(&self.registers);
+        out.extend_from_slice(&self.i.to_be_bytes());
+        out.extend_from_slice(&self.pc.to_be_bytes());
+        out.extend_from_slice(&[self.sp, self.dt, self.st, self.waiting_vblank as u8]);
+        for addr in self.stack {
+            out.extend_from_slice(&addr.to_be_bytes());
+        }
+        out.extend_from_slice(&self.memory);
+        for row in self.fb.rows() {
+            out.extend_from_slice(&row.to_be_bytes());
+        }
+        out
+    }
+
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), &'static str> {
+        if state.len() != STATE_SIZE || !state.starts_with(STATE_MAGIC) {
+            return Err("Not a save state from this version");
+        }
+        let mut rest = &state[STATE_MAGIC.len()..];
+        let mut take = |n: usize| {
+            let (head, tail) = rest.split_at(n);
+            rest = tail;
+            head
+        };
+        let registers = take(16);
+        let i = u16::from_be_bytes(take(2).try_into().unwrap());
+        let pc = u16::from_be_bytes(take(2).try_into().unwrap());
+        let [sp, dt, st, waiting_vblank] = take(4).try_into().unwrap();
+        // Checked before anything changes, since the next CALL, RET or fetch would panic
+        if sp as usize > self.stack.len() || pc > 0xFFE {
+            return Err("Save state has a stack pointer or PC out of range");
+        }
+        self.registers.copy_from_slice(registers);
+        (self.i, self.pc) = (i, pc);
+        (self.sp, self.dt, self.st, self.waiting_vblank) = (sp, dt, st, waiting_vblank != 0);
+        for addr in &mut self.stack {
+            *addr = u16::from_be_bytes(take(2).try_into().unwrap());
+        }
+        self.memory.copy_from_slice(take(4096));
+        let mut rows = [0; H];
+        for row in &mut rows {
+            *row = Row::from_be_bytes(take(8).try_into().unwrap());
+        }
+        self.fb.set_rows(rows);
+        if let Some(cache) = &mut self.decode_cache {
+            cache.fill(None);
+        }
+        // What ran and what was written belongs to the run the state was saved from
+        self.usage = [0; 4096];
+        self.warnings.clear();
+        Ok(())
+    }
+
+    // Everything needed to run on from here exactly as before, which a save state alone
+    // isn't: it leaves out the RNG and the keypad
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            state: self.save_state(),
+            rng: self.rng.clone(),
+            keypad: self.keypad,
+            usage: self.usage,
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.load_state(&snapshot.state)
+            .expect("snapshots are valid save states");
+        self.rng = snapshot.rng.clone();
+        self.keypad = snapshot.keypad;
+        self.usage = snapshot.usage;
+    }
+
+    // Makes RND reproducible
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache = enabled.then(|| vec![None; self.memory.len()]);
+    }
+
+    // Fetches, decodes and executes one instruction. On an error PC is left on the
+    // instruction that failed.
+    pub fn step(&mut self) -> Result<OpCode, &'static str> {
+        let pc = self.pc as usize;
+        if pc > 0xFFE {
+            return Err("PC out of memory");
+        }
+        self.note_execution(pc);
+        let cached = self.decode_cache.as_ref().and_then(|cache| cache[pc]);
+        let op = match cached {
+            Some(op) => {
+                self.pc = self.pc.wrapping_add(2);
+                op
+            }
+            None => {
+                let Some(op) = OpCode::decode(self.fetch()) else {
+                    self.pc = pc as u16;
+                    return Err("Unknown opcode");
+                };
+                if let Some(cache) = &mut self.decode_cache {
+                    cache[pc] = Some(op);
+                }
+                op
+            }
+        };
+        if let Err(e) = self.execute(op) {
+            self.pc = pc as u16;
+            return Err(e);
+        }
+        Ok(op)
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    // The instruction at PC, without running it
+    pub fn current_op(&self) -> Option<OpCode> {
+        let pc = self.pc as usize;
+        let word = u16::from_be_bytes([*self.memory.get(pc)?, *self.memory.get(pc + 1)?]);
+        OpCode::decode(word)
+    }
+
+    // Both bytes of the instruction at `pc` count as code
+    fn note_execution(&mut self, pc: usize) {
+        let mut warn = false;
+        for addr in [pc, pc + 1] {
+            // The caller checks PC, but a bad one is an error for step to report, not a panic
+            let Some(usage) = self.usage.get_mut(addr) else {
+                continue;
+            };
+            if *usage & (WRITTEN | WARNED_RUN) == WRITTEN {
+                *usage |= WARNED_RUN;
+                warn = true;
+            }
+            *usage |= EXECUTED;
+        }
+        if warn {
+            self.warnings
+                .push(Warning::WrittenCodeRun { pc: pc as u16 });
+        }
+    }
+
+    // Self-modifying code seen since the last call, at most once for each address
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    // All writes to memory go through here so cached instructions overlapping the address
+    // are thrown away. Only addresses that have run can be in the cache, which is also when
+    // a write is self-modifying code.
+    pub fn write_memory(&mut self, addr: usize, value: u8) {
+        self.memory[addr] = value;
+        let usage = &mut self.usage[addr];
+        if *usage & (EXECUTED | WARNED_OVERWRITE) == EXECUTED {
+            *usage |= WARNED_OVERWRITE;
+            self.warnings.push(Warning::CodeOverwritten {
+                pc: self.pc.wrapping_sub(2),
+                addr: addr as u16,
+            });
+        }
+        *usage |= WRITTEN;
+        self.invalidate(addr);
+    }
+
+    // A write from a debugger rather than the program, so it doesn't count as
+    // self-modifying code
+    pub fn poke(&mut self, addr: usize, value: u8) {
+        self.memory[addr] = value;
+        self.invalidate(addr);
+    }
+
+    fn invalidate(&mut self, addr: usize) {
+        if let Some(cache) = &mut self.decode_cache {
+            cache[addr] = None;
+            if addr > 0 {
+                cache[addr - 1] = None;
+            }
+        }
+    }
+
     pub fn fetch(&mut self) -> u16 {
         let byte_1 = self.memory[self.pc as usize];
         let byte_2 = self.memory[self.pc as usize + 1];
@@ -76,6 +350,24 @@ impl Chip8 {
         ((byte_1 as u16) << 8) | byte_2 as u16
     }
 
+    // The original interpreter shifted VY into VX, later ones shift VX in place
+    fn shift_source(&self, x: u8, y: u8) -> u8 {
+        if self.quirks.shift_vy {
+            self.registers[y as usize]
+        } else {
+            self.registers[x as usize]
+        }
+    }
+
+    // Where the `len` bytes from I start, as long as they're all in memory
+    fn i_range(&self, len: usize) -> Result<usize, &'static str> {
+        let start = self.i as usize;
+        if start + len > self.memory.len() {
+            return Err("I points past the end of memory");
+        }
+        Ok(start)
+    }
+
     fn skip_next(&mut self) {
         self.pc = self.pc.wrapping_add(2);
     }
@@ -83,18 +375,24 @@ impl Chip8 {
     pub fn execute(&mut self, op: OpCode) -> Result<(), &'static str> {
         match op {
             OpCode::Cls => {
-                self.fb = [[false; W]; H];
+                self.fb.clear();
             }
             OpCode::Ret => {
-                self.sp = self.sp.wrapping_sub(1);
+                if self.sp == 0 {
+                    return Err("Stack underflow");
+                }
+                self.sp -= 1;
                 self.pc = self.stack[self.sp as usize];
             }
             OpCode::Jp { addr } => {
                 self.pc = addr;
             }
             OpCode::Call { addr } => {
+                if self.sp as usize >= self.stack.len() {
+                    return Err("Stack overflow");
+                }
                 self.stack[self.sp as usize] = self.pc;
-                self.sp = self.sp.wrapping_add(1);
+                self.sp += 1;
                 self.pc = addr;
             }
             OpCode::SE_vx_byte { x, kk } => {
@@ -129,15 +427,21 @@ impl Chip8 {
             }
             OpCode::OR_vx_vy { x, y } => {
                 self.registers[x as usize] |= self.registers[y as usize];
-                self.registers[0xF] = 0;
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
+                }
             }
             OpCode::AND_vx_vy { x, y } => {
                 self.registers[x as usize] &= self.registers[y as usize];
-                self.registers[0xF] = 0;
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
+                }
             }
             OpCode::XOR_vx_vy { x, y } => {
                 self.registers[x as usize] ^= self.registers[y as usize];
-                self.registers[0xF] = 0;
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
+                }
             }
             OpCode::ADD_vx_vy { x, y } => {
                 let a = self.registers[x as usize] as u16;
@@ -152,10 +456,10 @@ impl Chip8 {
                 self.registers[0xF] = if vx > vy { 1 } else { 0 };
                 self.registers[x as usize] = vx.wrapping_sub(vy);
             }
-            OpCode::SHR_vx_vy { x, .. } => {
-                let vx = self.registers[x as usize];
-                self.registers[0xF] = vx & 0x01;
-                self.registers[x as usize] = vx >> 1;
+            OpCode::SHR_vx_vy { x, y } => {
+                let v = self.shift_source(x, y);
+                self.registers[0xF] = v & 0x01;
+                self.registers[x as usize] = v >> 1;
             }
             OpCode::SUBN_vx_vy { x, y } => {
                 let vx = self.registers[x as usize];
@@ -163,25 +467,30 @@ impl Chip8 {
                 self.registers[0xF] = if vy > vx { 1 } else { 0 };
                 self.registers[x as usize] = vy.wrapping_sub(vx);
             }
-            OpCode::SHL_vx_vy { x, .. } => {
-                let vx = self.registers[x as usize];
-                self.registers[0xF] = (vx >> 7) & 0x01;
-                self.registers[x as usize] = vx << 1;
+            OpCode::SHL_vx_vy { x, y } => {
+                let v = self.shift_source(x, y);
+                self.registers[0xF] = (v >> 7) & 0x01;
+                self.registers[x as usize] = v << 1;
             }
             OpCode::LD_I_addr { addr } => {
                 self.i = addr;
             }
             OpCode::JP_v0_addr { addr } => {
-                self.pc = addr.wrapping_add(self.registers[0] as u16);
+                let x = if self.quirks.jump_vx { (addr >> 8) as usize & 0xF } else { 0 };
+                self.pc = addr.wrapping_add(self.registers[x] as u16);
             }
             OpCode::RND_vx_byte { x, kk } => {
-                let r: u8 = rand::random();
+                let r = self.rng.next_u32() as u8;
                 self.registers[x as usize] = r & kk;
             }
             OpCode::DRW_x_y_nibble { x, y, n } => {
                 let vx = self.registers[x as usize];
                 let vy = self.registers[y as usize];
+                self.i_range(n as usize)?;
                 self.draw_sprite(vx, vy, n);
+                if self.quirks.display_wait {
+                    self.waiting_vblank = true;
+                }
             }
             OpCode::SKP_vx { x } => {
                 let key = self.registers[x as usize] as usize;
@@ -215,54 +524,97 @@ impl Chip8 {
             }
             OpCode::LD_B_vx { x } => {
                 let v = self.registers[x as usize];
-                self.memory[self.i as usize] = v / 100;
-                self.memory[self.i as usize + 1] = (v / 10) % 10;
-                self.memory[self.i as usize + 2] = v % 10;
+                let i = self.i_range(3)?;
+                self.write_memory(i, v / 100);
+                self.write_memory(i + 1, (v / 10) % 10);
+                self.write_memory(i + 2, v % 10);
             }
             OpCode::LD_I_vx { x } => {
                 let end = x as usize;
+                let i = self.i_range(end + 1)?;
                 for r in 0..=end {
-                    let addr = self.i.wrapping_add(r as u16) as usize;
-                    self.memory[addr] = self.registers[r];
+                    self.write_memory(i + r, self.registers[r]);
+                }
+                if self.quirks.memory_increment {
+                    self.i = self.i.wrapping_add(end as u16 + 1);
                 }
-                self.i = self.i.wrapping_add(end as u16 + 1);
             }
             OpCode::LD_vx_I { x } => {
                 let end = x as usize;
+                let i = self.i_range(end + 1)?;
                 for r in 0..=end {
-                    let addr = self.i.wrapping_add(r as u16) as usize;
-                    self.registers[r] = self.memory[addr];
+                    self.registers[r] = self.memory[i + r];
+                }
+                if self.quirks.memory_increment {
+                    self.i = self.i.wrapping_add(end as u16 + 1);
                 }
-                self.i = self.i.wrapping_add(end as u16 + 1);
             }
         }
         Ok(())
     }
 
     pub fn draw_sprite(&mut self, vx:u8, vy:u8, n:u8) {
-        self.registers[0xF] = 0;
         let x0 = vx as usize % W;
         let y0 = vy as usize % H;
+        let wrap = self.quirks.wrap_sprites;
+        let mut collided = false;
 
         for row in 0..(n as usize) {
+            if !wrap && y0 + row >= H {
+                break;
+            }
             let sprite_byte = self.memory[self.i.wrapping_add(row as u16) as usize];
             let y = (y0 + row) % H;
+            collided |= self.fb.xor_row(y, framebuffer::sprite_row(sprite_byte, x0, wrap));
+        }
+        self.registers[0xF] = collided as u8;
+    }
 
-            for bit in 0..8 {
-                let x = (x0 + bit) % W;
-                let pixel_on = (sprite_byte >> (7-bit)) & 1 == 1;
-
-                if pixel_on {
-                    let prev = self.fb[y][x];
-                    let new = !prev;
-                    self.fb[y][x] = new;
-                    if prev && !new {
-                        self.registers[0xF] = 1;
-                    }
-                }
-            }
+    // True while a DRW is stalled by the display wait quirk. Nothing should be executed until
+    // the next vblank.
+    pub fn waiting_for_vblank(&self) -> bool {
+        self.waiting_vblank
+    }
 
-        }
+    // Called by the frame scheduler at every 60 Hz frame boundary
+    pub fn vblank(&mut self) {
+        self.tick_timers();
+        self.waiting_vblank = false;
+    }
+
+    // True while LD Vx, K is spinning because no key is held
+    pub fn waiting_for_key(&self) -> bool {
+        let pc = self.pc as usize;
+        pc + 1 < self.memory.len()
+            && self.memory[pc] & 0xF0 == 0xF0
+            && self.memory[pc + 1] == 0x0A
+            && !self.keypad.contains(&true)
+    }
+
+    // Return addresses of the active subroutine calls, outermost first
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..(self.sp as usize).min(self.stack.len())]
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.dt
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.st
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.dt = value;
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.st = value;
+    }
+
+    // The buzzer sounds for as long as the sound timer is non-zero
+    pub fn sound_active(&self) -> bool {
+        self.st > 0
     }
 
     pub fn tick_timers(&mut self) {
@@ -275,3 +627,327 @@ impl Chip8 {
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Draws an 8x2 solid sprite at (vx, vy)
+    fn draw_block(chip8: &mut Chip8, vx: u8, vy: u8) {
+        chip8.memory[0x300] = 0xFF;
+        chip8.memory[0x301] = 0xFF;
+        chip8.i = 0x300;
+        chip8.draw_sprite(vx, vy, 2);
+    }
+
+    fn lit_pixels(chip8: &Chip8) -> usize {
+        (0..H)
+            .flat_map(|y| (0..W).map(move |x| (x, y)))
+            .filter(|&(x, y)| chip8.fb.get(x, y))
+            .count()
+    }
+
+    #[test]
+    fn clips_sprite_at_right_edge() {
+        let mut chip8 = Chip8::new();
+        draw_block(&mut chip8, 60, 0);
+        assert!(chip8.fb.get(60, 0) && chip8.fb.get(63, 0));
+        assert!(!chip8.fb.get(0, 0) && !chip8.fb.get(3, 0));
+        assert_eq!(lit_pixels(&chip8), 8);
+    }
+
+    #[test]
+    fn clips_sprite_at_bottom_edge() {
+        let mut chip8 = Chip8::new();
+        draw_block(&mut chip8, 0, 31);
+        assert!(chip8.fb.get(0, 31));
+        assert!(!chip8.fb.get(0, 0));
+        assert_eq!(lit_pixels(&chip8), 8);
+    }
+
+    #[test]
+    fn wraps_sprite_at_right_edge() {
+        let mut chip8 = Chip8::new();
+        chip8.quirks.wrap_sprites = true;
+        draw_block(&mut chip8, 60, 0);
+        assert!(chip8.fb.get(60, 0) && chip8.fb.get(63, 0));
+        assert!(chip8.fb.get(0, 0) && chip8.fb.get(3, 0));
+        assert!(!chip8.fb.get(4, 0));
+        assert_eq!(lit_pixels(&chip8), 16);
+    }
+
+    #[test]
+    fn wraps_sprite_at_bottom_edge() {
+        let mut chip8 = Chip8::new();
+        chip8.quirks.wrap_sprites = true;
+        draw_block(&mut chip8, 0, 31);
+        assert!(chip8.fb.get(0, 31) && chip8.fb.get(0, 0));
+        assert_eq!(lit_pixels(&chip8), 16);
+    }
+
+    #[test]
+    fn detects_collision_and_erases() {
+        let mut chip8 = Chip8::new();
+        draw_block(&mut chip8, 10, 5);
+        assert_eq!(chip8.registers[0xF], 0);
+        draw_block(&mut chip8, 14, 5);
+        assert_eq!(chip8.registers[0xF], 1);
+        assert!(chip8.fb.get(10, 5) && !chip8.fb.get(14, 5) && chip8.fb.get(21, 6));
+    }
+
+    #[test]
+    fn tracks_dirty_rows() {
+        let mut chip8 = Chip8::new();
+        chip8.fb.take_dirty();
+        draw_block(&mut chip8, 0, 3);
+        assert_eq!(chip8.fb.take_dirty(), 0b11000);
+        assert_eq!(chip8.fb.take_dirty(), 0);
+    }
+
+    #[test]
+    fn decode_cache_sees_self_modifying_writes() {
+        let mut chip8 = Chip8::new();
+        chip8.set_decode_cache(true);
+        // 0x200: LD V0, 0x01  0x202: JP 0x200
+        chip8.ROM_loader(&[0x60, 0x01, 0x12, 0x00]).unwrap();
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+
+        // Overwrite the first instruction with LD V0, 0x02 through LD [I], V1
+        chip8.registers[0] = 0x60;
+        chip8.registers[1] = 0x02;
+        chip8.i = 0x200;
+        chip8.execute(OpCode::LD_I_vx { x: 1 }).unwrap();
+
+        chip8.step().unwrap();
+        assert_eq!(chip8.registers[0], 0x02);
+    }
+
+    #[test]
+    fn warns_about_self_modifying_code() {
+        let mut chip8 = Chip8::new();
+        // 0x200: LD I, 0x208  0x202: LD V0, 0x61  0x204: LD V1, 0x05  0x206: LD [I], V1
+        // 0x208: CLS, which becomes LD V1, 0x05  0x20A: JP 0x206
+        let rom = [
+            0xA2, 0x08, 0x60, 0x61, 0x61, 0x05, 0xF1, 0x55, 0x00, 0xE0, 0x12, 0x06,
+        ];
+        chip8.ROM_loader(&rom).unwrap();
+        // So every LD [I], V1 writes to 0x208
+        chip8.quirks.memory_increment = false;
+        for _ in 0..5 {
+            chip8.step().unwrap();
+        }
+        assert_eq!(
+            chip8.take_warnings(),
+            [Warning::WrittenCodeRun { pc: 0x208 }]
+        );
+
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        let overwritten = |addr| Warning::CodeOverwritten { pc: 0x206, addr };
+        assert_eq!(
+            chip8.take_warnings(),
+            [overwritten(0x208), overwritten(0x209)]
+        );
+        assert_eq!(
+            overwritten(0x208).to_string(),
+            "0x206 wrote over code at 0x208"
+        );
+        // Only the first time
+        for _ in 0..6 {
+            chip8.step().unwrap();
+        }
+        assert!(chip8.take_warnings().is_empty());
+    }
+
+    #[test]
+    fn ret_with_empty_stack_is_an_error() {
+        let mut chip8 = Chip8::new();
+        chip8.ROM_loader(&[0x00, 0xEE]).unwrap();
+        assert_eq!(chip8.step().unwrap_err(), "Stack underflow");
+        assert_eq!(chip8.sp, 0);
+        // Still on the RET, so resuming runs it again rather than skipping it
+        assert_eq!(chip8.pc, 0x200);
+    }
+
+    #[test]
+    fn call_with_full_stack_is_an_error() {
+        let mut chip8 = Chip8::new();
+        // 0x200: CALL 0x200, forever
+        chip8.ROM_loader(&[0x22, 0x00]).unwrap();
+        for _ in 0..16 {
+            chip8.step().unwrap();
+        }
+        assert_eq!(chip8.step().unwrap_err(), "Stack overflow");
+        assert_eq!(chip8.stack().len(), 16);
+        assert_eq!(chip8.pc, 0x200);
+    }
+
+    #[test]
+    fn pc_past_memory_is_an_error() {
+        let mut chip8 = Chip8::new();
+        chip8.ROM_loader(&[0x1F, 0xFF]).unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.step().unwrap_err(), "PC out of memory");
+        assert_eq!(chip8.pc, 0xFFF);
+
+        // A full ROM that runs off the end
+        let mut chip8 = Chip8::new();
+        let rom: Vec<u8> = [0x60, 0x00].repeat(0xE00 / 2);
+        chip8.ROM_loader(&rom).unwrap();
+        for _ in 0..rom.len() / 2 {
+            chip8.step().unwrap();
+        }
+        assert_eq!(chip8.step().unwrap_err(), "PC out of memory");
+        assert_eq!(chip8.pc, 0x1000);
+    }
+
+    #[test]
+    fn noting_execution_at_the_end_of_memory() {
+        let mut chip8 = Chip8::new();
+        chip8.note_execution(0xFFF);
+        chip8.note_execution(0x1000);
+        assert_eq!(chip8.usage[0xFFF], EXECUTED);
+        // Doesn't wrap round to the start
+        assert_eq!(chip8.usage[0], 0);
+    }
+
+    #[test]
+    fn unknown_opcode_leaves_pc_on_it() {
+        let mut chip8 = Chip8::new();
+        chip8.ROM_loader(&[0x60, 0x01, 0xFF, 0xFF]).unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.step().unwrap_err(), "Unknown opcode");
+        assert_eq!(chip8.pc, 0x202);
+    }
+
+    #[test]
+    fn i_past_memory_is_an_error() {
+        let mut chip8 = Chip8::new();
+        chip8.i = 0xFFE;
+        assert!(chip8.execute(OpCode::LD_B_vx { x: 0 }).is_err());
+        assert!(chip8.execute(OpCode::LD_vx_I { x: 1 }).is_ok());
+        chip8.i = 0xFFD;
+        assert!(chip8.execute(OpCode::DRW_x_y_nibble { x: 0, y: 0, n: 4 }).is_err());
+    }
+
+    #[test]
+    fn restoring_brings_back_what_had_run() {
+        let mut chip8 = Chip8::new();
+        // 0x200: LD I, 0x202  0x202: LD [I], V0, over itself  0x204: JP 0x202
+        chip8.ROM_loader(&[0xA2, 0x02, 0xF0, 0x55, 0x12, 0x02]).unwrap();
+        chip8.quirks.memory_increment = false;
+        chip8.registers[0] = 0xF0;
+        let start = chip8.snapshot();
+        chip8.step().unwrap();
+        let state = chip8.save_state();
+        let run = |chip8: &mut Chip8| {
+            for _ in 0..3 {
+                chip8.step().unwrap();
+            }
+            chip8.take_warnings()
+        };
+        let warnings = run(&mut chip8);
+        assert_eq!(warnings.len(), 2);
+
+        // Warned about already on the abandoned run, but not on this one
+        chip8.restore(&start);
+        chip8.step().unwrap();
+        assert_eq!(run(&mut chip8), warnings);
+        // A save state starts over with nothing run, dropping warnings not taken yet
+        chip8.restore(&start);
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        chip8.load_state(&state).unwrap();
+        assert!(chip8.take_warnings().is_empty());
+        assert_eq!(run(&mut chip8), warnings);
+    }
+
+    #[test]
+    fn swap_rom_keeps_machine_state() {
+        let mut chip8 = Chip8::new();
+        chip8.set_decode_cache(true);
+        // 0x200: LD V0, 0x01  0x202: LD V1, 0x01  0x204: JP 0x204
+        let old = [0x60, 0x01, 0x61, 0x01, 0x12, 0x04];
+        chip8.ROM_loader(&old).unwrap();
+        chip8.step().unwrap();
+
+        // The new build is shorter, so the old JP gets cleared
+        chip8.swap_rom(old.len(), &[0x60, 0x05, 0x61, 0x07]).unwrap();
+        assert_eq!(chip8.registers[0], 0x01);
+        assert_eq!(chip8.pc, 0x202);
+        chip8.step().unwrap();
+        assert_eq!(chip8.registers[1], 0x07);
+        assert_eq!(chip8.memory[0x204], 0);
+    }
+
+    #[test]
+    fn save_state_round_trips() {
+        let mut chip8 = Chip8::new();
+        chip8.registers[3] = 0x42;
+        chip8.i = 0x300;
+        chip8.st = 5;
+        chip8.execute(OpCode::Call { addr: 0x208 }).unwrap();
+        draw_block(&mut chip8, 10, 10);
+        let state = chip8.save_state();
+
+        let mut restored = Chip8::new();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert!(restored.fb == chip8.fb && restored.pc == 0x208 && restored.sound_active());
+        assert!(restored.load_state(&state[1..]).is_err());
+
+        // Right size, but a stack pointer or PC the core can't run with
+        let at = STATE_MAGIC.len() + 16 + 2;
+        for (offset, bytes) in [(at, [0x0F, 0xFF]), (at + 2, [0x11, 0])] {
+            let mut bad = state.clone();
+            bad[offset..offset + 2].copy_from_slice(&bytes);
+            assert!(restored.load_state(&bad).is_err());
+        }
+        assert_eq!(restored.save_state(), state);
+    }
+
+    #[test]
+    fn snapshots_replay_random_numbers() {
+        let mut chip8 = Chip8::new();
+        chip8.keypad[7] = true;
+        let snapshot = chip8.snapshot();
+        let roll = |chip8: &mut Chip8| {
+            chip8
+                .execute(OpCode::RND_vx_byte { x: 0, kk: 0xFF })
+                .unwrap();
+            chip8.registers[0]
+        };
+        let rolls: Vec<u8> = (0..8).map(|_| roll(&mut chip8)).collect();
+
+        chip8.keypad[7] = false;
+        chip8.restore(&snapshot);
+        assert_eq!((0..8).map(|_| roll(&mut chip8)).collect::<Vec<u8>>(), rolls);
+        assert!(chip8.keypad[7]);
+    }
+
+    #[test]
+    fn starting_coordinate_wraps_in_both_modes() {
+        for wrap in [false, true] {
+            let mut chip8 = Chip8::new();
+            chip8.quirks.wrap_sprites = wrap;
+            draw_block(&mut chip8, 64 + 2, 32 + 1);
+            assert!(chip8.fb.get(2, 1) && chip8.fb.get(9, 2));
+            assert_eq!(lit_pixels(&chip8), 16);
+        }
+    }
+
+    #[test]
+    fn collision_only_counts_visible_pixels_when_clipping() {
+        let mut chip8 = Chip8::new();
+        chip8.memory[0x310] = 0x80;
+        chip8.i = 0x310;
+        chip8.draw_sprite(0, 0, 1);
+        draw_block(&mut chip8, 60, 0);
+        assert_eq!(chip8.registers[0xF], 0);
+
+        chip8.quirks.wrap_sprites = true;
+        draw_block(&mut chip8, 60, 0);
+        assert_eq!(chip8.registers[0xF], 1);
+    }
+}
@@ -0,0 +1,342 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+use emulator::flicker::FlickerMode;
+use emulator::keymap::Keymap;
+use emulator::quirks::Quirks;
+use emulator::scheduler::{DEFAULT_IPF, Speed};
+use emulator::settings::Settings;
+use emulator::theme::{self, ColorMode, Theme};
+
+#[derive(Parser)]
+#[command(version, about = "A CHIP-8 emulator")]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    // `emulator <rom>` is short for `emulator run <rom>`
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run a ROM in the terminal
+    Run(Box<RunArgs>),
+    /// Print a disassembly of a ROM
+    Disasm {
+        rom: PathBuf,
+        /// Symbol file naming addresses [default: the ROM with a .sym extension, if any]
+        #[arg(long)]
+        symbols: Option<PathBuf>,
+    },
+    /// Assemble a source file into a ROM
+    Asm {
+        source: PathBuf,
+        /// Output file [default: the source file with a .ch8 extension]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Also write the labels to this symbol file
+        #[arg(long)]
+        symbols: Option<PathBuf>,
+    },
+    /// Measure raw emulation speed without rendering
+    Bench(BenchArgs),
+    /// Show information about a ROM
+    Info { rom: PathBuf },
+    /// Check a ROM for likely bugs and the quirks it depends on, without running it
+    Analyze {
+        rom: PathBuf,
+        /// Symbol file naming addresses [default: the ROM with a .sym extension, if any]
+        #[arg(long)]
+        symbols: Option<PathBuf>,
+        /// Also write the control-flow graph to this file, for Graphviz
+        #[arg(long, value_name = "FILE")]
+        dot: Option<PathBuf>,
+    },
+    /// Serve the Debug Adapter Protocol to an editor, on stdin/stdout or a TCP port
+    Dap {
+        /// Listen on this localhost port instead of using stdin/stdout
+        #[arg(long, value_name = "PORT")]
+        port: Option<u16>,
+
+        /// Config file [default: $XDG_CONFIG_HOME/chip8-emulator/config.toml]
+        #[arg(long)]
+        config: Option<PathBuf>,
+    },
+}
+
+#[derive(Args)]
+pub struct RunArgs {
+    /// ROM file to run [default: pick one in the ROM browser]
+    pub rom: Option<PathBuf>,
+
+    /// Show registers and instruction history next to the display
+    #[arg(long)]
+    pub debug: bool,
+
+    /// Config file [default: $XDG_CONFIG_HOME/chip8-emulator/config.toml]
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// Symbol file naming addresses in the debug view [default: the ROM with a .sym extension, if any]
+    #[arg(long)]
+    pub symbols: Option<PathBuf>,
+
+    /// Instructions executed per 60 Hz frame [default: 12]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub ipf: Option<u32>,
+
+    /// Quirk profile: default, vip or schip
+    #[arg(long, value_parser = parse_quirks)]
+    pub quirks: Option<Quirks>,
+
+    /// Make DRW wait for the next frame like the COSMAC VIP
+    #[arg(long)]
+    pub display_wait: bool,
+
+    /// Wrap sprites around the screen edges instead of clipping them
+    #[arg(long)]
+    pub wrap_sprites: bool,
+
+    /// Seed for RND, so runs are reproducible
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Colour theme: default, green, amber or octo
+    #[arg(long, value_parser = parse_theme)]
+    pub theme: Option<Theme>,
+
+    /// Up to four comma separated #rrggbb colours for the background, plane 1, plane 2
+    /// and both planes, overriding the theme
+    #[arg(long, value_parser = parse_color, value_delimiter = ',')]
+    pub palette: Option<Vec<theme::Rgb>>,
+
+    /// Foreground colour as #rrggbb, overriding the theme
+    #[arg(long, value_parser = parse_color)]
+    pub fg: Option<theme::Rgb>,
+
+    /// Background colour as #rrggbb, overriding the theme
+    #[arg(long, value_parser = parse_color)]
+    pub bg: Option<theme::Rgb>,
+
+    /// Colour output: truecolor or 256 [default: detected from COLORTERM]
+    #[arg(long, value_parser = parse_color_mode)]
+    pub color: Option<ColorMode>,
+
+    /// Flicker reduction: off, vblank, blend or phosphor
+    #[arg(long, value_parser = parse_flicker)]
+    pub flicker: Option<FlickerMode>,
+
+    /// Keyboard layout (qwerty, azerty, colemak, dvorak) or the 16 keys for 0-F
+    #[arg(long, value_parser = parse_keymap)]
+    pub keymap: Option<Keymap>,
+
+    /// Terminal rows per CHIP-8 pixel
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..=8))]
+    pub scale: Option<u16>,
+
+    /// Ring the terminal bell when the sound timer starts: on or off
+    #[arg(long, value_parser = parse_switch)]
+    pub audio: Option<bool>,
+
+    /// Fast-forward speed for F9, like 4x, or uncapped
+    #[arg(long, value_parser = parse_speed)]
+    pub fast_forward: Option<Speed>,
+
+    /// Cache decoded instructions
+    #[arg(long)]
+    pub decode_cache: bool,
+
+    /// Reload the ROM when the file changes
+    #[arg(long)]
+    pub watch: bool,
+
+    /// With --watch, keep registers, timers and the screen when reloading
+    #[arg(long, requires = "watch")]
+    pub keep_state: bool,
+
+    /// Wait for gdb on this localhost port and let it drive the emulator
+    #[arg(long, value_name = "PORT")]
+    pub gdb: Option<u16>,
+
+    /// Run without the terminal UI and print the screen at the end
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Frames to run for with --headless
+    #[arg(long, default_value_t = 600)]
+    pub frames: u32,
+
+    /// Profile the run and write hot spots, instruction and subroutine counts and an
+    /// annotated disassembly to this file when it ends
+    #[arg(long, value_name = "FILE", conflicts_with = "gdb")]
+    pub profile: Option<PathBuf>,
+
+    /// Write the profile's call stacks in folded format, for flamegraph.pl or inferno
+    #[arg(long, value_name = "FILE", conflicts_with = "gdb")]
+    pub folded: Option<PathBuf>,
+
+    /// Write which instructions ran and which bytes were used as data to this file when the
+    /// run ends: an lcov tracefile for .info or .lcov, otherwise an annotated disassembly
+    #[arg(long, value_name = "FILE", conflicts_with = "gdb")]
+    pub coverage: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct BenchArgs {
+    /// ROM file, or a directory to benchmark every ROM in
+    #[arg(default_value = "roms")]
+    pub path: PathBuf,
+
+    /// Frames to run each ROM for
+    #[arg(long, default_value_t = 600)]
+    pub frames: u32,
+
+    /// Instructions per frame
+    #[arg(long, default_value_t = DEFAULT_IPF)]
+    pub ipf: u32,
+
+    /// Cache decoded instructions
+    #[arg(long)]
+    pub decode_cache: bool,
+}
+
+impl RunArgs {
+    // Flags given on the command line override whatever the settings already hold
+    pub fn apply(&self, settings: &mut Settings) {
+        settings.debug |= self.debug;
+        settings.decode_cache |= self.decode_cache;
+        if let Some(ipf) = self.ipf {
+            settings.ipf = ipf;
+        }
+        if self.seed.is_some() {
+            settings.seed = self.seed;
+        }
+        if let Some(quirks) = self.quirks {
+            settings.quirks = quirks;
+        }
+        settings.quirks.display_wait |= self.display_wait;
+        settings.quirks.wrap_sprites |= self.wrap_sprites;
+        if let Some(theme) = self.theme {
+            settings.theme = theme;
+        }
+        if let Some(palette) = &self.palette {
+            settings.theme = settings.theme.with_palette(palette);
+        }
+        if let Some(fg) = self.fg {
+            settings.theme = settings.theme.with_fg(fg);
+        }
+        if let Some(bg) = self.bg {
+            settings.theme = settings.theme.with_bg(bg);
+        }
+        if let Some(color) = self.color {
+            settings.color_mode = color;
+        }
+        if let Some(flicker) = self.flicker {
+            settings.flicker = flicker;
+        }
+        if let Some(keymap) = self.keymap {
+            settings.keymap.set_layout(&keymap);
+        }
+        if let Some(scale) = self.scale {
+            settings.scale = scale;
+        }
+        if let Some(speed) = self.fast_forward {
+            settings.fast_forward = speed;
+        }
+        if let Some(audio) = self.audio {
+            settings.audio = audio;
+        }
+    }
+}
+
+fn parse_quirks(s: &str) -> Result<Quirks, String> {
+    Quirks::profile(s).ok_or_else(|| {
+        format!(
+            "unknown quirk profile, expected one of: {}",
+            Quirks::profile_names().join(", ")
+        )
+    })
+}
+
+fn parse_theme(s: &str) -> Result<Theme, String> {
+    Theme::by_name(s).ok_or_else(|| {
+        format!(
+            "unknown theme, expected one of: {}",
+            Theme::names().join(", ")
+        )
+    })
+}
+
+fn parse_color(s: &str) -> Result<theme::Rgb, String> {
+    theme::parse_hex_color(s).ok_or_else(|| "expected a colour like #33ff33".to_string())
+}
+
+fn parse_color_mode(s: &str) -> Result<ColorMode, String> {
+    ColorMode::from_name(s).ok_or_else(|| "expected truecolor or 256".to_string())
+}
+
+fn parse_flicker(s: &str) -> Result<FlickerMode, String> {
+    FlickerMode::from_name(s).ok_or_else(|| "expected off, vblank, blend or phosphor".to_string())
+}
+
+fn parse_keymap(s: &str) -> Result<Keymap, String> {
+    Keymap::from_name(s).ok_or_else(|| {
+        format!(
+            "expected one of {} or 16 characters for keys 0-F",
+            Keymap::layout_names().join(", ")
+        )
+    })
+}
+
+fn parse_speed(s: &str) -> Result<Speed, String> {
+    Speed::from_name(s).ok_or_else(|| "expected a multiplier like 4x, or uncapped".to_string())
+}
+
+fn parse_switch(s: &str) -> Result<bool, String> {
+    match s {
+        "on" | "true" | "yes" => Ok(true),
+        "off" | "false" | "no" => Ok(false),
+        _ => Err("expected on or off".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emulator::config::Config;
+
+    #[test]
+    fn flags_win_over_the_config() {
+        let config = Config::parse("ipf = 15\ntheme = \"amber\"\nfg = \"#102030\"\n").unwrap();
+        let cli =
+            Cli::try_parse_from(["emulator", "--ipf", "30", "--fg", "#33ff33", "PONG"]).unwrap();
+        let mut settings = Settings::default();
+        config.apply(&mut settings);
+        cli.run.apply(&mut settings);
+        assert_eq!(settings.ipf, 30);
+        assert_eq!(settings.theme.planes[1], Some((0x33, 0xFF, 0x33)));
+        // Whatever the flags leave alone still comes from the config
+        assert_eq!(settings.theme.planes[0], Some((0x1A, 0x10, 0x00)));
+    }
+
+    #[test]
+    fn palette_sets_planes_from_the_background_up() {
+        let cli = Cli::try_parse_from(["emulator", "--palette", "#000000,#ffffff,#ff0000", "PONG"])
+            .unwrap();
+        let mut settings = Settings::default();
+        cli.run.apply(&mut settings);
+        assert_eq!(
+            settings.theme.planes[..3],
+            [
+                Some((0, 0, 0)),
+                Some((0xFF, 0xFF, 0xFF)),
+                Some((0xFF, 0, 0))
+            ]
+        );
+        assert!(Cli::try_parse_from(["emulator", "--palette", "#000000,red", "PONG"]).is_err());
+    }
+}
@@ -0,0 +1,375 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+
+use crate::keymap::Keymap;
+use crate::quirks::Quirks;
+use crate::rom;
+use crate::settings::Settings;
+use crate::theme::{self, Rgb, Theme};
+
+// The user's config file, $XDG_CONFIG_HOME/chip8-emulator/config.toml:
+//
+//   ipf = 15
+//   quirks = "vip"
+//   theme = "amber"
+//   palette = ["#000000", "#ffffff", "#ff0000", "#ffff00"]   # background, planes 1, 2, both
+//   fg = "#33ff33"   # single colours over the theme's
+//   bg = "#001100"
+//   keymap = "colemak"
+//   audio = false
+//   save_state_dir = "~/chip8/states"
+//   database = "~/src/chip-8-database/database"   # instead of the built-in ROM database
+//   rom_dir = "~/chip8/roms"                          # what the ROM browser lists
+//
+//   # Overrides for a single game, keyed by the SHA-1 of the ROM (`emulator info` prints it)
+//   [rom.a1b2c3...]
+//   name = "Space Invaders"   # just for your own reference
+//   ipf = 30
+//   quirks = "schip"
+//
+// Every key is optional. The ROM database wins over the top level settings, a ROM's own
+// section wins over the database, and command line flags win over everything.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default, deserialize_with = "de_ipf")]
+    pub ipf: Option<u32>,
+    #[serde(default, deserialize_with = "de_quirks")]
+    pub quirks: Option<Quirks>,
+    #[serde(default, deserialize_with = "de_theme")]
+    pub theme: Option<Theme>,
+    #[serde(default, deserialize_with = "de_palette")]
+    pub palette: Option<Vec<Rgb>>,
+    #[serde(default, deserialize_with = "de_color")]
+    pub fg: Option<Rgb>,
+    #[serde(default, deserialize_with = "de_color")]
+    pub bg: Option<Rgb>,
+    #[serde(default, deserialize_with = "de_keymap")]
+    pub keymap: Option<Keymap>,
+    pub audio: Option<bool>,
+    pub save_state_dir: Option<PathBuf>,
+    pub database: Option<PathBuf>,
+    pub rom_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub rom: BTreeMap<RomHash, RomConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RomConfig {
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "de_ipf")]
+    pub ipf: Option<u32>,
+    #[serde(default, deserialize_with = "de_quirks")]
+    pub quirks: Option<Quirks>,
+    #[serde(default, deserialize_with = "de_theme")]
+    pub theme: Option<Theme>,
+    #[serde(default, deserialize_with = "de_palette")]
+    pub palette: Option<Vec<Rgb>>,
+    #[serde(default, deserialize_with = "de_color")]
+    pub fg: Option<Rgb>,
+    #[serde(default, deserialize_with = "de_color")]
+    pub bg: Option<Rgb>,
+    #[serde(default, deserialize_with = "de_keymap")]
+    pub keymap: Option<Keymap>,
+    pub audio: Option<bool>,
+}
+
+// Lowercase hex SHA-1, as produced by rom::sha1
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RomHash(pub String);
+
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "in config file {}: {}",
+            self.path.display(),
+            self.message
+        )
+    }
+}
+
+impl Config {
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("chip8-emulator").join("config.toml"))
+    }
+
+    // Reads the config from its default location. Not having one is fine.
+    pub fn load_default() -> Result<Config, ConfigError> {
+        match Config::default_path() {
+            Some(path) if path.exists() => Config::load(&path),
+            _ => Ok(Config::default()),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let err = |message: String| ConfigError {
+            path: path.to_path_buf(),
+            message,
+        };
+        let text = fs::read_to_string(path).map_err(|e: io::Error| err(e.to_string()))?;
+        Config::parse(&text).map_err(err)
+    }
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config: Config = toml::from_str(text).map_err(|e| e.to_string())?;
+        config.save_state_dir = config.save_state_dir.map(expand_home);
+        config.database = config.database.map(expand_home);
+        config.rom_dir = config.rom_dir.map(expand_home);
+        Ok(config)
+    }
+
+    pub fn for_rom(&self, rom: &[u8]) -> Option<&RomConfig> {
+        self.rom.get(&RomHash(rom::sha1(rom)))
+    }
+
+    // The top level settings, which go under what the ROM database knows about a game
+    pub fn apply(&self, settings: &mut Settings) {
+        apply_game(
+            settings,
+            self.ipf,
+            self.quirks,
+            self.theme,
+            self.keymap,
+            self.audio,
+        );
+        apply_colors(settings, self.palette.as_deref(), self.fg, self.bg);
+        if let Some(dir) = &self.save_state_dir {
+            settings.save_state_dir = dir.clone();
+        }
+    }
+
+    // The ROM's own section, which goes over the database
+    pub fn apply_rom(&self, settings: &mut Settings, rom: &[u8]) {
+        if let Some(game) = self.for_rom(rom) {
+            apply_game(
+                settings,
+                game.ipf,
+                game.quirks,
+                game.theme,
+                game.keymap,
+                game.audio,
+            );
+            apply_colors(settings, game.palette.as_deref(), game.fg, game.bg);
+        }
+    }
+}
+
+fn apply_game(
+    settings: &mut Settings,
+    ipf: Option<u32>,
+    quirks: Option<Quirks>,
+    theme: Option<Theme>,
+    keymap: Option<Keymap>,
+    audio: Option<bool>,
+) {
+    if let Some(ipf) = ipf {
+        settings.ipf = ipf;
+    }
+    if let Some(quirks) = quirks {
+        settings.quirks = quirks;
+    }
+    if let Some(theme) = theme {
+        settings.theme = theme;
+    }
+    if let Some(keymap) = keymap {
+        settings.keymap.set_layout(&keymap);
+    }
+    if let Some(audio) = audio {
+        settings.audio = audio;
+    }
+}
+
+// After the theme, which they change
+fn apply_colors(
+    settings: &mut Settings,
+    palette: Option<&[Rgb]>,
+    fg: Option<Rgb>,
+    bg: Option<Rgb>,
+) {
+    if let Some(palette) = palette {
+        settings.theme = settings.theme.with_palette(palette);
+    }
+    if let Some(fg) = fg {
+        settings.theme = settings.theme.with_fg(fg);
+    }
+    if let Some(bg) = bg {
+        settings.theme = settings.theme.with_bg(bg);
+    }
+}
+
+fn expand_home(path: PathBuf) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path,
+    }
+}
+
+impl<'de> Deserialize<'de> for RomHash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hash = String::deserialize(deserializer)?.to_ascii_lowercase();
+        if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(D::Error::custom(format!(
+                "'{}' isn't a ROM hash, expected the 40 hex digit SHA-1 that `emulator info` prints",
+                hash
+            )));
+        }
+        Ok(RomHash(hash))
+    }
+}
+
+// Errors raised here get reported with the line and column of the bad value
+fn named<'de, D, T>(
+    deserializer: D,
+    lookup: impl Fn(&str) -> Option<T>,
+    what: &str,
+    expected: String,
+) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    match lookup(&name) {
+        Some(value) => Ok(Some(value)),
+        None => Err(D::Error::custom(format!(
+            "unknown {} '{}', expected {}",
+            what, name, expected
+        ))),
+    }
+}
+
+fn de_ipf<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    match u32::deserialize(deserializer)? {
+        0 => Err(D::Error::custom("ipf must be at least 1")),
+        ipf => Ok(Some(ipf)),
+    }
+}
+
+fn de_quirks<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Quirks>, D::Error> {
+    named(
+        deserializer,
+        Quirks::profile,
+        "quirk profile",
+        format!("one of {}", Quirks::profile_names().join(", ")),
+    )
+}
+
+fn de_theme<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Theme>, D::Error> {
+    named(
+        deserializer,
+        Theme::by_name,
+        "theme",
+        format!("one of {}", Theme::names().join(", ")),
+    )
+}
+
+fn de_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Rgb>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    parse_color::<D>(&text).map(Some)
+}
+
+fn de_palette<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<Rgb>>, D::Error> {
+    let colors = Vec::<String>::deserialize(deserializer)?;
+    if colors.len() > 4 {
+        return Err(D::Error::custom(format!(
+            "a palette has at most 4 colours, found {}",
+            colors.len()
+        )));
+    }
+    colors
+        .iter()
+        .map(|text| parse_color::<D>(text))
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+fn parse_color<'de, D: Deserializer<'de>>(text: &str) -> Result<Rgb, D::Error> {
+    theme::parse_hex_color(text)
+        .ok_or_else(|| D::Error::custom(format!("'{}' isn't a colour, expected #rrggbb", text)))
+}
+
+fn de_keymap<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Keymap>, D::Error> {
+    named(
+        deserializer,
+        Keymap::from_name,
+        "keymap",
+        format!(
+            "one of {} or 16 characters for keys 0-F",
+            Keymap::layout_names().join(", ")
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The message for a bad config, which has to point at what's wrong
+    fn error(text: &str) -> String {
+        Config::parse(text).unwrap_err()
+    }
+
+    #[test]
+    fn reports_what_is_wrong() {
+        let message = error("ipf = 15\nspeed = 3\n");
+        assert!(message.contains("line 2") && message.contains("unknown field `speed`"));
+
+        let message = error("quirks = \"turbo\"\n");
+        assert!(message.contains("quirks = \"turbo\""));
+        assert!(message.contains("unknown quirk profile 'turbo', expected one of default"));
+
+        let message = error("fg = \"#33ff3\"\n");
+        assert!(message.contains("fg = \"#33ff3\""));
+        assert!(message.contains("'#33ff3' isn't a colour, expected #rrggbb"));
+
+        let message = error("palette = [\"#000000\", \"red\"]\n");
+        assert!(message.contains("palette = [") && message.contains("'red' isn't a colour"));
+
+        let message = error("[rom.abc123]\nipf = 30\n");
+        assert!(message.contains("[rom.abc123]"));
+        assert!(message.contains("'abc123' isn't a ROM hash"));
+
+        let hash = "0".repeat(40);
+        let message = error(&format!("[rom.{}]\ntheme = \"pink\"\n", hash));
+        assert!(message.contains("line 2") && message.contains("unknown theme 'pink'"));
+    }
+
+    #[test]
+    fn applies_colours_over_the_theme() {
+        let config = Config::parse("theme = \"amber\"\nfg = \"#102030\"\n").unwrap();
+        let mut settings = Settings::default();
+        config.apply(&mut settings);
+        assert_eq!(settings.theme.name, "custom");
+        assert_eq!(settings.theme.planes[1], Some((0x10, 0x20, 0x30)));
+        // The background is still amber's
+        assert_eq!(settings.theme.planes[0], Some((0x1A, 0x10, 0x00)));
+
+        let config = Config::parse(
+            "palette = [\"#000000\", \"#ffffff\", \"#ff0000\", \"#ffff00\"]\nfg = \"#102030\"\n",
+        )
+        .unwrap();
+        config.apply(&mut settings);
+        assert_eq!(
+            settings.theme.planes,
+            [
+                Some((0, 0, 0)),
+                Some((0x10, 0x20, 0x30)),
+                Some((0xFF, 0, 0)),
+                Some((0xFF, 0xFF, 0))
+            ]
+        );
+    }
+}
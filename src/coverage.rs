@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::chip8::{Chip8, ROM_START};
+use crate::disasm;
+use crate::isa::OpCode;
+use crate::observe::Observer;
+
+// Which addresses a run executed as instructions, and which it used as data: sprites for
+// DRW, bytes loaded by LD Vx, [I] and BCD digits stored by LD B, Vx. Words that were only
+// ever data aren't counted as missed code.
+pub struct Coverage {
+    rom: Vec<u8>,
+    labels: BTreeMap<u16, String>,
+    // Where finish writes the report. Ending in .info or .lcov makes it an lcov tracefile
+    path: PathBuf,
+    executed: Vec<u64>,
+    data: Vec<u64>,
+}
+
+// One line of the disassembly listing a report is about
+struct Line {
+    addr: u16,
+    code: bool,
+    runs: u64,
+    reads: u64,
+}
+
+impl Coverage {
+    pub fn new(rom: &[u8], labels: BTreeMap<u16, String>, path: PathBuf) -> Self {
+        Self {
+            rom: rom.to_vec(),
+            labels,
+            path,
+            executed: vec![0; 4096],
+            data: vec![0; 4096],
+        }
+    }
+
+    fn read(&mut self, start: u16, len: u16) {
+        for offset in 0..len {
+            self.data[(start.wrapping_add(offset) & 0xFFF) as usize] += 1;
+        }
+    }
+
+    fn line(&self, instruction: &disasm::Instruction) -> Line {
+        let addr = instruction.addr as usize;
+        let runs = self.executed[addr] + self.executed[(addr + 1) & 0xFFF];
+        let reads = self.data[addr] + self.data[(addr + 1) & 0xFFF];
+        Line {
+            addr: instruction.addr,
+            // Anything that decodes might be code, unless the run only ever used it as data
+            code: instruction.op.is_some() && (runs > 0 || reads == 0),
+            runs,
+            reads,
+        }
+    }
+
+    fn lines(&self) -> Vec<Line> {
+        disasm::disassemble(&self.rom)
+            .iter()
+            .map(|instruction| self.line(instruction))
+            .collect()
+    }
+
+    fn summary(&self) -> String {
+        let lines = self.lines();
+        let code = lines.iter().filter(|line| line.code).count();
+        let run = lines
+            .iter()
+            .filter(|line| line.code && line.runs > 0)
+            .count();
+        let data = (ROM_START as usize..ROM_START as usize + self.rom.len())
+            .filter(|&addr| self.data[addr & 0xFFF] > 0)
+            .count();
+        format!(
+            "{} of {} instructions run ({:.1}%), {} bytes used as data",
+            run,
+            code,
+            100.0 * run as f64 / code.max(1) as f64,
+            data
+        )
+    }
+
+    // The disassembly with how often each word ran or was used as data
+    pub fn annotated(&self) -> String {
+        let lines: BTreeMap<u16, Line> = self
+            .lines()
+            .into_iter()
+            .map(|line| (line.addr, line))
+            .collect();
+        let listing = disasm::annotated_listing(&self.rom, &self.labels, |addr| {
+            let line = &lines[&addr];
+            match (line.runs, line.reads) {
+                (0, 0) if line.code => Some("never run".to_string()),
+                (0, 0) => None,
+                (runs, 0) => Some(format!("run {}", runs)),
+                (0, reads) => Some(format!("data, read {}", reads)),
+                (runs, reads) => Some(format!("run {}, data, read {}", runs, reads)),
+            }
+        });
+        format!("; {}\n{}", self.summary(), listing)
+    }
+
+    // An lcov tracefile for `listing`, the disassembly, with its lines standing in for
+    // source lines
+    pub fn lcov(&self, listing: &Path) -> String {
+        let lines: BTreeMap<u16, Line> = self
+            .lines()
+            .into_iter()
+            .map(|line| (line.addr, line))
+            .collect();
+        let text = disasm::listing(&self.rom, &self.labels);
+        let mut out = format!("TN:\nSF:{}\n", listing.display());
+        let mut functions = Vec::new();
+        let (mut found, mut hit) = (0, 0);
+        for (n, text) in text.lines().enumerate() {
+            // Listing lines are "    0x2A4  ..." or a label
+            if let Some(name) = text.strip_suffix(':') {
+                functions.push((n + 2, name));
+                continue;
+            }
+            let Some(addr) = text
+                .trim_start()
+                .strip_prefix("0x")
+                .and_then(|rest| u16::from_str_radix(rest.get(..3)?, 16).ok())
+            else {
+                continue;
+            };
+            let line = &lines[&addr];
+            if line.code {
+                writeln!(out, "DA:{},{}", n + 1, line.runs).unwrap();
+                found += 1;
+                hit += (line.runs > 0) as u32;
+            }
+        }
+        // Labels that are subroutines count as functions
+        let subroutines: Vec<(usize, &str, u16)> = functions
+            .into_iter()
+            .filter_map(|(line, name)| {
+                let addr = self.labels.iter().find(|(_, label)| *label == name)?.0;
+                self.called(*addr).then_some((line, name, *addr))
+            })
+            .collect();
+        for (line, name, _) in &subroutines {
+            writeln!(out, "FN:{},{}", line, name).unwrap();
+        }
+        for (_, name, addr) in &subroutines {
+            writeln!(out, "FNDA:{},{}", self.executed[*addr as usize], name).unwrap();
+        }
+        let called = subroutines
+            .iter()
+            .filter(|(_, _, addr)| self.executed[*addr as usize] > 0)
+            .count();
+        writeln!(out, "FNF:{}\nFNH:{}", subroutines.len(), called).unwrap();
+        writeln!(out, "LF:{}\nLH:{}\nend_of_record", found, hit).unwrap();
+        out
+    }
+
+    // Whether the ROM has a CALL to an address
+    fn called(&self, addr: u16) -> bool {
+        disasm::disassemble(&self.rom).iter().any(
+            |instruction| matches!(instruction.op, Some(OpCode::Call { addr: to }) if to == addr),
+        )
+    }
+}
+
+impl Observer for Coverage {
+    fn instruction(&mut self, chip8: &Chip8, op: OpCode) {
+        self.executed[chip8.pc as usize] += 1;
+        match op {
+            OpCode::DRW_x_y_nibble { n, .. } => self.read(chip8.i, n as u16),
+            OpCode::LD_vx_I { x } => self.read(chip8.i, x as u16 + 1),
+            OpCode::LD_B_vx { .. } => self.read(chip8.i, 3),
+            _ => {}
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let lcov = matches!(
+            self.path.extension().and_then(|e| e.to_str()),
+            Some("info" | "lcov")
+        );
+        if lcov {
+            // Not .asm, which could well be the game's own source
+            let listing = self.path.with_extension("lst");
+            fs::write(&listing, disasm::listing(&self.rom, &self.labels))?;
+            fs::write(&self.path, self.lcov(&listing))
+        } else {
+            fs::write(&self.path, self.annotated())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless;
+
+    #[test]
+    fn tells_code_from_data() {
+        // 0x200: LD I, 0x208  0x202: DRW V0, V0, 2  0x204: JP 0x204  0x206: CLS (never run)
+        // 0x208: sprite that happens to decode as LD V0, V0
+        let rom = [0xA2, 0x08, 0xD0, 0x02, 0x12, 0x04, 0x00, 0xE0, 0x80, 0x00];
+        let mut chip8 = Chip8::new();
+        chip8.ROM_loader(&rom).unwrap();
+        let mut coverage = Coverage::new(&rom, BTreeMap::new(), PathBuf::new());
+        headless::run_frames_observed(&mut chip8, 1, 4, &mut coverage).unwrap();
+
+        let annotated = coverage.annotated();
+        assert!(annotated.starts_with("; 3 of 4 instructions run (75.0%), 2 bytes used as data"));
+        assert!(annotated.contains("CLS                    ; never run"));
+        assert!(annotated.contains("LD V0, V0              ; data, read 2"));
+        let lcov = coverage.lcov(Path::new("rom.lst"));
+        assert!(lcov.starts_with("TN:\nSF:rom.lst\nDA:1,1\nDA:2,1\nDA:4,2\nDA:5,0\n"));
+        assert!(lcov.ends_with("LF:4\nLH:3\nend_of_record\n"));
+    }
+}
@@ -0,0 +1,1085 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use serde_json::{Value, json};
+
+use crate::asm::{self, SourceMap};
+use crate::debugger::{Breakpoint, Debugger, Stop};
+use crate::disasm;
+use crate::expr::{Expr, Template};
+use crate::framebuffer::{H, W};
+use crate::isa::OpCode;
+use crate::rom;
+use crate::settings::Settings;
+use crate::symbols;
+
+// Debug Adapter Protocol server, so ROMs can be debugged from VS Code, neovim and other
+// editors. Messages are JSON with an HTTP-style Content-Length header, over stdin/stdout or
+// a TCP connection. Launching an .asm file assembles it and keeps the source map, so
+// breakpoints and stack frames work by line. Anything else is loaded as a ROM and debugged
+// by address in the editor's disassembly view.
+
+// There's only ever one thread
+const THREAD_ID: i64 = 1;
+
+// variablesReference of each scope
+const REGISTERS: i64 = 1;
+const KEYPAD: i64 = 2;
+const SCREEN: i64 = 3;
+
+const MEMORY_SIZE: i64 = 4096;
+
+pub fn serve_stdio(settings_for: &dyn Fn(&[u8]) -> Settings) -> io::Result<()> {
+    let messages = read_messages(io::stdin());
+    Session::new(settings_for).run(messages, &mut io::stdout().lock())
+}
+
+// Waits for one connection on localhost and serves it until the editor disconnects
+pub fn serve_tcp(port: u16, settings_for: &dyn Fn(&[u8]) -> Settings) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("Waiting for a debugger on 127.0.0.1:{}", port);
+    let (mut stream, addr) = listener.accept()?;
+    eprintln!("Debugger connected from {}", addr);
+    let messages = read_messages(stream.try_clone()?);
+    Session::new(settings_for).run(messages, &mut stream)
+}
+
+// Reads on a thread of its own, so requests like pause can arrive while the ROM runs
+fn read_messages(input: impl Read + Send + 'static) -> Receiver<Value> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(input);
+        while let Ok(Some(message)) = read_message(&mut reader) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+// Returns None at the end of the input
+fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        match header.split_once(':') {
+            Some((name, value)) if name.eq_ignore_ascii_case("Content-Length") => {
+                length = value.trim().parse().ok();
+            }
+            _ => {}
+        }
+    }
+    let Some(length) = length else {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "message without a Content-Length header",
+        ));
+    };
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+// What the editor asked the ROM to do until it next stops
+#[derive(Debug, Clone, Copy)]
+enum Run {
+    Continue,
+    // Until the stack is back down to this depth
+    StepOver(usize),
+    StepOut(usize),
+}
+
+struct Program {
+    path: PathBuf,
+    // Only for programs assembled from source
+    source: Option<SourceMap>,
+    labels: BTreeMap<u16, String>,
+}
+
+pub struct Session<'a> {
+    settings_for: &'a dyn Fn(&[u8]) -> Settings,
+    debugger: Option<Debugger>,
+    program: Option<Program>,
+    line_breakpoints: BTreeMap<u16, Breakpoint>,
+    address_breakpoints: BTreeMap<u16, Breakpoint>,
+    // Function breakpoints, which are by symbol name
+    symbol_breakpoints: BTreeMap<u16, Breakpoint>,
+    // Logpoint messages waiting to go out
+    logs: Rc<RefCell<Vec<String>>>,
+    stop_on_entry: bool,
+    running: Option<Run>,
+    seq: i64,
+    outgoing: Vec<Value>,
+    done: bool,
+}
+
+impl<'a> Session<'a> {
+    pub fn new(settings_for: &'a dyn Fn(&[u8]) -> Settings) -> Self {
+        Self {
+            settings_for,
+            debugger: None,
+            program: None,
+            line_breakpoints: BTreeMap::new(),
+            address_breakpoints: BTreeMap::new(),
+            symbol_breakpoints: BTreeMap::new(),
+            logs: Rc::default(),
+            stop_on_entry: false,
+            running: None,
+            seq: 0,
+            outgoing: Vec::new(),
+            done: false,
+        }
+    }
+
+    pub fn run(&mut self, messages: Receiver<Value>, output: &mut impl Write) -> io::Result<()> {
+        let mut queued = VecDeque::new();
+        let mut hung_up = false;
+        while !self.done && !hung_up {
+            if let Some(message) = queued.pop_front() {
+                self.handle(&message);
+            } else if let Some(run) = self.running {
+                // Requests and log messages that arrive while running stop it long enough
+                // to be dealt with
+                let logs = self.logs.clone();
+                self.resume(run, || {
+                    loop {
+                        match messages.try_recv() {
+                            Ok(message) => queued.push_back(message),
+                            Err(TryRecvError::Empty) => {
+                                return !queued.is_empty() || !logs.borrow().is_empty();
+                            }
+                            Err(TryRecvError::Disconnected) => {
+                                hung_up = true;
+                                return true;
+                            }
+                        }
+                    }
+                });
+            } else {
+                match messages.recv() {
+                    Ok(message) => self.handle(&message),
+                    Err(_) => hung_up = true,
+                }
+            }
+            // Numbered as they go out, as responses are queued ahead of events
+            for mut message in self.outgoing.drain(..) {
+                self.seq += 1;
+                message["seq"] = self.seq.into();
+                write_message(output, &message)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, request: &Value) {
+        // Events sent while handling a request go out after its response
+        let events_from = self.outgoing.len();
+        let command = request["command"].as_str().unwrap_or_default();
+        let args = &request["arguments"];
+        let result = match command {
+            "initialize" => Ok(capabilities()),
+            "launch" => self.launch(args),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(args),
+            "setFunctionBreakpoints" => self.set_function_breakpoints(args),
+            "setExceptionBreakpoints" => Ok(Value::Null),
+            "configurationDone" => self.configuration_done(),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(scopes()),
+            "variables" => self.variables(args),
+            "setVariable" => self.set_variable(args),
+            "readMemory" => self.read_memory(args),
+            "disassemble" => self.disassemble(args),
+            "continue" => self
+                .start(|_| Run::Continue)
+                .map(|_| json!({ "allThreadsContinued": true })),
+            "next" => self.start(Run::StepOver),
+            "stepOut" => self.start(Run::StepOut),
+            "stepIn" => self.step_in(),
+            "stepBack" => self.go_back(Debugger::step_back),
+            "reverseContinue" => self.go_back(Debugger::reverse_resume),
+            "pause" => {
+                if self.running.take().is_some() {
+                    self.stopped("pause", None);
+                }
+                Ok(Value::Null)
+            }
+            "terminate" => {
+                self.running = None;
+                self.debugger = None;
+                self.event("terminated", Value::Null);
+                Ok(Value::Null)
+            }
+            "disconnect" => {
+                self.done = true;
+                Ok(Value::Null)
+            }
+            _ => Err(format!("{} isn't supported", command)),
+        };
+
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = message.into(),
+        }
+        self.outgoing.insert(events_from, response);
+    }
+
+    fn event(&mut self, event: &str, body: Value) {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.outgoing.push(message);
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<&str>) {
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(text) = text {
+            body["text"] = text.into();
+        }
+        self.event("stopped", body);
+    }
+
+    fn debugger(&mut self) -> Result<&mut Debugger, String> {
+        self.debugger
+            .as_mut()
+            .ok_or_else(|| "no program is running".to_string())
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let path = args["program"]
+            .as_str()
+            .ok_or("launch needs a program to run")?;
+        let path = canonical(Path::new(path));
+        let data = fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let (rom, source) = if path.extension().is_some_and(|ext| ext == "asm") {
+            let text = String::from_utf8_lossy(&data);
+            let (rom, map) =
+                asm::assemble_with_map(&text).map_err(|e| format!("{}:{}", path.display(), e))?;
+            (rom, Some(map))
+        } else {
+            (data, None)
+        };
+        if rom.len() > rom::MAX_SIZE {
+            return Err(format!("{} is too large to be a ROM", path.display()));
+        }
+
+        let settings = (self.settings_for)(&rom);
+        let chip8 = settings.build_chip8(&rom)?;
+        let mut labels = match &source {
+            Some(map) => map
+                .labels
+                .iter()
+                .map(|(name, &addr)| (addr, name.clone()))
+                .collect(),
+            None => disasm::labels(&rom),
+        };
+        let symbol_file = match args["symbols"].as_str() {
+            Some(file) => Some(PathBuf::from(file)),
+            None => symbols::sidecar(&path),
+        };
+        if let Some(file) = symbol_file {
+            labels.extend(symbols::load(&file)?);
+        }
+        let mut debugger = Debugger::new(chip8, settings.ipf);
+        let logs = self.logs.clone();
+        debugger.set_log(move |message| logs.borrow_mut().push(message));
+        self.debugger = Some(debugger);
+        self.program = Some(Program {
+            path,
+            source,
+            labels,
+        });
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        self.sync_breakpoints();
+        // Now the editor can send breakpoints
+        self.event("initialized", Value::Null);
+        Ok(Value::Null)
+    }
+
+    fn configuration_done(&mut self) -> Result<Value, String> {
+        self.debugger()?;
+        if self.stop_on_entry {
+            self.stopped("entry", None);
+        } else {
+            self.running = Some(Run::Continue);
+        }
+        Ok(Value::Null)
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let path = args["source"]["path"]
+            .as_str()
+            .map(|p| canonical(Path::new(p)));
+        let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+
+        let ours = self.program.as_ref().is_some_and(|program| {
+            program.source.is_some() && Some(&program.path) == path.as_ref()
+        });
+        if !ours {
+            // Not our source file, or we have no source for it
+            let breakpoints: Vec<Value> = requested
+                .iter()
+                .map(|b| {
+                    json!({ "verified": false, "line": b["line"], "message": "No source map for this file" })
+                })
+                .collect();
+            return Ok(json!({ "breakpoints": breakpoints }));
+        }
+
+        let mut placed = BTreeMap::new();
+        let mut breakpoints = Vec::new();
+        for requested in &requested {
+            let line = requested["line"].as_u64().unwrap_or(0) as usize;
+            let map = self
+                .program
+                .as_ref()
+                .and_then(|p| p.source.as_ref())
+                .unwrap();
+            let Some((addr, line)) = map.address(line) else {
+                breakpoints
+                    .push(json!({ "verified": false, "line": line, "message": "No code here" }));
+                continue;
+            };
+            match self.breakpoint(requested) {
+                Ok(breakpoint) => {
+                    placed.insert(addr, breakpoint);
+                    breakpoints.push(json!({
+                        "verified": true,
+                        "line": line,
+                        "instructionReference": hex(addr),
+                    }));
+                }
+                Err(e) => {
+                    breakpoints.push(json!({ "verified": false, "line": line, "message": e }))
+                }
+            }
+        }
+        self.line_breakpoints = placed;
+        self.sync_breakpoints();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let mut placed = BTreeMap::new();
+        let mut breakpoints = Vec::new();
+        for requested in args["breakpoints"].as_array().into_iter().flatten() {
+            let addr = requested["instructionReference"]
+                .as_str()
+                .and_then(|reference| self.address_of(reference))
+                .and_then(|addr| addr.checked_add(requested["offset"].as_i64().unwrap_or(0)))
+                .filter(|addr| (0..MEMORY_SIZE).contains(addr))
+                .ok_or_else(|| "Not an address in memory".to_string());
+            match addr.and_then(|addr| Ok((addr as u16, self.breakpoint(requested)?))) {
+                Ok((addr, breakpoint)) => {
+                    placed.insert(addr, breakpoint);
+                    breakpoints
+                        .push(json!({ "verified": true, "instructionReference": hex(addr) }));
+                }
+                Err(e) => breakpoints.push(json!({ "verified": false, "message": e })),
+            }
+        }
+        self.address_breakpoints = placed;
+        self.sync_breakpoints();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn set_function_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let mut placed = BTreeMap::new();
+        let mut breakpoints = Vec::new();
+        for requested in args["breakpoints"].as_array().into_iter().flatten() {
+            let name = requested["name"].as_str().unwrap_or_default();
+            let addr = self
+                .address_of(name)
+                .ok_or_else(|| format!("No symbol called {}", name));
+            match addr.and_then(|addr| Ok((addr as u16, self.breakpoint(requested)?))) {
+                Ok((addr, breakpoint)) => {
+                    placed.insert(addr, breakpoint);
+                    breakpoints
+                        .push(json!({ "verified": true, "instructionReference": hex(addr) }));
+                }
+                Err(e) => breakpoints.push(json!({ "verified": false, "message": e })),
+            }
+        }
+        self.symbol_breakpoints = placed;
+        self.sync_breakpoints();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    // The condition and log message an editor's breakpoint comes with
+    fn breakpoint(&self, requested: &Value) -> Result<Breakpoint, String> {
+        let no_labels = BTreeMap::new();
+        let labels = self.program.as_ref().map_or(&no_labels, |p| &p.labels);
+        let text = |field: &str| requested[field].as_str().filter(|s| !s.trim().is_empty());
+        let condition = text("condition")
+            .map(|condition| Expr::parse(condition, labels))
+            .transpose()
+            .map_err(|e| format!("Bad condition: {}", e))?;
+        let log = text("logMessage")
+            .map(|message| Template::parse(message, labels))
+            .transpose()
+            .map_err(|e| format!("Bad log message: {}", e))?;
+        Ok(Breakpoint { condition, log })
+    }
+
+    // A label or symbol name, or a plain address
+    fn address_of(&self, name: &str) -> Option<i64> {
+        let symbol = self
+            .program
+            .as_ref()
+            .and_then(|program| symbols::address_of(&program.labels, name));
+        symbol
+            .map(i64::from)
+            .or_else(|| parse_number(name))
+            .filter(|addr| (0..MEMORY_SIZE).contains(addr))
+    }
+
+    // The debugger has one breakpoint per address. Where the kinds overlap, line
+    // breakpoints win over instruction ones, and those over function ones.
+    fn sync_breakpoints(&mut self) {
+        let mut all = BTreeMap::new();
+        for breakpoints in [
+            &self.line_breakpoints,
+            &self.address_breakpoints,
+            &self.symbol_breakpoints,
+        ] {
+            for (&addr, breakpoint) in breakpoints {
+                all.entry(addr).or_insert_with(|| breakpoint.clone());
+            }
+        }
+        if let Some(debugger) = &mut self.debugger {
+            debugger.breakpoints = all;
+        }
+    }
+
+    // Continue, next and stepOut all run until something stops them
+    fn start(&mut self, run: impl FnOnce(usize) -> Run) -> Result<Value, String> {
+        let depth = self.debugger()?.chip8.stack().len();
+        self.running = Some(run(depth));
+        Ok(Value::Null)
+    }
+
+    fn step_in(&mut self) -> Result<Value, String> {
+        self.running = None;
+        match self.debugger()?.step() {
+            Ok(_) => self.stopped("step", None),
+            Err(e) => self.stopped("exception", Some(e)),
+        }
+        Ok(Value::Null)
+    }
+
+    // Backwards is replayed from the history rather than run in real time, so it's done
+    // before responding, like stepIn
+    fn go_back(&mut self, run: fn(&mut Debugger) -> Stop) -> Result<Value, String> {
+        self.running = None;
+        match run(self.debugger()?) {
+            Stop::Breakpoint(_) => self.stopped("breakpoint", None),
+            Stop::HistoryStart => self.stopped("step", Some("Start of the history")),
+            _ => self.stopped("step", None),
+        }
+        Ok(Value::Null)
+    }
+
+    fn resume(&mut self, run: Run, interrupted: impl FnMut() -> bool) {
+        let Some(debugger) = &mut self.debugger else {
+            self.running = None;
+            return;
+        };
+        let stop = match run {
+            Run::Continue => debugger.resume(interrupted),
+            Run::StepOver(depth) => {
+                debugger.run_until(interrupted, |chip8| chip8.stack().len() <= depth)
+            }
+            Run::StepOut(depth) => {
+                debugger.run_until(interrupted, |chip8| chip8.stack().len() < depth)
+            }
+        };
+        self.flush_logs();
+        match stop {
+            // Still running, there's just a request or log message to deal with
+            Stop::Interrupted => return,
+            Stop::Step => self.stopped("step", None),
+            Stop::Breakpoint(_) => self.stopped("breakpoint", None),
+            Stop::Watchpoint(..) => self.stopped("data breakpoint", None),
+            Stop::Error(e) => self.stopped("exception", Some(e)),
+            Stop::HistoryStart => self.stopped("step", Some("Start of the history")),
+        }
+        self.running = None;
+    }
+
+    fn flush_logs(&mut self) {
+        let logs = std::mem::take(&mut *self.logs.borrow_mut());
+        for message in logs {
+            self.event(
+                "output",
+                json!({ "category": "console", "output": message + "\n" }),
+            );
+        }
+    }
+
+    // The current instruction, then the CALL each return address on the stack came from
+    fn stack_trace(&mut self) -> Result<Value, String> {
+        let (Some(debugger), Some(program)) = (&self.debugger, &self.program) else {
+            return Err("no program is running".to_string());
+        };
+        let chip8 = &debugger.chip8;
+        let mut pcs = vec![chip8.pc];
+        pcs.extend(chip8.stack().iter().rev().map(|&ret| ret.wrapping_sub(2)));
+
+        let frames: Vec<Value> = pcs
+            .iter()
+            .enumerate()
+            .map(|(id, &pc)| {
+                let mut frame = json!({
+                    "id": id,
+                    "name": disasm::symbolize(&program.labels, pc),
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": hex(pc),
+                });
+                if let Some(line) = program.source.as_ref().and_then(|map| map.line(pc)) {
+                    frame["source"] = source(&program.path);
+                    frame["line"] = line.into();
+                    frame["column"] = 1.into();
+                }
+                frame
+            })
+            .collect();
+        Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+    }
+
+    fn variables(&mut self, args: &Value) -> Result<Value, String> {
+        let chip8 = &self.debugger()?.chip8;
+        let variables: Vec<Value> = match args["variablesReference"].as_i64() {
+            Some(REGISTERS) => {
+                let mut registers: Vec<Value> = chip8
+                    .registers
+                    .iter()
+                    .enumerate()
+                    .map(|(n, &v)| variable(&format!("V{:X}", n), format!("0x{:02X}", v)))
+                    .collect();
+                let mut i = variable("I", hex(chip8.i));
+                i["memoryReference"] = hex(chip8.i).into();
+                let mut pc = variable("PC", hex(chip8.pc));
+                pc["memoryReference"] = hex(chip8.pc).into();
+                registers.extend([
+                    i,
+                    pc,
+                    variable("SP", chip8.sp.to_string()),
+                    variable("DT", chip8.delay_timer().to_string()),
+                    variable("ST", chip8.sound_timer().to_string()),
+                ]);
+                registers
+            }
+            Some(KEYPAD) => chip8
+                .keypad
+                .iter()
+                .enumerate()
+                .map(|(key, &down)| {
+                    variable(
+                        &format!("{:X}", key),
+                        if down { "down" } else { "up" }.to_string(),
+                    )
+                })
+                .collect(),
+            Some(SCREEN) => (0..H)
+                .map(|y| {
+                    let row: String = (0..W)
+                        .map(|x| if chip8.fb.get(x, y) { '█' } else { ' ' })
+                        .collect();
+                    variable(&format!("{:02}", y), row)
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn set_variable(&mut self, args: &Value) -> Result<Value, String> {
+        let name = args["name"].as_str().unwrap_or_default();
+        let value = args["value"].as_str().unwrap_or_default().trim();
+        let chip8 = &mut self.debugger()?.chip8;
+        let shown = match args["variablesReference"].as_i64() {
+            Some(REGISTERS) => {
+                let n = parse_number(value)
+                    .filter(|n| (0..=0xFFFF).contains(n))
+                    .ok_or("expected a number like 42 or 0x2A")? as u16;
+                let byte = || u8::try_from(n).map_err(|_| format!("{} is 8 bits", name));
+                match name {
+                    "I" => {
+                        if n as usize >= chip8.memory().len() {
+                            return Err("I has to point into the 4K of memory".to_string());
+                        }
+                        chip8.i = n;
+                        hex(n)
+                    }
+                    "PC" => {
+                        // Both bytes of the instruction have to be in memory
+                        if n > 0xFFE {
+                            return Err("PC can be at most 0xFFE".to_string());
+                        }
+                        chip8.pc = n;
+                        hex(n)
+                    }
+                    "DT" => {
+                        chip8.set_delay_timer(byte()?);
+                        n.to_string()
+                    }
+                    "ST" => {
+                        chip8.set_sound_timer(byte()?);
+                        n.to_string()
+                    }
+                    _ => {
+                        let register = name
+                            .strip_prefix('V')
+                            .and_then(|r| u8::from_str_radix(r, 16).ok())
+                            .filter(|&r| r < 16)
+                            .ok_or_else(|| format!("{} can't be changed", name))?;
+                        chip8.registers[register as usize] = byte()?;
+                        format!("0x{:02X}", n)
+                    }
+                }
+            }
+            Some(KEYPAD) => {
+                let key = u8::from_str_radix(name, 16)
+                    .ok()
+                    .filter(|&k| k < 16)
+                    .ok_or("no such key")?;
+                let down = match value {
+                    "down" | "1" | "true" => true,
+                    "up" | "0" | "false" => false,
+                    _ => return Err("expected down or up".to_string()),
+                };
+                chip8.keypad[key as usize] = down;
+                value.to_string()
+            }
+            _ => return Err(format!("{} can't be changed", name)),
+        };
+        self.debugger()?.edited();
+        Ok(json!({ "value": shown }))
+    }
+
+    fn read_memory(&mut self, args: &Value) -> Result<Value, String> {
+        let addr = args["memoryReference"]
+            .as_str()
+            .and_then(parse_number)
+            .ok_or("memoryReference isn't an address")?;
+        let start = addr.saturating_add(args["offset"].as_i64().unwrap_or(0));
+        let count = args["count"].as_i64().unwrap_or(0).max(0);
+        let memory = self.debugger()?.chip8.memory();
+        let readable =
+            start.clamp(0, MEMORY_SIZE)..start.saturating_add(count).clamp(0, MEMORY_SIZE);
+        let data = &memory[readable.start as usize..readable.end as usize];
+        Ok(json!({
+            "address": hex(readable.start as u16),
+            "data": base64(data),
+            "unreadableBytes": count - data.len() as i64,
+        }))
+    }
+
+    fn disassemble(&mut self, args: &Value) -> Result<Value, String> {
+        let addr = args["memoryReference"]
+            .as_str()
+            .and_then(parse_number)
+            .ok_or("memoryReference isn't an address")?;
+        let start = addr
+            .saturating_add(args["offset"].as_i64().unwrap_or(0))
+            .saturating_add(
+                args["instructionOffset"]
+                    .as_i64()
+                    .unwrap_or(0)
+                    .saturating_mul(2),
+            );
+        // Asking for more than all of memory only gets more invalid lines
+        let count = args["instructionCount"]
+            .as_i64()
+            .unwrap_or(0)
+            .clamp(0, MEMORY_SIZE / 2);
+        let (Some(debugger), Some(program)) = (&self.debugger, &self.program) else {
+            return Err("no program is running".to_string());
+        };
+        let memory = debugger.chip8.memory();
+
+        let instructions: Vec<Value> = (0..count)
+            .map(|n| start.saturating_add(2 * n))
+            .map(|addr| {
+                if !(0..MEMORY_SIZE - 1).contains(&addr) {
+                    return json!({
+                        "address": format!("{}0x{:03X}", if addr < 0 { "-" } else { "" }, addr.unsigned_abs()),
+                        "instruction": "??",
+                        "presentationHint": "invalid",
+                    });
+                }
+                let bytes = &memory[addr as usize..addr as usize + 2];
+                let word = u16::from_be_bytes([bytes[0], bytes[1]]);
+                let addr = addr as u16;
+                let mut instruction = json!({
+                    "address": hex(addr),
+                    "instructionBytes": format!("{:04X}", word),
+                    "instruction": match OpCode::decode(word) {
+                        Some(op) => disasm::format_op(op, &program.labels),
+                        None => format!("DB 0x{:02X}, 0x{:02X}", bytes[0], bytes[1]),
+                    },
+                });
+                if let Some(label) = program.labels.get(&addr) {
+                    instruction["symbol"] = label.clone().into();
+                }
+                if let Some(line) = program.source.as_ref().and_then(|map| map.line(addr)) {
+                    instruction["location"] = source(&program.path);
+                    instruction["line"] = line.into();
+                }
+                instruction
+            })
+            .collect();
+        Ok(json!({ "instructions": instructions }))
+    }
+}
+
+fn capabilities() -> Value {
+    json!({
+        "supportsConfigurationDoneRequest": true,
+        "supportsSetVariable": true,
+        "supportsReadMemoryRequest": true,
+        "supportsDisassembleRequest": true,
+        "supportsInstructionBreakpoints": true,
+        "supportsFunctionBreakpoints": true,
+        "supportsStepBack": true,
+        "supportsConditionalBreakpoints": true,
+        "supportsLogPoints": true,
+        "supportsTerminateRequest": true,
+    })
+}
+
+// Registers are global, so every frame has the same scopes
+fn scopes() -> Value {
+    json!({ "scopes": [
+        { "name": "Registers", "variablesReference": REGISTERS, "presentationHint": "registers", "expensive": false },
+        { "name": "Keypad", "variablesReference": KEYPAD, "expensive": false },
+        { "name": "Screen", "variablesReference": SCREEN, "expensive": true },
+    ]})
+}
+
+fn variable(name: &str, value: String) -> Value {
+    json!({ "name": name, "value": value, "variablesReference": 0 })
+}
+
+fn source(path: &Path) -> Value {
+    json!({
+        "name": path.file_name().map(|n| n.to_string_lossy()),
+        "path": path,
+    })
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn hex(addr: u16) -> String {
+    format!("0x{:03X}", addr)
+}
+
+// Decimal, or hex with 0x
+fn parse_number(s: &str) -> Option<i64> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (n, &b)| bits | ((b as u32) << (16 - 8 * n)));
+        for n in 0..4 {
+            if n <= chunk.len() {
+                out.push(ALPHABET[((bits >> (18 - 6 * n)) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(_: &[u8]) -> Settings {
+        Settings::default()
+    }
+
+    fn request(session: &mut Session, command: &str, arguments: Value) -> Value {
+        session.handle(&json!({ "seq": 1, "command": command, "arguments": arguments }));
+        let n = session
+            .outgoing
+            .iter()
+            .position(|m| m["type"] == "response")
+            .unwrap();
+        let response = session.outgoing.remove(n);
+        assert_eq!(response["success"], true, "{}", response);
+        response["body"].clone()
+    }
+
+    fn events(session: &mut Session) -> Vec<String> {
+        session
+            .outgoing
+            .drain(..)
+            .filter(|m| m["type"] == "event")
+            .map(|m| m["event"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    fn launch(session: &mut Session, name: &str, source: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}.asm", name, std::process::id()));
+        fs::write(&path, source).unwrap();
+        request(
+            session,
+            "launch",
+            json!({ "program": path, "stopOnEntry": true }),
+        );
+        events(session);
+        canonical(&path)
+    }
+
+    const SOURCE: &str = "\
+start:
+    LD V0, 1      ; line 2
+loop:
+    ADD V0, 1     ; line 4
+    CALL sub      ; line 5
+    JP loop
+sub:
+    LD I, 0x300
+    RET
+";
+
+    #[test]
+    fn stops_at_line_breakpoints() {
+        let settings_for = settings;
+        let mut session = Session::new(&settings_for);
+        let path = launch(&mut session, "dap-lines", SOURCE);
+
+        let body = request(
+            &mut session,
+            "setBreakpoints",
+            json!({ "source": { "path": path }, "breakpoints": [{ "line": 3 }, { "line": 100 }] }),
+        );
+        // A breakpoint on the label moves down to the instruction after it
+        assert_eq!(body["breakpoints"][0]["line"], 4);
+        assert_eq!(body["breakpoints"][0]["instructionReference"], "0x202");
+        assert_eq!(body["breakpoints"][1]["verified"], false);
+
+        request(&mut session, "configurationDone", Value::Null);
+        assert_eq!(events(&mut session), ["stopped"]);
+        request(&mut session, "continue", Value::Null);
+        session.resume(session.running.unwrap(), || false);
+        assert_eq!(events(&mut session), ["stopped"]);
+
+        let body = request(&mut session, "stackTrace", Value::Null);
+        assert_eq!(body["stackFrames"][0]["line"], 4);
+        assert_eq!(body["stackFrames"][0]["name"], "loop");
+    }
+
+    #[test]
+    fn stops_at_function_breakpoints() {
+        let settings_for = settings;
+        let mut session = Session::new(&settings_for);
+        launch(&mut session, "dap-functions", SOURCE);
+        let body = request(
+            &mut session,
+            "setFunctionBreakpoints",
+            json!({ "breakpoints": [{ "name": "sub" }, { "name": "nowhere" }] }),
+        );
+        assert_eq!(body["breakpoints"][0]["instructionReference"], "0x208");
+        assert_eq!(body["breakpoints"][1]["verified"], false);
+
+        request(&mut session, "continue", Value::Null);
+        session.resume(session.running.unwrap(), || false);
+        assert_eq!(session.debugger.as_ref().unwrap().chip8.pc, 0x208);
+        let body = request(
+            &mut session,
+            "disassemble",
+            json!({ "memoryReference": "0x204", "instructionCount": 1 }),
+        );
+        assert_eq!(body["instructions"][0]["instruction"], "CALL sub");
+    }
+
+    #[test]
+    fn checks_conditions_and_logs_messages() {
+        let settings_for = settings;
+        let mut session = Session::new(&settings_for);
+        let path = launch(&mut session, "dap-conditions", SOURCE);
+        let body = request(
+            &mut session,
+            "setBreakpoints",
+            json!({ "source": { "path": path }, "breakpoints": [
+                { "line": 4, "condition": "V0 == 4" },
+                { "line": 5, "logMessage": "V0 is {V0}" },
+                { "line": 8, "condition": "V0 ==" },
+            ] }),
+        );
+        assert_eq!(body["breakpoints"][1]["verified"], true);
+        assert_eq!(body["breakpoints"][2]["verified"], false);
+
+        request(&mut session, "continue", Value::Null);
+        session.resume(session.running.unwrap(), || false);
+        let output: Vec<Value> = session
+            .outgoing
+            .iter()
+            .filter(|m| m["event"] == "output")
+            .map(|m| m["body"]["output"].clone())
+            .collect();
+        assert_eq!(output, ["V0 is 2\n", "V0 is 3\n", "V0 is 4\n"]);
+        assert_eq!(session.outgoing.last().unwrap()["event"], "stopped");
+        assert_eq!(session.debugger.as_ref().unwrap().chip8.registers[0], 4);
+    }
+
+    #[test]
+    fn steps_over_and_out_of_calls() {
+        let settings_for = settings;
+        let mut session = Session::new(&settings_for);
+        launch(&mut session, "dap-steps", SOURCE);
+        request(&mut session, "configurationDone", Value::Null);
+        request(&mut session, "stepIn", Value::Null);
+        request(&mut session, "stepIn", Value::Null);
+        // At the CALL
+        request(&mut session, "next", Value::Null);
+        session.resume(session.running.unwrap(), || false);
+        assert_eq!(session.debugger.as_ref().unwrap().chip8.pc, 0x206);
+        assert_eq!(session.debugger.as_ref().unwrap().chip8.i, 0x300);
+
+        // Back round the loop and into the subroutine
+        for _ in 0..3 {
+            request(&mut session, "stepIn", Value::Null);
+        }
+        let body = request(&mut session, "stackTrace", Value::Null);
+        assert_eq!(body["totalFrames"], 2);
+        assert_eq!(body["stackFrames"][1]["line"], 5);
+        request(&mut session, "stepOut", Value::Null);
+        session.resume(session.running.unwrap(), || false);
+        assert_eq!(session.debugger.as_ref().unwrap().chip8.pc, 0x206);
+    }
+
+    #[test]
+    fn shows_and_sets_registers_and_memory() {
+        let settings_for = settings;
+        let mut session = Session::new(&settings_for);
+        launch(&mut session, "dap-vars", SOURCE);
+        request(&mut session, "stepIn", Value::Null);
+
+        let body = request(
+            &mut session,
+            "variables",
+            json!({ "variablesReference": REGISTERS }),
+        );
+        assert_eq!(body["variables"][0]["value"], "0x01");
+        assert_eq!(body["variables"][17]["name"], "PC");
+        assert_eq!(body["variables"][17]["value"], "0x202");
+
+        let body = request(
+            &mut session,
+            "setVariable",
+            json!({ "variablesReference": REGISTERS, "name": "V3", "value": "0x42" }),
+        );
+        assert_eq!(body["value"], "0x42");
+        assert_eq!(session.debugger.as_ref().unwrap().chip8.registers[3], 0x42);
+
+        // Out of memory, so the next step would have nothing to run
+        for (name, value) in [("PC", "0xFFF"), ("I", "0x1000")] {
+            let arguments =
+                json!({ "variablesReference": REGISTERS, "name": name, "value": value });
+            session.handle(&json!({ "seq": 1, "command": "setVariable", "arguments": arguments }));
+            let response = session.outgoing.pop().unwrap();
+            assert_eq!(response["success"], false, "{}", response);
+        }
+        let chip8 = &session.debugger.as_ref().unwrap().chip8;
+        assert_eq!((chip8.pc, chip8.i), (0x202, 0));
+        request(&mut session, "stepIn", Value::Null);
+
+        let body = request(
+            &mut session,
+            "readMemory",
+            json!({ "memoryReference": "0x200", "offset": 0, "count": 3 }),
+        );
+        // 60 01 70
+        assert_eq!(body["data"], "YAFw");
+        assert_eq!(body["unreadableBytes"], 0);
+        let body = request(
+            &mut session,
+            "readMemory",
+            json!({ "memoryReference": "0xFFE", "count": 4 }),
+        );
+        assert_eq!(body["unreadableBytes"], 2);
+
+        let body = request(
+            &mut session,
+            "disassemble",
+            json!({ "memoryReference": "0x202", "instructionCount": 2 }),
+        );
+        assert_eq!(body["instructions"][0]["symbol"], "loop");
+        assert_eq!(body["instructions"][1]["line"], 5);
+
+        // Whatever the editor asks for, nothing overflows
+        let body = request(
+            &mut session,
+            "readMemory",
+            json!({ "memoryReference": "0x200", "offset": i64::MAX, "count": i64::MAX }),
+        );
+        assert_eq!(body["data"], "");
+        let body = request(
+            &mut session,
+            "disassemble",
+            json!({ "memoryReference": "0x200", "instructionOffset": i64::MIN,
+                    "instructionCount": i64::MAX }),
+        );
+        let instructions = body["instructions"].as_array().unwrap();
+        assert_eq!(instructions.len(), 2048);
+        assert_eq!(instructions[0]["presentationHint"], "invalid");
+        let body = request(
+            &mut session,
+            "setInstructionBreakpoints",
+            json!({ "breakpoints": [{ "instructionReference": "0x200", "offset": i64::MAX }] }),
+        );
+        assert_eq!(body["breakpoints"][0]["verified"], false);
+    }
+}
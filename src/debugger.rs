@@ -0,0 +1,487 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::chip8::{Chip8, Snapshot};
+use crate::expr::{Expr, Template};
+use crate::isa::OpCode;
+use crate::scheduler::FrameScheduler;
+
+// Why execution stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Step,
+    Breakpoint(u16),
+    // The front end asked us to stop
+    Interrupted,
+    Error(&'static str),
+    // An instruction touched a watched address
+    Watchpoint(Watch, u16),
+    // Going backwards, and there's no more history
+    HistoryStart,
+}
+
+// How often the history takes a snapshot, in instructions, and how many it keeps. Going
+// back replays from the snapshot before, so that's at most a thousand instructions of
+// replay per step back, over the last million or so instructions.
+const SNAPSHOT_INTERVAL: u64 = 1000;
+const MAX_SNAPSHOTS: usize = 1000;
+
+struct Checkpoint {
+    position: u64,
+    // Where we were in the frame
+    executed: u32,
+    snapshot: Snapshot,
+}
+
+// Stops execution when the PC gets to its address, if its condition holds. A logpoint
+// prints its message and carries on instead.
+#[derive(Debug, Clone, Default)]
+pub struct Breakpoint {
+    pub condition: Option<Expr>,
+    pub log: Option<Template>,
+}
+
+// What a watchpoint looks out for. Only the instructions that go through I touch memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watch {
+    Write,
+    Read,
+    Access,
+}
+
+// Drives a core one instruction at a time for the debugger front ends, keeping the 60 Hz
+// frame structure intact: every `ipf` instructions (or when DRW waits for the display) a
+// vblank ticks the timers, same as in the TUI.
+pub struct Debugger {
+    pub chip8: Chip8,
+    pub breakpoints: BTreeMap<u16, Breakpoint>,
+    // By byte, so a watch over a range is one entry for each address in it
+    pub watchpoints: BTreeMap<u16, Watch>,
+    // Where logpoint messages and warnings go, stderr unless the front end has somewhere
+    // better
+    log: Box<dyn FnMut(String)>,
+    ipf: u32,
+    // Instructions run so far in the current frame
+    executed: u32,
+    // The last run stopped for the front end rather than at a breakpoint or step
+    interrupted: bool,
+    // Instructions run since the start, which is where we are in the history
+    position: u64,
+    checkpoints: VecDeque<Checkpoint>,
+    // Keypad changes by position, so replays see the same presses
+    inputs: Vec<(u64, [bool; 16])>,
+}
+
+impl Debugger {
+    pub fn new(chip8: Chip8, ipf: u32) -> Self {
+        let mut debugger = Self {
+            chip8,
+            breakpoints: BTreeMap::new(),
+            watchpoints: BTreeMap::new(),
+            log: Box::new(|message| eprintln!("{}", message)),
+            ipf: ipf.max(1),
+            executed: 0,
+            interrupted: false,
+            position: 0,
+            checkpoints: VecDeque::new(),
+            inputs: Vec::new(),
+        };
+        debugger.checkpoint();
+        debugger
+    }
+
+    pub fn set_log(&mut self, log: impl FnMut(String) + 'static) {
+        self.log = Box::new(log);
+    }
+
+    // The breakpoint at `pc`, if there is one and its condition holds
+    fn triggered(&self, pc: u16) -> Option<&Breakpoint> {
+        self.breakpoints.get(&pc).filter(|breakpoint| {
+            breakpoint
+                .condition
+                .as_ref()
+                .is_none_or(|condition| condition.is_true(&self.chip8))
+        })
+    }
+
+    // Whether the breakpoint at `pc`, if any, stops execution. Conditions and logpoints are
+    // evaluated here, before the instruction runs.
+    fn hit(&mut self, pc: u16) -> bool {
+        match self.triggered(pc).map(|breakpoint| &breakpoint.log) {
+            Some(Some(template)) => {
+                let message = template.render(&self.chip8);
+                (self.log)(message);
+                false
+            }
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    // The watchpoint the instruction at PC sets off, if any. It has to be checked before
+    // the instruction runs, while I still points at what it touches.
+    fn watched(&self) -> Option<(Watch, u16)> {
+        if self.watchpoints.is_empty() {
+            return None;
+        }
+        let (write, len) = match self.chip8.current_op()? {
+            OpCode::LD_I_vx { x } => (true, x as u16 + 1),
+            OpCode::LD_B_vx { .. } => (true, 3),
+            OpCode::LD_vx_I { x } => (false, x as u16 + 1),
+            OpCode::DRW_x_y_nibble { n, .. } => (false, n as u16),
+            _ => return None,
+        };
+        let start = self.chip8.i;
+        self.watchpoints
+            .range(start..start.saturating_add(len))
+            .find(|&(_, &watch)| match watch {
+                Watch::Write => write,
+                Watch::Read => !write,
+                Watch::Access => true,
+            })
+            .map(|(&addr, &watch)| (watch, addr))
+    }
+
+    // Executes one instruction, finishing the frame first if it's used up. Self-modifying
+    // code is logged as a warning.
+    pub fn step(&mut self) -> Result<OpCode, &'static str> {
+        self.record();
+        self.interrupted = false;
+        let result = self.execute();
+        for warning in self.chip8.take_warnings() {
+            (self.log)(format!("warning: {}", warning));
+        }
+        result
+    }
+
+    fn execute(&mut self) -> Result<OpCode, &'static str> {
+        if self.executed >= self.ipf || self.chip8.waiting_for_vblank() {
+            self.end_frame();
+        }
+        let result = self.chip8.step();
+        // An instruction that fails doesn't run, so it isn't part of the history
+        if result.is_ok() {
+            self.executed += 1;
+            self.position += 1;
+        }
+        result
+    }
+
+    fn end_frame(&mut self) {
+        self.chip8.vblank();
+        self.executed = 0;
+    }
+
+    // Runs in real time until a breakpoint is hit or `interrupted` returns true, which is
+    // checked once a frame. The instruction at the current PC always runs, so resuming from
+    // a breakpoint doesn't stop on it again straight away, unless the last run was
+    // interrupted before getting to it.
+    pub fn resume(&mut self, interrupted: impl FnMut() -> bool) -> Stop {
+        self.run_until(interrupted, |_| false)
+    }
+
+    // Like `resume`, but also stops once `done` is true after an instruction. Stepping over
+    // a CALL is running until the stack is back to the depth it was.
+    pub fn run_until(
+        &mut self,
+        mut interrupted: impl FnMut() -> bool,
+        mut done: impl FnMut(&Chip8) -> bool,
+    ) -> Stop {
+        let mut scheduler = FrameScheduler::new();
+        let mut first = !std::mem::take(&mut self.interrupted);
+        loop {
+            while self.executed < self.ipf && !self.chip8.waiting_for_vblank() {
+                let pc = self.chip8.pc;
+                // Logpoints print even for the instruction we start on
+                if self.hit(pc) && !first {
+                    return Stop::Breakpoint(pc);
+                }
+                first = false;
+                let watched = self.watched();
+                if let Err(e) = self.step() {
+                    return Stop::Error(e);
+                }
+                // Stops after the access, like gdb expects
+                if let Some((watch, addr)) = watched {
+                    return Stop::Watchpoint(watch, addr);
+                }
+                if done(&self.chip8) {
+                    return Stop::Step;
+                }
+            }
+            self.end_frame();
+            if interrupted() {
+                self.interrupted = true;
+                return Stop::Interrupted;
+            }
+            scheduler.wait_for_next_frame();
+        }
+    }
+
+    // The front end changed registers or memory. Replaying from an earlier snapshot would
+    // lose that, so take a new one here.
+    pub fn edited(&mut self) {
+        self.checkpoint();
+    }
+
+    // Goes back one instruction
+    pub fn step_back(&mut self) -> Stop {
+        if self.position <= self.history_start() {
+            return Stop::HistoryStart;
+        }
+        self.go_back_to(self.position - 1);
+        Stop::Step
+    }
+
+    // Runs backwards to the last time a breakpoint or watchpoint would have stopped
+    // execution, by replaying each stretch between snapshots, latest first, and noting where
+    // it hits. Logpoints don't print on the way. A watchpoint stops just before the
+    // instruction that set it off, so PC is on the culprit.
+    pub fn reverse_resume(&mut self) -> Stop {
+        let end = self.position;
+        for n in (0..self.checkpoints.len()).rev() {
+            if self.checkpoints[n].position >= end {
+                continue;
+            }
+            let until = self
+                .checkpoints
+                .get(n + 1)
+                .map_or(end, |next| next.position.min(end));
+            self.restore(n);
+            let mut last = None;
+            while self.position < until {
+                let pc = self.chip8.pc;
+                if self
+                    .triggered(pc)
+                    .is_some_and(|breakpoint| breakpoint.log.is_none())
+                {
+                    last = Some((self.position, Stop::Breakpoint(pc)));
+                }
+                if let Some((watch, addr)) = self.watched() {
+                    last = Some((self.position, Stop::Watchpoint(watch, addr)));
+                }
+                self.replay_one();
+            }
+            if let Some((position, stop)) = last {
+                self.go_back_to(position);
+                return stop;
+            }
+        }
+        self.go_back_to(self.history_start());
+        Stop::HistoryStart
+    }
+
+    fn history_start(&self) -> u64 {
+        self.checkpoints
+            .front()
+            .map_or(self.position, |checkpoint| checkpoint.position)
+    }
+
+    // Notes keypad changes, and takes a snapshot every so often
+    fn record(&mut self) {
+        let keypad = self.chip8.keypad;
+        if self.inputs.last().is_none_or(|(_, keys)| *keys != keypad) {
+            self.inputs.push((self.position, keypad));
+        }
+        if self.position.is_multiple_of(SNAPSHOT_INTERVAL)
+            && self
+                .checkpoints
+                .back()
+                .is_none_or(|checkpoint| checkpoint.position != self.position)
+        {
+            self.checkpoint();
+        }
+    }
+
+    fn checkpoint(&mut self) {
+        if self
+            .checkpoints
+            .back()
+            .is_some_and(|checkpoint| checkpoint.position == self.position)
+        {
+            self.checkpoints.pop_back();
+        }
+        self.checkpoints.push_back(Checkpoint {
+            position: self.position,
+            executed: self.executed,
+            snapshot: self.chip8.snapshot(),
+        });
+        if self.checkpoints.len() > MAX_SNAPSHOTS {
+            self.checkpoints.pop_front();
+            let start = self.history_start();
+            self.inputs.retain(|&(position, _)| position >= start);
+        }
+    }
+
+    fn restore(&mut self, n: usize) {
+        let checkpoint = &self.checkpoints[n];
+        self.chip8.restore(&checkpoint.snapshot);
+        self.position = checkpoint.position;
+        self.executed = checkpoint.executed;
+    }
+
+    // Runs the next instruction again, with the keys that were down the first time
+    fn replay_one(&mut self) {
+        if let Ok(n) = self
+            .inputs
+            .binary_search_by_key(&self.position, |&(position, _)| position)
+        {
+            self.chip8.keypad = self.inputs[n].1;
+        }
+        // Any error or warning already happened the first time round
+        let _ = self.execute();
+        self.chip8.take_warnings();
+    }
+
+    // Replays from the snapshot before `position` up to it. What came after is forgotten,
+    // since running on from here can go differently.
+    fn go_back_to(&mut self, position: u64) {
+        let n = self
+            .checkpoints
+            .iter()
+            .rposition(|checkpoint| checkpoint.position <= position)
+            .unwrap_or(0);
+        self.restore(n);
+        while self.position < position {
+            self.replay_one();
+        }
+        self.checkpoints.truncate(n + 1);
+        self.inputs.retain(|&(recorded, _)| recorded < position);
+        self.interrupted = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn debugger(rom: &[u8]) -> Debugger {
+        let mut chip8 = Chip8::new();
+        chip8.ROM_loader(rom).unwrap();
+        // Fast, since running to a stop goes in real time
+        Debugger::new(chip8, 1000)
+    }
+
+    fn state(chip8: &Chip8) -> ([u8; 16], u16, u16) {
+        (chip8.registers, chip8.i, chip8.pc)
+    }
+
+    #[test]
+    fn steps_back_across_a_snapshot() {
+        // 0x200: ADD V0, 1  0x202: ADD I, V0  0x204: JP 0x200
+        let mut debugger = debugger(&[0x70, 0x01, 0xF0, 0x1E, 0x12, 0x00]);
+        let mut states = vec![state(&debugger.chip8)];
+        for _ in 0..1010 {
+            debugger.step().unwrap();
+            states.push(state(&debugger.chip8));
+        }
+        for position in (990..1010).rev() {
+            assert_eq!(debugger.step_back(), Stop::Step);
+            assert_eq!(debugger.position, position);
+            assert_eq!(state(&debugger.chip8), states[position as usize]);
+        }
+    }
+
+    #[test]
+    fn replays_the_keys_that_were_down() {
+        // 0x200: SKP V1  0x202: ADD V2, 1  0x204: ADD V3, 1  0x206: JP 0x200
+        let mut debugger = debugger(&[0xE1, 0x9E, 0x72, 0x01, 0x73, 0x01, 0x12, 0x00]);
+        let mut states = vec![state(&debugger.chip8)];
+        for n in 0..1200 {
+            debugger.chip8.keypad[0] = n % 14 < 7;
+            debugger.step().unwrap();
+            states.push(state(&debugger.chip8));
+        }
+        // Key 0 was held for some of the loops, so V2 lags V3
+        assert!(debugger.chip8.registers[2] != debugger.chip8.registers[3]);
+        for _ in 0..300 {
+            debugger.step_back();
+        }
+        assert_eq!(state(&debugger.chip8), states[900]);
+        // Going back dropped the later presses, so what's down now is up to the front end
+        debugger.chip8.keypad = [false; 16];
+        debugger.step().unwrap();
+        assert_eq!(debugger.inputs.last().unwrap().0, 900);
+    }
+
+    #[test]
+    fn replays_the_same_random_numbers() {
+        // 0x200: RND V0, 0xFF  0x202: ADD I, V0  0x204: JP 0x200
+        let mut debugger = debugger(&[0xC0, 0xFF, 0xF0, 0x1E, 0x12, 0x00]);
+        let mut states = vec![state(&debugger.chip8)];
+        for _ in 0..1500 {
+            debugger.step().unwrap();
+            states.push(state(&debugger.chip8));
+        }
+        for _ in 0..600 {
+            debugger.step_back();
+        }
+        assert_eq!(state(&debugger.chip8), states[900]);
+        for expected in &states[901..] {
+            debugger.step().unwrap();
+            assert_eq!(state(&debugger.chip8), *expected);
+        }
+    }
+
+    #[test]
+    fn history_only_goes_back_so_far() {
+        // 0x200: ADD V0, 1  0x202: JP 0x200
+        let mut debugger = debugger(&[0x70, 0x01, 0x12, 0x00]);
+        let total = SNAPSHOT_INTERVAL * MAX_SNAPSHOTS as u64 + 2500;
+        for _ in 0..total {
+            debugger.step().unwrap();
+        }
+        assert_eq!(debugger.checkpoints.len(), MAX_SNAPSHOTS);
+        let oldest = debugger.history_start();
+        assert_eq!(
+            oldest,
+            total - total % SNAPSHOT_INTERVAL - SNAPSHOT_INTERVAL * 999
+        );
+
+        assert_eq!(debugger.reverse_resume(), Stop::HistoryStart);
+        assert_eq!(debugger.position, oldest);
+        assert_eq!(debugger.chip8.registers[0], (oldest / 2) as u8);
+        assert_eq!(debugger.step_back(), Stop::HistoryStart);
+        assert_eq!(debugger.position, oldest);
+    }
+
+    #[test]
+    fn stops_at_watched_writes_both_ways() {
+        // 0x200: LD I, 0x300  0x202: ADD V0, 1  0x204: SNE V0, 50  0x206: LD [I], V0
+        // 0x208: JP 0x202
+        let mut debugger = debugger(&[0xA3, 0x00, 0x70, 0x01, 0x40, 0x32, 0xF0, 0x55, 0x12, 0x02]);
+        debugger.watchpoints.insert(0x301, Watch::Write);
+        debugger.watchpoints.insert(0x302, Watch::Write);
+        debugger.watchpoints.insert(0x300, Watch::Write);
+        assert_eq!(
+            debugger.resume(|| false),
+            Stop::Watchpoint(Watch::Write, 0x300)
+        );
+        assert_eq!(
+            (debugger.chip8.pc, debugger.chip8.memory()[0x300]),
+            (0x208, 50)
+        );
+        let first = debugger.position;
+        // V0 goes all the way round before it's 50 again, by which time the store has moved
+        // I on to the next byte
+        assert_eq!(
+            debugger.resume(|| false),
+            Stop::Watchpoint(Watch::Write, 0x301)
+        );
+        let second = debugger.position;
+        assert_eq!(second, first + 1 + 3 * 255 + 3);
+
+        // Backwards, it stops on the instruction that did the write
+        assert_eq!(
+            debugger.reverse_resume(),
+            Stop::Watchpoint(Watch::Write, 0x301)
+        );
+        assert_eq!(debugger.position, second - 1);
+        assert_eq!(
+            debugger.reverse_resume(),
+            Stop::Watchpoint(Watch::Write, 0x300)
+        );
+        assert_eq!(debugger.position, first - 1);
+        assert_eq!(debugger.chip8.pc, 0x206);
+        assert_eq!(debugger.chip8.memory()[0x300], 0);
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::chip8::ROM_START;
+use crate::isa::OpCode;
+
+pub struct Instruction {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    // None when the bytes don't decode to an instruction, usually because they are data
+    pub op: Option<OpCode>,
+}
+
+// Linear sweep over every aligned word of the ROM
+pub fn disassemble(rom: &[u8]) -> Vec<Instruction> {
+    rom.chunks(2)
+        .enumerate()
+        .map(|(n, bytes)| {
+            let op = match bytes {
+                [hi, lo] => OpCode::decode(((*hi as u16) << 8) | *lo as u16),
+                _ => None,
+            };
+            Instruction {
+                addr: ROM_START + 2 * n as u16,
+                bytes: bytes.to_vec(),
+                op,
+            }
+        })
+        .collect()
+}
+
+// Names for the targets of jumps and calls
+pub fn labels(rom: &[u8]) -> BTreeMap<u16, String> {
+    let mut labels = BTreeMap::new();
+    for instruction in disassemble(rom) {
+        match instruction.op {
+            Some(OpCode::Call { addr }) => {
+                labels.insert(addr, format!("sub_{:03X}", addr));
+            }
+            Some(OpCode::Jp { addr }) => {
+                labels
+                    .entry(addr)
+                    .or_insert_with(|| format!("label_{:03X}", addr));
+            }
+            _ => {}
+        }
+    }
+    labels
+}
+
+// Names an address after the closest label at or before it, like "sub_2A0+4"
+pub fn symbolize(labels: &BTreeMap<u16, String>, addr: u16) -> String {
+    match labels.range(..=addr).next_back() {
+        Some((&start, name)) if start == addr => name.clone(),
+        Some((&start, name)) => format!("{}+{:X}", name, addr - start),
+        None => format!("0x{:03X}", addr),
+    }
+}
+
+// The instruction with its address operand named after a label, like "CALL draw_paddle"
+pub fn format_op(op: OpCode, labels: &BTreeMap<u16, String>) -> String {
+    let (mnemonic, addr) = match op {
+        OpCode::Jp { addr } => ("JP", addr),
+        OpCode::Call { addr } => ("CALL", addr),
+        OpCode::LD_I_addr { addr } => ("LD I,", addr),
+        OpCode::JP_v0_addr { addr } => ("JP V0,", addr),
+        _ => return op.to_string(),
+    };
+    match labels.get(&addr) {
+        Some(name) => format!("{} {}", mnemonic, name),
+        None => op.to_string(),
+    }
+}
+
+// Symbols from a symbol file name addresses on top of the generated labels
+pub fn listing(rom: &[u8], symbols: &BTreeMap<u16, String>) -> String {
+    annotated_listing(rom, symbols, |_| None)
+}
+
+// A listing with a comment on some instructions, like how often the profiler saw them run
+pub fn annotated_listing(
+    rom: &[u8],
+    symbols: &BTreeMap<u16, String>,
+    annotate: impl Fn(u16) -> Option<String>,
+) -> String {
+    let instructions = disassemble(rom);
+    let mut labels = labels(rom);
+    labels.extend(symbols.iter().map(|(&addr, name)| (addr, name.clone())));
+    // Only name operands after labels the listing defines, so it assembles again
+    labels.retain(|addr, _| instructions.iter().any(|i| i.addr == *addr));
+    let mut out = String::new();
+    for instruction in instructions {
+        if let Some(label) = labels.get(&instruction.addr) {
+            writeln!(out, "{}:", label).unwrap();
+        }
+        let hex: String = instruction
+            .bytes
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect();
+        let text = match instruction.op {
+            Some(op) => format_op(op, &labels),
+            None => data_directive(&instruction.bytes),
+        };
+        let line = format!("    0x{:03X}  {:<4}  {}", instruction.addr, hex, text);
+        match annotate(instruction.addr) {
+            Some(note) => writeln!(out, "{:<40}; {}", line, note).unwrap(),
+            None => writeln!(out, "{}", line).unwrap(),
+        }
+    }
+    out
+}
+
+fn data_directive(bytes: &[u8]) -> String {
+    let values: Vec<String> = bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+    format!("DB {}", values.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+
+    #[test]
+    fn disassembles_what_assembles_again() {
+        let source = "\
+    LD V0, 0x05
+loop:
+    CALL draw
+    ADD V0, 0xFF
+    SE V0, 0
+    JP loop
+    JP V0, table
+draw:
+    LD I, sprite
+    DRW V0, V1, 2
+    RET
+table:
+sprite:
+    DB 0xF0, 0x90
+    DW 0x1234
+";
+        let rom = asm::assemble(source).unwrap();
+        let mut labels = labels(&rom);
+        assert_eq!(labels[&0x202], "label_202");
+        assert_eq!(labels[&0x20C], "sub_20C");
+        // The DW decodes as JP 0x234, past the end, which the listing leaves as a number
+        labels.retain(|&addr, _| addr < ROM_START + rom.len() as u16);
+
+        let mut again = String::new();
+        for instruction in disassemble(&rom) {
+            if let Some(label) = labels.get(&instruction.addr) {
+                writeln!(again, "{}:", label).unwrap();
+            }
+            match instruction.op {
+                Some(op) => writeln!(again, "    {}", format_op(op, &labels)).unwrap(),
+                None => writeln!(again, "    {}", data_directive(&instruction.bytes)).unwrap(),
+            }
+        }
+        assert!(again.contains("    CALL sub_20C\n"));
+        assert_eq!(asm::assemble(&again).unwrap(), rom);
+    }
+
+    #[test]
+    fn names_addresses_after_labels() {
+        let labels = BTreeMap::from([(0x2A0, "draw".to_string())]);
+        assert_eq!(symbolize(&labels, 0x2A0), "draw");
+        assert_eq!(symbolize(&labels, 0x2A4), "draw+4");
+        assert_eq!(symbolize(&labels, 0x200), "0x200");
+    }
+}
@@ -0,0 +1,427 @@
+use std::collections::BTreeMap;
+
+use crate::chip8::Chip8;
+use crate::symbols;
+
+// Expressions for conditional breakpoints and logpoints, evaluated against the machine:
+//
+//   V3 == 0x10 && I > 0x300     registers V0-VF, I, PC, SP, DT and ST
+//   mem[I + 1] != 0             a byte of memory
+//   PC == draw_paddle           labels and symbols stand for their address
+//
+// The operators are the usual ones, with Rust's precedence: unary ! and -, then * / %,
+// + -, &, ^, |, comparisons, && and ||. Comparisons and ! give 1 or 0, and anything
+// that isn't 0 is true.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    BitAnd,
+    BitXor,
+    BitOr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Number(i64),
+    Register(Register),
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+// Longest first, so "<=" isn't read as "<" then "="
+const OPERATORS: [&str; 22] = [
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "+", "-", "*", "/", "%", "&", "^", "|", "!", "(",
+    ")", "[", "]", "=",
+];
+
+impl Register {
+    fn from_name(name: &str) -> Option<Register> {
+        let register = match name.to_ascii_uppercase().as_str() {
+            "I" => Register::I,
+            "PC" => Register::Pc,
+            "SP" => Register::Sp,
+            "DT" => Register::Dt,
+            "ST" => Register::St,
+            upper => {
+                let n = upper.strip_prefix('V')?;
+                if n.len() != 1 {
+                    return None;
+                }
+                Register::V(u8::from_str_radix(n, 16).ok()?)
+            }
+        };
+        Some(register)
+    }
+}
+
+impl BinOp {
+    fn from_token(token: &Token) -> Option<BinOp> {
+        let Token::Op(op) = token else {
+            return None;
+        };
+        let op = match *op {
+            "*" => BinOp::Mul,
+            "/" => BinOp::Div,
+            "%" => BinOp::Rem,
+            "+" => BinOp::Add,
+            "-" => BinOp::Sub,
+            "&" => BinOp::BitAnd,
+            "^" => BinOp::BitXor,
+            "|" => BinOp::BitOr,
+            "==" => BinOp::Eq,
+            "!=" => BinOp::Ne,
+            "<" => BinOp::Lt,
+            "<=" => BinOp::Le,
+            ">" => BinOp::Gt,
+            ">=" => BinOp::Ge,
+            "&&" => BinOp::And,
+            "||" => BinOp::Or,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    // Higher binds tighter
+    fn precedence(self) -> u8 {
+        match self {
+            BinOp::Or => 1,
+            BinOp::And => 2,
+            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => 3,
+            BinOp::BitOr => 4,
+            BinOp::BitXor => 5,
+            BinOp::BitAnd => 6,
+            BinOp::Add | BinOp::Sub => 7,
+            BinOp::Mul | BinOp::Div | BinOp::Rem => 8,
+        }
+    }
+
+    fn apply(self, a: i64, b: i64) -> i64 {
+        match self {
+            BinOp::Mul => a.wrapping_mul(b),
+            // Dividing by zero gives 0 rather than stopping the machine
+            BinOp::Div => a.checked_div(b).unwrap_or(0),
+            BinOp::Rem => a.checked_rem(b).unwrap_or(0),
+            BinOp::Add => a.wrapping_add(b),
+            BinOp::Sub => a.wrapping_sub(b),
+            BinOp::BitAnd => a & b,
+            BinOp::BitXor => a ^ b,
+            BinOp::BitOr => a | b,
+            BinOp::Eq => (a == b) as i64,
+            BinOp::Ne => (a != b) as i64,
+            BinOp::Lt => (a < b) as i64,
+            BinOp::Le => (a <= b) as i64,
+            BinOp::Gt => (a > b) as i64,
+            BinOp::Ge => (a >= b) as i64,
+            BinOp::And => (a != 0 && b != 0) as i64,
+            BinOp::Or => (a != 0 || b != 0) as i64,
+        }
+    }
+}
+
+impl Expr {
+    // `labels` resolves names that aren't registers
+    pub fn parse(text: &str, labels: &BTreeMap<u16, String>) -> Result<Expr, String> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            pos: 0,
+            labels,
+        };
+        let expr = parser.expr(0)?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(expr),
+            // A lone = is almost always a mistyped ==
+            Some(Token::Op("=")) => Err("use == to compare".to_string()),
+            Some(token) => Err(format!("unexpected {}", describe(token))),
+        }
+    }
+
+    pub fn eval(&self, chip8: &Chip8) -> i64 {
+        match self {
+            Expr::Number(n) => *n,
+            Expr::Register(register) => match *register {
+                Register::V(n) => chip8.registers[n as usize] as i64,
+                Register::I => chip8.i as i64,
+                Register::Pc => chip8.pc as i64,
+                Register::Sp => chip8.sp as i64,
+                Register::Dt => chip8.delay_timer() as i64,
+                Register::St => chip8.sound_timer() as i64,
+            },
+            Expr::Memory(addr) => {
+                let memory = chip8.memory();
+                memory[addr.eval(chip8).rem_euclid(memory.len() as i64) as usize] as i64
+            }
+            Expr::Not(e) => (e.eval(chip8) == 0) as i64,
+            Expr::Neg(e) => e.eval(chip8).wrapping_neg(),
+            // && and || don't need to short-circuit, nothing has side effects
+            Expr::Binary(op, a, b) => op.apply(a.eval(chip8), b.eval(chip8)),
+        }
+    }
+
+    pub fn is_true(&self, chip8: &Chip8) -> bool {
+        self.eval(chip8) != 0
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_ascii_alphanumeric() || c == '_' || c == '$' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '$'))
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+            if c.is_ascii_digit() || c == '$' {
+                let n = parse_number(word).ok_or_else(|| format!("bad number {}", word))?;
+                tokens.push(Token::Number(n));
+            } else {
+                tokens.push(Token::Name(word.to_string()));
+            }
+            len
+        } else {
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(**op))
+                .ok_or_else(|| format!("unexpected '{}'", c))?;
+            tokens.push(Token::Op(op));
+            op.len()
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+fn parse_number(s: &str) -> Option<i64> {
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(hex) = s.strip_prefix('$') {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = s.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(n) => n.to_string(),
+        Token::Name(name) => name.clone(),
+        Token::Op(op) => format!("'{}'", op),
+    }
+}
+
+// Precedence climbing over the token list
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    labels: &'a BTreeMap<u16, String>,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Op(found)) if found == op => Ok(()),
+            Some(token) => Err(format!("expected '{}' but found {}", op, describe(&token))),
+            None => Err(format!("expected '{}'", op)),
+        }
+    }
+
+    // Binary operators that bind tighter than `min`
+    fn expr(&mut self, min: u8) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        while let Some(op) = self.tokens.get(self.pos).and_then(BinOp::from_token) {
+            if op.precedence() <= min {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.expr(op.precedence())?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let expr = match self.next() {
+            Some(Token::Number(n)) => Expr::Number(n),
+            Some(Token::Op("!")) => Expr::Not(Box::new(self.unary()?)),
+            Some(Token::Op("-")) => Expr::Neg(Box::new(self.unary()?)),
+            Some(Token::Op("(")) => {
+                let expr = self.expr(0)?;
+                self.expect(")")?;
+                expr
+            }
+            Some(Token::Name(name)) if name.eq_ignore_ascii_case("mem") => {
+                self.expect("[")?;
+                let addr = self.expr(0)?;
+                self.expect("]")?;
+                Expr::Memory(Box::new(addr))
+            }
+            Some(Token::Name(name)) => match Register::from_name(&name) {
+                Some(register) => Expr::Register(register),
+                None => match symbols::address_of(self.labels, &name) {
+                    Some(addr) => Expr::Number(addr as i64),
+                    None => return Err(format!("unknown name {}", name)),
+                },
+            },
+            Some(token) => return Err(format!("unexpected {}", describe(&token))),
+            None => return Err("expression ends too early".to_string()),
+        };
+        Ok(expr)
+    }
+}
+
+// Logpoint messages: text with expressions in braces, like "score={V5} at {PC:x}". `:x`
+// prints in hex, and {{ and }} are literal braces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Value { expr: Expr, hex: bool },
+}
+
+impl Template {
+    pub fn parse(text: &str, labels: &BTreeMap<u16, String>) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = text;
+        while let Some(i) = rest.find(['{', '}']) {
+            literal.push_str(&rest[..i]);
+            let brace = &rest[i..i + 1];
+            rest = &rest[i + 1..];
+            if let Some(after) = rest.strip_prefix(brace) {
+                literal.push_str(brace);
+                rest = after;
+                continue;
+            }
+            if brace == "}" {
+                return Err("unmatched } (use }} for a brace)".to_string());
+            }
+            let end = rest.find('}').ok_or("unmatched { (use {{ for a brace)")?;
+            let inside = &rest[..end];
+            rest = &rest[end + 1..];
+            let (source, hex) = match inside.rsplit_once(':') {
+                Some((source, "x")) => (source, true),
+                _ => (inside, false),
+            };
+            if !literal.is_empty() {
+                parts.push(Part::Text(std::mem::take(&mut literal)));
+            }
+            parts.push(Part::Value {
+                expr: Expr::parse(source, labels)?,
+                hex,
+            });
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Text(literal));
+        }
+        Ok(Template { parts })
+    }
+
+    pub fn render(&self, chip8: &Chip8) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Value { expr, hex: true } => format!("0x{:X}", expr.eval(chip8)),
+                Part::Value { expr, hex: false } => expr.eval(chip8).to_string(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chip8() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.registers[3] = 0x10;
+        chip8.registers[5] = 42;
+        chip8.i = 0x310;
+        chip8.write_memory(0x310, 7);
+        chip8
+    }
+
+    fn eval(text: &str) -> i64 {
+        let labels = BTreeMap::from([(0x2A4, "draw_paddle".to_string())]);
+        Expr::parse(text, &labels).unwrap().eval(&chip8())
+    }
+
+    #[test]
+    fn evaluates_conditions() {
+        assert_eq!(eval("V3 == 0x10 && I > 0x300"), 1);
+        assert_eq!(eval("v3 == 0x10 && I > 0x400"), 0);
+        assert_eq!(eval("mem[I] != 0"), 1);
+        assert_eq!(eval("mem[I + 1]"), 0);
+        assert_eq!(eval("1 + 2 * 3 == 7"), 1);
+        assert_eq!(eval("(1 + 2) * 3"), 9);
+        assert_eq!(eval("V5 & 0xF | 0b1"), 11);
+        assert_eq!(eval("!(V3 < 5) || 1 / 0"), 1);
+        assert_eq!(eval("-PC"), -0x200);
+        assert_eq!(eval("draw_paddle"), 0x2A4);
+    }
+
+    #[test]
+    fn rejects_bad_expressions() {
+        let labels = BTreeMap::new();
+        assert!(Expr::parse("V3 = 1", &labels).is_err());
+        assert!(Expr::parse("V3 ==", &labels).is_err());
+        assert!(Expr::parse("mem[I", &labels).is_err());
+        assert!(Expr::parse("VG", &labels).is_err());
+        assert!(Expr::parse("1 2", &labels).is_err());
+        assert!(Expr::parse("0xZZ", &labels).is_err());
+    }
+
+    #[test]
+    fn renders_templates() {
+        let labels = BTreeMap::new();
+        let template = Template::parse("score={V5} at {I:x} {{raw}}", &labels).unwrap();
+        assert_eq!(template.render(&chip8()), "score=42 at 0x310 {raw}");
+        assert!(Template::parse("oops {V5", &labels).is_err());
+        assert!(Template::parse("{nothing}", &labels).is_err());
+    }
+}
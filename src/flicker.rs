@@ -0,0 +1,163 @@
+use crate::framebuffer::{Framebuffer, H, W};
+
+pub const MAX_LEVEL: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlickerMode {
+    // Present every DRW/CLS as it happens
+    Off,
+    // Present the framebuffer once per 60 Hz frame
+    Vblank,
+    // OR the last two frames together
+    Blend,
+    // Lit pixels fade out over a few frames instead of switching off
+    Phosphor,
+}
+
+const MODES: [FlickerMode; 4] = [
+    FlickerMode::Off,
+    FlickerMode::Vblank,
+    FlickerMode::Blend,
+    FlickerMode::Phosphor,
+];
+
+impl FlickerMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        MODES.iter().copied().find(|m| m.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FlickerMode::Off => "off",
+            FlickerMode::Vblank => "vblank",
+            FlickerMode::Blend => "blend",
+            FlickerMode::Phosphor => "phosphor",
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        let i = MODES.iter().position(|m| m == self).unwrap();
+        MODES[(i + 1) % MODES.len()]
+    }
+}
+
+// Keeps the framebuffer history needed to compute what is actually shown
+pub struct Persistence {
+    pub mode: FlickerMode,
+    prev: Framebuffer,
+    levels: [[u8; W]; H],
+    // Rows whose brightness changed at the last vblanks
+    dirty: u64,
+}
+
+impl Persistence {
+    pub fn new(mode: FlickerMode) -> Self {
+        Self {
+            mode,
+            prev: Framebuffer::new(),
+            levels: [[0; W]; H],
+            dirty: u64::MAX,
+        }
+    }
+
+    // Called once per frame with the framebuffer as it is at vertical blank
+    pub fn vblank(&mut self, fb: &Framebuffer) {
+        for (y, row) in self.levels.iter_mut().enumerate() {
+            let before = *row;
+            for (x, level) in row.iter_mut().enumerate() {
+                let on = fb.get(x, y);
+                *level = match self.mode {
+                    FlickerMode::Off | FlickerMode::Vblank => on as u8 * MAX_LEVEL,
+                    FlickerMode::Blend => (on || self.prev.get(x, y)) as u8 * MAX_LEVEL,
+                    FlickerMode::Phosphor => {
+                        if on {
+                            MAX_LEVEL
+                        } else {
+                            level.saturating_sub(1)
+                        }
+                    }
+                };
+            }
+            if *row != before {
+                self.dirty |= 1 << y;
+            }
+        }
+        self.prev = fb.clone();
+    }
+
+    // Brightness of a pixel from 0 (off) to MAX_LEVEL
+    pub fn level(&self, fb: &Framebuffer, x: usize, y: usize) -> u8 {
+        match self.mode {
+            FlickerMode::Off => fb.get(x, y) as u8 * MAX_LEVEL,
+            _ => self.levels[y][x],
+        }
+    }
+
+    pub fn take_dirty(&mut self) -> u64 {
+        std::mem::take(&mut self.dirty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A screen with only the top left pixel lit, or nothing at all
+    fn screen(lit: bool) -> Framebuffer {
+        let mut fb = Framebuffer::new();
+        fb.xor_row(0, (lit as u64) << 63);
+        fb
+    }
+
+    #[test]
+    fn vblank_shows_the_screen_as_it_was_at_the_last_frame() {
+        let mut persistence = Persistence::new(FlickerMode::Vblank);
+        persistence.vblank(&screen(true));
+        // Erased mid-frame, as a sprite about to be redrawn would be
+        let erased = screen(false);
+        assert_eq!(persistence.level(&erased, 0, 0), MAX_LEVEL);
+        persistence.vblank(&erased);
+        assert_eq!(persistence.level(&erased, 0, 0), 0);
+
+        persistence.mode = FlickerMode::Off;
+        assert_eq!(persistence.level(&screen(true), 0, 0), MAX_LEVEL);
+    }
+
+    #[test]
+    fn blend_keeps_pixels_lit_for_one_more_frame() {
+        let mut persistence = Persistence::new(FlickerMode::Blend);
+        let levels: Vec<u8> = [true, false, false, true, false]
+            .into_iter()
+            .map(|lit| {
+                persistence.vblank(&screen(lit));
+                persistence.level(&screen(false), 0, 0)
+            })
+            .collect();
+        assert_eq!(levels, [3, 3, 0, 3, 3]);
+    }
+
+    #[test]
+    fn phosphor_fades_out_a_level_per_frame() {
+        let mut persistence = Persistence::new(FlickerMode::Phosphor);
+        let levels: Vec<u8> = [true, false, false, true, false, false, false, false]
+            .into_iter()
+            .map(|lit| {
+                persistence.vblank(&screen(lit));
+                persistence.level(&screen(false), 0, 0)
+            })
+            .collect();
+        assert_eq!(levels, [3, 2, 1, 3, 2, 1, 0, 0]);
+    }
+
+    #[test]
+    fn only_rows_that_changed_brightness_are_dirty() {
+        let mut persistence = Persistence::new(FlickerMode::Phosphor);
+        assert_eq!(persistence.take_dirty(), u64::MAX);
+        persistence.vblank(&screen(true));
+        assert_eq!(persistence.take_dirty(), 1);
+        persistence.vblank(&screen(true));
+        assert_eq!(persistence.take_dirty(), 0);
+        persistence.vblank(&screen(false));
+        assert_eq!(persistence.take_dirty(), 1);
+    }
+}
@@ -0,0 +1,70 @@
+pub const W: usize = 64;
+pub const H: usize = 32;
+
+// One bit per pixel, leftmost pixel in the most significant bit. A 64 px lores row fits a
+// u64; SCHIP hires would need u128 rows.
+pub type Row = u64;
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Framebuffer {
+    rows: [Row; H],
+    // Bit y is set when row y changed since the last take_dirty()
+    dirty: u64,
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Framebuffer {
+    pub fn new() -> Self {
+        Self {
+            rows: [0; H],
+            dirty: u64::MAX,
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        (self.rows[y] >> (W - 1 - x)) & 1 == 1
+    }
+
+    pub fn rows(&self) -> &[Row; H] {
+        &self.rows
+    }
+
+    // Replaces the whole screen, e.g. when loading a save state
+    pub fn set_rows(&mut self, rows: [Row; H]) {
+        self.rows = rows;
+        self.dirty = u64::MAX;
+    }
+
+    pub fn clear(&mut self) {
+        self.rows = [0; H];
+        self.dirty = u64::MAX;
+    }
+
+    // XORs a whole row at once. Returns true if any lit pixel was switched off.
+    pub fn xor_row(&mut self, y: usize, bits: Row) -> bool {
+        let collided = self.rows[y] & bits != 0;
+        self.rows[y] ^= bits;
+        if bits != 0 {
+            self.dirty |= 1 << y;
+        }
+        collided
+    }
+
+    // Returns the rows that changed since the last call as a bit mask and resets it
+    pub fn take_dirty(&mut self) -> u64 {
+        std::mem::take(&mut self.dirty)
+    }
+}
+
+// Lines an 8 pixel sprite row up with column x. Pixels that run past the right edge either
+// wrap around to column 0 or are clipped.
+pub fn sprite_row(byte: u8, x: usize, wrap: bool) -> Row {
+    let wide = ((byte as u128) << 120) >> x;
+    let visible = (wide >> 64) as Row;
+    if wrap { visible | wide as Row } else { visible }
+}
@@ -0,0 +1,725 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::debugger::{Breakpoint, Debugger, Stop, Watch};
+use crate::disasm;
+use crate::expr::{Expr, Template};
+use crate::symbols;
+
+// GDB remote serial protocol stub. Registers are numbered V0-VF (0-15), I (16), PC (17),
+// SP (18), DT (19) and ST (20). V0-VF, SP and the timers are 8 bits, I and PC are 16 bits
+// sent little-endian like every other multi-byte value in RSP. gdb has no CHIP-8
+// architecture, so the register layout is described to it with target.xml.
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+const REGISTERS: usize = 21;
+
+// Byte gdb sends out of band to interrupt a running target
+const INTERRUPT: u8 = 0x03;
+
+// Waits for one gdb connection on localhost and serves it until gdb detaches or hangs up.
+// `labels` name addresses for the monitor commands.
+pub fn serve(port: u16, debugger: &mut Debugger, labels: BTreeMap<u16, String>) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("Waiting for gdb on 127.0.0.1:{}", port);
+    let (stream, addr) = listener.accept()?;
+    eprintln!("gdb connected from {}", addr);
+    stream.set_nodelay(true)?;
+    // Logpoints print on gdb's console, which takes output packets while the target runs
+    let mut console = stream.try_clone()?;
+    debugger.set_log(move |message| {
+        let _ = write_packet(&mut console, &format!("O{}", encode_hex(&(message + "\n"))));
+    });
+    Connection::new(stream)?.run(&mut Stub::new(debugger, labels))
+}
+
+// Packet framing, acks and interrupts
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    no_ack: bool,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        Ok(Self {
+            writer: stream.try_clone()?,
+            reader: BufReader::new(stream),
+            no_ack: false,
+        })
+    }
+
+    fn run(&mut self, stub: &mut Stub) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            let reply = stub.handle(&packet, &mut || self.poll_interrupt());
+            match reply {
+                Some(reply) => self.send(&reply)?,
+                None => {
+                    self.send("OK")?;
+                    return Ok(());
+                }
+            }
+            if packet == "QStartNoAckMode" {
+                self.no_ack = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match self.reader.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    // Returns None when gdb hangs up
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // Skip acks and stray interrupts until the start of a packet
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => {}
+                Some(_) => continue,
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let mut checksum = [0; 2];
+            self.reader.read_exact(&mut checksum)?;
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            let valid = expected == Some(checksum_of(&data));
+            if !self.no_ack {
+                self.writer.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        write_packet(&mut self.writer, data)
+    }
+
+    // Checked once a frame while the target runs
+    fn poll_interrupt(&mut self) -> bool {
+        if !self.reader.buffer().is_empty() {
+            return self.reader.buffer().contains(&INTERRUPT);
+        }
+        let stream = self.reader.get_ref();
+        if stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let mut byte = [0];
+        let result = stream.peek(&mut byte);
+        let _ = stream.set_nonblocking(false);
+        match result {
+            Ok(1) if byte[0] == INTERRUPT => {
+                let _ = self.reader.read(&mut byte);
+                true
+            }
+            // gdb hung up while we were running
+            Ok(0) => true,
+            Ok(_) => false,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => false,
+            Err(_) => true,
+        }
+    }
+}
+
+fn stop_reply(stop: Stop) -> String {
+    match stop {
+        Stop::Step | Stop::Breakpoint(_) => "S05",
+        Stop::Interrupted => "S02",
+        // SIGILL
+        Stop::Error(_) => "S04",
+        // Tells gdb it's gone back as far as it can
+        Stop::HistoryStart => "T05replaylog:begin;",
+        Stop::Watchpoint(watch, addr) => {
+            let kind = match watch {
+                Watch::Write => "watch",
+                Watch::Read => "rwatch",
+                Watch::Access => "awatch",
+            };
+            return format!("T05{}:{:x};", kind, addr);
+        }
+    }
+    .to_string()
+}
+
+fn write_packet(writer: &mut impl Write, data: &str) -> io::Result<()> {
+    let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+    writer.write_all(packet.as_bytes())?;
+    writer.flush()
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+// Answers packets. Kept apart from the socket so it can be driven directly.
+pub struct Stub<'a> {
+    debugger: &'a mut Debugger,
+    labels: BTreeMap<u16, String>,
+}
+
+impl<'a> Stub<'a> {
+    pub fn new(debugger: &'a mut Debugger, labels: BTreeMap<u16, String>) -> Self {
+        Self { debugger, labels }
+    }
+
+    // The reply to a packet, or None if the session should end. `interrupted` is polled
+    // while the target runs.
+    pub fn handle(
+        &mut self,
+        packet: &str,
+        interrupted: &mut dyn FnMut() -> bool,
+    ) -> Option<String> {
+        let (command, args) = packet.split_at(packet.len().min(1));
+        let reply = match command {
+            "?" => "S05".to_string(),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(args),
+            "p" => self.read_register(args),
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "Z" | "z" => self.breakpoint(command == "Z", args),
+            "s" => stop_reply(self.step()),
+            "c" => stop_reply(self.debugger.resume(interrupted)),
+            // bs and bc, reverse-stepi and reverse-continue
+            "b" => match args {
+                "s" => stop_reply(self.debugger.step_back()),
+                "c" => stop_reply(self.debugger.reverse_resume()),
+                _ => String::new(),
+            },
+            "D" | "k" => return None,
+            "H" => "OK".to_string(),
+            "T" => "OK".to_string(),
+            "v" => self.v_packet(packet, interrupted),
+            "q" | "Q" => self.query(packet),
+            _ => String::new(),
+        };
+        // Replaying history from before the change would undo it
+        if matches!(command, "G" | "P" | "M") && reply == "OK" {
+            self.debugger.edited();
+        }
+        Some(reply)
+    }
+
+    fn step(&mut self) -> Stop {
+        match self.debugger.step() {
+            Ok(_) => Stop::Step,
+            Err(e) => Stop::Error(e),
+        }
+    }
+
+    fn register_bytes(&self, n: usize) -> Option<Vec<u8>> {
+        let chip8 = &self.debugger.chip8;
+        let bytes = match n {
+            0..=15 => vec![chip8.registers[n]],
+            16 => chip8.i.to_le_bytes().to_vec(),
+            17 => chip8.pc.to_le_bytes().to_vec(),
+            18 => vec![chip8.sp],
+            19 => vec![chip8.delay_timer()],
+            20 => vec![chip8.sound_timer()],
+            _ => return None,
+        };
+        Some(bytes)
+    }
+
+    // Register n from little-endian bytes, with how many bytes it took. Values the machine
+    // can't run with, like a PC past the end of memory, are refused.
+    fn parse_register(n: usize, bytes: &[u8]) -> Option<(u16, usize)> {
+        let word = || Some(u16::from_le_bytes([*bytes.first()?, *bytes.get(1)?]));
+        let byte = *bytes.first()? as u16;
+        match n {
+            0..=15 | 19 | 20 => Some((byte, 1)),
+            16 => Some((word()?, 2)),
+            17 => word().filter(|&pc| pc <= 0xFFE).map(|pc| (pc, 2)),
+            18 => (byte <= 16).then_some((byte, 1)),
+            _ => None,
+        }
+    }
+
+    fn set_register(&mut self, n: usize, value: u16) {
+        let chip8 = &mut self.debugger.chip8;
+        match n {
+            0..=15 => chip8.registers[n] = value as u8,
+            16 => chip8.i = value,
+            17 => chip8.pc = value,
+            18 => chip8.sp = value as u8,
+            19 => chip8.set_delay_timer(value as u8),
+            20 => chip8.set_sound_timer(value as u8),
+            _ => unreachable!("parse_register only accepts registers that exist"),
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        (0..REGISTERS)
+            .flat_map(|n| self.register_bytes(n).unwrap())
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    fn write_registers(&mut self, args: &str) -> String {
+        let Some(bytes) = decode_hex(args) else {
+            return "E01".to_string();
+        };
+        // All of them or none, so a bad value doesn't leave the rest half written
+        let mut values = Vec::with_capacity(REGISTERS);
+        let mut rest = &bytes[..];
+        for n in 0..REGISTERS {
+            let Some((value, used)) = Self::parse_register(n, rest) else {
+                return "E01".to_string();
+            };
+            values.push(value);
+            rest = &rest[used..];
+        }
+        for (n, value) in values.into_iter().enumerate() {
+            self.set_register(n, value);
+        }
+        "OK".to_string()
+    }
+
+    fn read_register(&self, args: &str) -> String {
+        usize::from_str_radix(args, 16)
+            .ok()
+            .and_then(|n| self.register_bytes(n))
+            .map(|bytes| bytes.iter().map(|b| format!("{:02x}", b)).collect())
+            .unwrap_or_else(|| "E01".to_string())
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let parsed = args.split_once('=').and_then(|(n, value)| {
+            let n = usize::from_str_radix(n, 16).ok()?;
+            Some((n, Self::parse_register(n, &decode_hex(value)?)?.0))
+        });
+        match parsed {
+            Some((n, value)) => {
+                self.set_register(n, value);
+                "OK".to_string()
+            }
+            None => "E01".to_string(),
+        }
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        let memory = self.debugger.chip8.memory();
+        match parse_range(args, memory.len()) {
+            Some((addr, len)) => memory[addr..addr + len]
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+            None => "E01".to_string(),
+        }
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let chip8 = &mut self.debugger.chip8;
+        let parsed = args.split_once(':').and_then(|(range, data)| {
+            let (addr, len) = parse_range(range, chip8.memory().len())?;
+            let bytes = decode_hex(data)?;
+            (bytes.len() == len).then_some((addr, bytes))
+        });
+        let Some((addr, bytes)) = parsed else {
+            return "E01".to_string();
+        };
+        for (offset, byte) in bytes.into_iter().enumerate() {
+            chip8.poke(addr + offset, byte);
+        }
+        "OK".to_string()
+    }
+
+    // Software and hardware breakpoints are the same thing here
+    fn breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let mut parts = args.split(',');
+        let (Some(kind), Some(addr)) = (parts.next(), parts.next()) else {
+            return "E01".to_string();
+        };
+        let Ok(addr) = u16::from_str_radix(addr, 16) else {
+            return "E01".to_string();
+        };
+        let watch = match kind {
+            "0" | "1" => None,
+            "2" => Some(Watch::Write),
+            "3" => Some(Watch::Read),
+            "4" => Some(Watch::Access),
+            _ => return String::new(),
+        };
+        if let Some(watch) = watch {
+            // The length is how many bytes to watch from addr
+            let Some(len) = parts
+                .next()
+                .and_then(|len| u16::from_str_radix(len, 16).ok())
+            else {
+                return "E01".to_string();
+            };
+            let end = addr.saturating_add(len).min(0x1000);
+            for addr in addr..end {
+                if insert {
+                    self.debugger.watchpoints.insert(addr, watch);
+                } else {
+                    self.debugger.watchpoints.remove(&addr);
+                }
+            }
+            return "OK".to_string();
+        }
+        if insert {
+            // Keep the condition or message if there's a monitor breakpoint here already
+            self.debugger.breakpoints.entry(addr).or_default();
+        } else {
+            self.debugger.breakpoints.remove(&addr);
+        }
+        "OK".to_string()
+    }
+
+    fn v_packet(&mut self, packet: &str, interrupted: &mut dyn FnMut() -> bool) -> String {
+        if packet == "vCont?" {
+            return "vCont;c;C;s;S".to_string();
+        }
+        let Some(actions) = packet.strip_prefix("vCont;") else {
+            return String::new();
+        };
+        // There's only one thread, so the first action is the one that applies
+        match actions.chars().next() {
+            Some('s' | 'S') => stop_reply(self.step()),
+            Some('c' | 'C') => stop_reply(self.debugger.resume(interrupted)),
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if let Some(command) = packet.strip_prefix("qRcmd,") {
+            let Some(command) = decode_hex(command) else {
+                return "E01".to_string();
+            };
+            let output = self.monitor(&String::from_utf8_lossy(&command));
+            return encode_hex(&(output + "\n"));
+        }
+        if packet.starts_with("qSupported") {
+            return "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+;ReverseStep+;ReverseContinue+".to_string();
+        }
+        if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return xfer(TARGET_XML, args);
+        }
+        match packet {
+            "QStartNoAckMode" => "OK",
+            "qAttached" => "1",
+            "qC" => "QC1",
+            "qfThreadInfo" => "m1",
+            "qsThreadInfo" => "l",
+            _ => "",
+        }
+        .to_string()
+    }
+}
+
+impl Stub<'_> {
+    // `monitor` commands, for breakpoints gdb itself can't set on the target:
+    //
+    //   monitor break draw_paddle if V3 == 0x10   conditional breakpoint
+    //   monitor log 0x2A4 score={V5}              logpoint
+    //   monitor delete draw_paddle
+    //   monitor breakpoints
+    fn monitor(&mut self, command: &str) -> String {
+        let (verb, rest) = command
+            .trim()
+            .split_once(' ')
+            .unwrap_or((command.trim(), ""));
+        let (place, rest) = rest.trim().split_once(' ').unwrap_or((rest.trim(), ""));
+        let addr = symbols::address_of(&self.labels, place).or_else(|| {
+            u16::from_str_radix(place.trim_start_matches("0x"), 16)
+                .ok()
+                .filter(|&addr| addr < 0x1000)
+        });
+        let breakpoint = match verb {
+            "breakpoints" => return self.list_breakpoints(),
+            "break" | "log" | "delete" if addr.is_none() => {
+                return format!("no symbol or address {}", place);
+            }
+            "break" => match rest.trim().strip_prefix("if ") {
+                Some(condition) => Expr::parse(condition, &self.labels).map(|condition| {
+                    Breakpoint {
+                        condition: Some(condition),
+                        log: None,
+                    }
+                }),
+                None if rest.trim().is_empty() => Ok(Breakpoint::default()),
+                None => Err("expected break <where> [if <condition>]".to_string()),
+            },
+            "log" => Template::parse(rest.trim().trim_matches('"'), &self.labels)
+                .map(|message| Breakpoint {
+                    condition: None,
+                    log: Some(message),
+                }),
+            "delete" => {
+                return match self.debugger.breakpoints.remove(&addr.unwrap()) {
+                    Some(_) => "Deleted".to_string(),
+                    None => "No breakpoint there".to_string(),
+                };
+            }
+            _ => return "commands: break <where> [if <condition>], log <where> <message>, delete <where>, breakpoints".to_string(),
+        };
+        match breakpoint {
+            Ok(breakpoint) => {
+                let addr = addr.unwrap();
+                self.debugger.breakpoints.insert(addr, breakpoint);
+                format!("Breakpoint at 0x{:03X}", addr)
+            }
+            Err(e) => e,
+        }
+    }
+
+    fn list_breakpoints(&self) -> String {
+        if self.debugger.breakpoints.is_empty() {
+            return "No breakpoints".to_string();
+        }
+        let lines: Vec<String> = self
+            .debugger
+            .breakpoints
+            .iter()
+            .map(|(&addr, breakpoint)| {
+                let kind = match (&breakpoint.condition, &breakpoint.log) {
+                    (_, Some(_)) => "logpoint",
+                    (Some(_), None) => "conditional",
+                    (None, None) => "breakpoint",
+                };
+                format!(
+                    "0x{:03X} {} {}",
+                    addr,
+                    disasm::symbolize(&self.labels, addr),
+                    kind
+                )
+            })
+            .collect();
+        lines.join("\n")
+    }
+}
+
+// One chunk of an object gdb reads with qXfer, prefixed with l if it's the last
+fn xfer(object: &str, args: &str) -> String {
+    let Some((offset, len)) = args.split_once(',').and_then(|(offset, len)| {
+        Some((
+            usize::from_str_radix(offset, 16).ok()?,
+            usize::from_str_radix(len, 16).ok()?,
+        ))
+    }) else {
+        return "E01".to_string();
+    };
+    let start = offset.min(object.len());
+    let end = start.saturating_add(len).min(object.len());
+    let prefix = if end == object.len() { 'l' } else { 'm' };
+    format!("{}{}", prefix, &object[start..end])
+}
+
+// "addr,len" in hex, checked against the size of memory
+fn parse_range(args: &str, size: usize) -> Option<(usize, usize)> {
+    let (addr, len) = args.split_once(',')?;
+    let addr = usize::from_str_radix(addr, 16).ok()?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+    (addr.checked_add(len)? <= size).then_some((addr, len))
+}
+
+fn encode_hex(s: &str) -> String {
+    s.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+
+    fn debugger(rom: &[u8]) -> Debugger {
+        let mut chip8 = Chip8::new();
+        chip8.ROM_loader(rom).unwrap();
+        Debugger::new(chip8, 12)
+    }
+
+    fn send(stub: &mut Stub, packet: &str) -> String {
+        stub.handle(packet, &mut || false).unwrap()
+    }
+
+    #[test]
+    fn reads_and_writes_registers() {
+        // LD V3, 0x42
+        let mut debugger = debugger(&[0x63, 0x42]);
+        let mut stub = Stub::new(&mut debugger, BTreeMap::new());
+        assert_eq!(send(&mut stub, "s"), "S05");
+        let regs = send(&mut stub, "g");
+        // 16 V registers, I and PC as two bytes each, then SP, DT and ST
+        assert_eq!(regs.len(), 2 * (16 + 2 + 2 + 3));
+        assert_eq!(&regs[6..8], "42");
+        assert_eq!(send(&mut stub, "p11"), "0202");
+
+        assert_eq!(send(&mut stub, "P10=3412"), "OK");
+        assert_eq!(send(&mut stub, "p10"), "3412");
+        assert_eq!(stub.debugger.chip8.i, 0x1234);
+        assert_eq!(send(&mut stub, "p15"), "E01");
+
+        // Nothing the next step couldn't run with
+        assert_eq!(send(&mut stub, "P11=ff0f"), "E01");
+        assert_eq!(send(&mut stub, "P12=11"), "E01");
+        assert_eq!(send(&mut stub, "P12=10"), "OK");
+        let regs = send(&mut stub, "g");
+        let bad_sp = format!("{}11{}", &regs[..40], &regs[42..]);
+        let changed_v0 = format!("ff{}", &bad_sp[2..]);
+        assert_eq!(send(&mut stub, &format!("G{}", changed_v0)), "E01");
+        assert_eq!(stub.debugger.chip8.registers[0], 0);
+        assert_eq!(send(&mut stub, &format!("G{}", regs)), "OK");
+    }
+
+    #[test]
+    fn reads_and_writes_memory() {
+        let mut debugger = debugger(&[0x63, 0x42]);
+        let mut stub = Stub::new(&mut debugger, BTreeMap::new());
+        assert_eq!(send(&mut stub, "s"), "S05");
+        assert_eq!(send(&mut stub, "m200,2"), "6342");
+        assert_eq!(send(&mut stub, "M200,2:6401"), "OK");
+        assert_eq!(send(&mut stub, "m200,2"), "6401");
+        // Over code that has run, but it's the debugger writing, not the program
+        assert!(stub.debugger.chip8.take_warnings().is_empty());
+        assert_eq!(send(&mut stub, "mfff,2"), "E01");
+        // Would wrap round to 0 if added without checking
+        assert_eq!(send(&mut stub, "mffffffffffffffff,1"), "E01");
+        assert_eq!(send(&mut stub, "M1,ffffffffffffffff:00"), "E01");
+    }
+
+    #[test]
+    fn continues_to_breakpoint() {
+        // 0x200: LD V0, 1  0x202: ADD V0, 1  0x204: JP 0x202
+        let mut debugger = debugger(&[0x60, 0x01, 0x70, 0x01, 0x12, 0x02]);
+        let mut stub = Stub::new(&mut debugger, BTreeMap::new());
+        assert_eq!(send(&mut stub, "Z0,204,2"), "OK");
+        assert_eq!(send(&mut stub, "c"), "S05");
+        assert_eq!(stub.debugger.chip8.pc, 0x204);
+        assert_eq!(send(&mut stub, "c"), "S05");
+        assert_eq!(stub.debugger.chip8.registers[0], 3);
+
+        assert_eq!(send(&mut stub, "z0,204,2"), "OK");
+        assert_eq!(stub.handle("c", &mut || true).unwrap(), "S02");
+    }
+
+    #[test]
+    fn stops_at_watchpoints() {
+        // 0x200: LD I, 0x300  0x202: LD B, V0  0x204: LD V1, [I]  0x206: JP 0x206
+        let mut chip8 = Chip8::new();
+        chip8
+            .ROM_loader(&[0xA3, 0x00, 0xF0, 0x33, 0xF1, 0x65, 0x12, 0x06])
+            .unwrap();
+        let mut debugger = Debugger::new(chip8, 1000);
+        let mut stub = Stub::new(&mut debugger, BTreeMap::new());
+        assert_eq!(send(&mut stub, "Z2,302,1"), "OK");
+        assert_eq!(send(&mut stub, "Z3,300,2"), "OK");
+        assert_eq!(send(&mut stub, "c"), "T05watch:302;");
+        assert_eq!(send(&mut stub, "c"), "T05rwatch:300;");
+        assert_eq!(send(&mut stub, "z3,300,2"), "OK");
+        assert_eq!(send(&mut stub, "bc"), "T05watch:302;");
+        assert_eq!(stub.debugger.chip8.pc, 0x202);
+        assert_eq!(send(&mut stub, "Z2,300"), "E01");
+    }
+
+    #[test]
+    fn goes_back_to_earlier_breakpoints() {
+        // 0x200: LD V3, 1  0x202: RND V1, 0xFF  0x204: ADD I, V3  0x206: JP 0x202
+        let mut chip8 = Chip8::new();
+        chip8
+            .ROM_loader(&[0x63, 0x01, 0xC1, 0xFF, 0xF3, 0x1E, 0x12, 0x02])
+            .unwrap();
+        // Fast, since continuing runs in real time
+        let mut debugger = Debugger::new(chip8, 1000);
+        let mut stub = Stub::new(&mut debugger, BTreeMap::new());
+        let break_when = |stub: &mut Stub, condition: &str| {
+            let condition = Expr::parse(condition, &BTreeMap::new()).unwrap();
+            stub.debugger.breakpoints.insert(
+                0x206,
+                Breakpoint {
+                    condition: Some(condition),
+                    log: None,
+                },
+            );
+        };
+        // Far enough in to go back over a few snapshots
+        break_when(&mut stub, "I == 400");
+        assert_eq!(send(&mut stub, "c"), "S05");
+        let before = send(&mut stub, "g");
+        break_when(&mut stub, "I == 1500");
+        assert_eq!(send(&mut stub, "c"), "S05");
+
+        break_when(&mut stub, "I == 400");
+        assert_eq!(send(&mut stub, "bc"), "S05");
+        assert_eq!(send(&mut stub, "g"), before);
+        assert_eq!(send(&mut stub, "bs"), "S05");
+        assert_eq!(stub.debugger.chip8.pc, 0x204);
+        // Forwards again replays the same random numbers
+        assert_eq!(send(&mut stub, "s"), "S05");
+        assert_eq!(send(&mut stub, "g"), before);
+
+        stub.debugger.breakpoints.clear();
+        assert_eq!(send(&mut stub, "bc"), "T05replaylog:begin;");
+        assert_eq!(stub.debugger.chip8.pc, 0x200);
+        assert_eq!(send(&mut stub, "bs"), "T05replaylog:begin;");
+    }
+
+    #[test]
+    fn serves_target_description_in_chunks() {
+        let mut debugger = debugger(&[]);
+        let mut stub = Stub::new(&mut debugger, BTreeMap::new());
+        let first = send(&mut stub, "qXfer:features:read:target.xml:0,10");
+        assert_eq!(first, format!("m{}", &TARGET_XML[..16]));
+        let rest = send(&mut stub, "qXfer:features:read:target.xml:10,1000");
+        assert!(rest.starts_with('l') && rest.ends_with("</target>\n"));
+        let huge = send(
+            &mut stub,
+            "qXfer:features:read:target.xml:10,ffffffffffffffff",
+        );
+        assert_eq!(huge, rest);
+    }
+}
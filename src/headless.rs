@@ -0,0 +1,65 @@
+use std::time::{Duration, Instant};
+
+use crate::chip8::Chip8;
+use crate::observe::Observer;
+
+pub struct RunStats {
+    pub instructions: u64,
+    pub frames: u32,
+    pub elapsed: Duration,
+}
+
+impl RunStats {
+    pub fn instructions_per_second(&self) -> f64 {
+        self.instructions as f64 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn frames_per_second(&self) -> f64 {
+        self.frames as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+// Runs the core as fast as possible, without rendering or frame pacing
+pub fn run_frames(chip8: &mut Chip8, frames: u32, ipf: u32) -> Result<RunStats, &'static str> {
+    let start = Instant::now();
+    let mut instructions = 0;
+    for _ in 0..frames {
+        for _ in 0..ipf {
+            if chip8.waiting_for_vblank() {
+                break;
+            }
+            chip8.step()?;
+            instructions += 1;
+        }
+        chip8.vblank();
+    }
+    Ok(RunStats {
+        instructions,
+        frames,
+        elapsed: start.elapsed(),
+    })
+}
+
+// Like run_frames, telling `observer` about every instruction. Stops early on an unknown
+// instruction, same as run_frames.
+pub fn run_frames_observed(
+    chip8: &mut Chip8,
+    frames: u32,
+    ipf: u32,
+    observer: &mut dyn Observer,
+) -> Result<(), &'static str> {
+    for _ in 0..frames {
+        for _ in 0..ipf {
+            if chip8.waiting_for_vblank() {
+                break;
+            }
+            if let Some(op) = chip8.current_op() {
+                observer.instruction(chip8, op);
+            }
+            chip8.step()?;
+        }
+        chip8.vblank();
+        observer.vblank();
+    }
+    Ok(())
+}
@@ -1,3 +1,6 @@
+use std::fmt;
+
+#[allow(non_camel_case_types)]
 #[derive(Debug, Clone, Copy)]
 pub enum OpCode {
     Cls,
@@ -38,13 +41,18 @@ pub enum OpCode {
 
 impl OpCode {
     pub fn from_u16(opcode: u16) -> OpCode {
+        Self::decode(opcode).unwrap_or_else(|| unimplemented!("Unknown opcode: 0x{:04X}", opcode))
+    }
+
+    // Like from_u16, but returns None for words that aren't instructions
+    pub fn decode(opcode: u16) -> Option<OpCode> {
         let opcode_arr: [u8; 4] = [
             ((opcode >> 12) & 0x0F) as u8,
             ((opcode >> 8) & 0x0F) as u8,
             ((opcode >> 4) & 0x0F) as u8,
             (opcode & 0x0F) as u8,
         ];
-        match opcode_arr {
+        let op = match opcode_arr {
             [0x0, 0x0, 0xE, 0x0] => OpCode::Cls,
             [0x0, 0x0, 0xE, 0xE] => OpCode::Ret,
             [0x1, n2, n3, n4] => {
@@ -106,7 +114,94 @@ impl OpCode {
             [0xF, x, 0x3, 0x3] => OpCode::LD_B_vx { x },
             [0xF, x, 0x5, 0x5] => OpCode::LD_I_vx { x },
             [0xF, x, 0x6, 0x5] => OpCode::LD_vx_I { x },
-            _ => unimplemented!("Unknown opcode: 0x{:04X}", opcode),
+            _ => return None,
+        };
+        Some(op)
+    }
+}
+
+impl OpCode {
+    // The inverse of decode
+    pub fn encode(&self) -> u16 {
+        let xy = |base: u16, x: u8, y: u8, n: u16| base | (x as u16) << 8 | (y as u16) << 4 | n;
+        let xkk = |base: u16, x: u8, kk: u8| base | (x as u16) << 8 | kk as u16;
+        let fx = |x: u8, low: u16| 0xF000 | (x as u16) << 8 | low;
+        match *self {
+            OpCode::Cls => 0x00E0,
+            OpCode::Ret => 0x00EE,
+            OpCode::Jp { addr } => 0x1000 | addr,
+            OpCode::Call { addr } => 0x2000 | addr,
+            OpCode::SE_vx_byte { x, kk } => xkk(0x3000, x, kk),
+            OpCode::SNE_vx_byte { x, kk } => xkk(0x4000, x, kk),
+            OpCode::SE_vx_vy { x, y } => xy(0x5000, x, y, 0x0),
+            OpCode::LD_vx_byte { x, kk } => xkk(0x6000, x, kk),
+            OpCode::ADD_vx_byte { x, kk } => xkk(0x7000, x, kk),
+            OpCode::LD_vx_vy { x, y } => xy(0x8000, x, y, 0x0),
+            OpCode::OR_vx_vy { x, y } => xy(0x8000, x, y, 0x1),
+            OpCode::AND_vx_vy { x, y } => xy(0x8000, x, y, 0x2),
+            OpCode::XOR_vx_vy { x, y } => xy(0x8000, x, y, 0x3),
+            OpCode::ADD_vx_vy { x, y } => xy(0x8000, x, y, 0x4),
+            OpCode::SUB_vx_vy { x, y } => xy(0x8000, x, y, 0x5),
+            OpCode::SHR_vx_vy { x, y } => xy(0x8000, x, y, 0x6),
+            OpCode::SUBN_vx_vy { x, y } => xy(0x8000, x, y, 0x7),
+            OpCode::SHL_vx_vy { x, y } => xy(0x8000, x, y, 0xE),
+            OpCode::SNE_vx_vy { x, y } => xy(0x9000, x, y, 0x0),
+            OpCode::LD_I_addr { addr } => 0xA000 | addr,
+            OpCode::JP_v0_addr { addr } => 0xB000 | addr,
+            OpCode::RND_vx_byte { x, kk } => xkk(0xC000, x, kk),
+            OpCode::DRW_x_y_nibble { x, y, n } => xy(0xD000, x, y, n as u16),
+            OpCode::SKP_vx { x } => xkk(0xE000, x, 0x9E),
+            OpCode::SKNP_vx { x } => xkk(0xE000, x, 0xA1),
+            OpCode::LD_vx_dt { x } => fx(x, 0x07),
+            OpCode::LD_vx_k { x } => fx(x, 0x0A),
+            OpCode::LD_dt_vx { x } => fx(x, 0x15),
+            OpCode::LD_st_vx { x } => fx(x, 0x18),
+            OpCode::ADD_I_vx { x } => fx(x, 0x1E),
+            OpCode::LD_F_vx { x } => fx(x, 0x29),
+            OpCode::LD_B_vx { x } => fx(x, 0x33),
+            OpCode::LD_I_vx { x } => fx(x, 0x55),
+            OpCode::LD_vx_I { x } => fx(x, 0x65),
+        }
+    }
+}
+
+impl fmt::Display for OpCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OpCode::Cls => write!(f, "CLS"),
+            OpCode::Ret => write!(f, "RET"),
+            OpCode::Jp { addr } => write!(f, "JP 0x{:03X}", addr),
+            OpCode::Call { addr } => write!(f, "CALL 0x{:03X}", addr),
+            OpCode::SE_vx_byte { x, kk } => write!(f, "SE V{}, 0x{:02X}", x, kk),
+            OpCode::SNE_vx_byte { x, kk } => write!(f, "SNE V{}, 0x{:02X}", x, kk),
+            OpCode::SE_vx_vy { x, y } => write!(f, "SE V{}, V{}", x, y),
+            OpCode::LD_vx_byte { x, kk } => write!(f, "LD V{}, 0x{:02X}", x, kk),
+            OpCode::ADD_vx_byte { x, kk } => write!(f, "ADD V{}, 0x{:02X}", x, kk),
+            OpCode::LD_vx_vy { x, y } => write!(f, "LD V{}, V{}", x, y),
+            OpCode::OR_vx_vy { x, y } => write!(f, "OR V{}, V{}", x, y),
+            OpCode::AND_vx_vy { x, y } => write!(f, "AND V{}, V{}", x, y),
+            OpCode::XOR_vx_vy { x, y } => write!(f, "XOR V{}, V{}", x, y),
+            OpCode::ADD_vx_vy { x, y } => write!(f, "ADD V{}, V{}", x, y),
+            OpCode::SUB_vx_vy { x, y } => write!(f, "SUB V{}, V{}", x, y),
+            OpCode::SHR_vx_vy { x, y } => write!(f, "SHR V{}, V{}", x, y),
+            OpCode::SUBN_vx_vy { x, y } => write!(f, "SUBN V{}, V{}", x, y),
+            OpCode::SHL_vx_vy { x, y } => write!(f, "SHL V{}, V{}", x, y),
+            OpCode::SNE_vx_vy { x, y } => write!(f, "SNE V{}, V{}", x, y),
+            OpCode::LD_I_addr { addr } => write!(f, "LD I, 0x{:03X}", addr),
+            OpCode::JP_v0_addr { addr } => write!(f, "JP V0, 0x{:03X}", addr),
+            OpCode::RND_vx_byte { x, kk } => write!(f, "RND V{}, 0x{:02X}", x, kk),
+            OpCode::DRW_x_y_nibble { x, y, n } => write!(f, "DRW V{}, V{}, {}", x, y, n),
+            OpCode::SKP_vx { x } => write!(f, "SKP V{}", x),
+            OpCode::SKNP_vx { x } => write!(f, "SKNP V{}", x),
+            OpCode::LD_vx_dt { x } => write!(f, "LD V{}, DT", x),
+            OpCode::LD_vx_k { x } => write!(f, "LD V{}, K", x),
+            OpCode::LD_dt_vx { x } => write!(f, "LD DT, V{}", x),
+            OpCode::LD_st_vx { x } => write!(f, "LD ST, V{}", x),
+            OpCode::ADD_I_vx { x } => write!(f, "ADD I, V{}", x),
+            OpCode::LD_F_vx { x } => write!(f, "LD F, V{}", x),
+            OpCode::LD_B_vx { x } => write!(f, "LD B, V{}", x),
+            OpCode::LD_I_vx { x } => write!(f, "LD I, V{}", x),
+            OpCode::LD_vx_I { x } => write!(f, "LD V{}, I", x),
         }
     }
 }
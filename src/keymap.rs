@@ -0,0 +1,117 @@
+use crossterm::event::KeyCode;
+
+// Keyboard key for each CHIP-8 key 0x0-0xF. The built-in layouts put the 4x4 keypad
+// on the same physical keys:
+//   1 2 3 C        1 2 3 4
+//   4 5 6 D   ->   q w e r
+//   7 8 9 E        a s d f
+//   A 0 B F        z x c v
+//
+// Games can also bind the arrow keys, space and enter to whatever CHIP-8 keys they use for
+// movement and fire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keymap {
+    keys: [char; 16],
+    bindings: [Option<u8>; 6],
+}
+
+// Keys a game can bind, in the order of Keymap::bindings
+const BINDABLE: [KeyCode; 6] = [
+    KeyCode::Up,
+    KeyCode::Down,
+    KeyCode::Left,
+    KeyCode::Right,
+    KeyCode::Char(' '),
+    KeyCode::Enter,
+];
+
+const LAYOUTS: [(&str, &str); 4] = [
+    ("qwerty", "x123qweasdzc4rfv"),
+    ("azerty", "x123azeqsdwc4rfv"),
+    ("colemak", "x123qwfarszc4ptv"),
+    ("dvorak", "q123',.aoe;j4puk"),
+];
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::from_name("qwerty").unwrap()
+    }
+}
+
+impl Keymap {
+    // Accepts a layout name or 16 characters giving the key for 0x0 through 0xF
+    pub fn from_name(name: &str) -> Option<Keymap> {
+        let layout = LAYOUTS
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, keys)| *keys)
+            .unwrap_or(name);
+        let keys: Vec<char> = layout.chars().map(|c| c.to_ascii_lowercase()).collect();
+        Some(Keymap {
+            keys: keys.try_into().ok()?,
+            bindings: [None; 6],
+        })
+    }
+
+    pub fn layout_names() -> Vec<&'static str> {
+        LAYOUTS.iter().map(|(n, _)| *n).collect()
+    }
+
+    // Switches to another keyboard layout, keeping the game's bindings
+    pub fn set_layout(&mut self, layout: &Keymap) {
+        self.keys = layout.keys;
+    }
+
+    // Binds one of the arrow keys, space or enter to a CHIP-8 key
+    pub fn bind(&mut self, code: KeyCode, chip8_key: u8) {
+        if let Some(slot) = BINDABLE.iter().position(|&k| k == code) {
+            self.bindings[slot] = Some(chip8_key & 0xF);
+        }
+    }
+
+    pub fn map(&self, code: KeyCode) -> Option<usize> {
+        let slot = BINDABLE.iter().position(|&k| k == code);
+        if let Some(key) = slot.and_then(|slot| self.bindings[slot]) {
+            return Some(key as usize);
+        }
+        match code {
+            KeyCode::Char(c) => self.keys.iter().position(|&k| k == c.to_ascii_lowercase()),
+            _ => None,
+        }
+    }
+
+    // Keyboard key bound to a CHIP-8 key
+    pub fn key(&self, chip8_key: usize) -> char {
+        self.keys[chip8_key]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colemak_puts_the_keypad_on_the_same_keys() {
+        let keymap = Keymap::from_name("colemak").unwrap();
+        // The left hand's home row: 7 8 9 E
+        let row: Vec<_> = "arst"
+            .chars()
+            .map(|c| keymap.map(KeyCode::Char(c)))
+            .collect();
+        assert_eq!(row, [Some(0x7), Some(0x8), Some(0x9), Some(0xE)]);
+        assert_eq!(keymap.map(KeyCode::Char('F')), Some(0x6));
+        assert_eq!(keymap.map(KeyCode::Char('e')), None);
+        assert_eq!(keymap.key(0xD), 'p');
+    }
+
+    #[test]
+    fn switching_layouts_keeps_bindings() {
+        let mut keymap = Keymap::default();
+        keymap.bind(KeyCode::Left, 0x4);
+        keymap.set_layout(&Keymap::from_name("dvorak").unwrap());
+        assert_eq!(keymap.map(KeyCode::Left), Some(0x4));
+        assert_eq!(keymap.map(KeyCode::Char('o')), Some(0x8));
+        assert_eq!(Keymap::from_name("0123456789abcdef").unwrap().key(0xA), 'a');
+        assert_eq!(Keymap::from_name("workman"), None);
+    }
+}
@@ -0,0 +1,29 @@
+pub mod analyze;
+pub mod asm;
+pub mod bench;
+pub mod browser;
+pub mod chip8;
+pub mod config;
+pub mod coverage;
+pub mod dap;
+pub mod debugger;
+pub mod disasm;
+pub mod expr;
+pub mod flicker;
+pub mod framebuffer;
+pub mod gdb;
+pub mod headless;
+pub mod isa;
+pub mod keymap;
+pub mod observe;
+pub mod profile;
+pub mod quirks;
+pub mod rom;
+pub mod romdb;
+pub mod savestate;
+pub mod scheduler;
+pub mod settings;
+pub mod symbols;
+pub mod theme;
+pub mod tui;
+pub mod watch;
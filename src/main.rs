@@ -1,39 +1,405 @@
-mod chip8;
-mod isa;
-mod tui;
+mod cli;
 
-use std::env;
+use std::collections::BTreeMap;
 use std::fs;
 use std::io;
+use std::path::{Path, PathBuf};
 use std::process;
-use tui::App;
 
-fn main() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
+use clap::Parser;
+use cli::{BenchArgs, Cli, Command, RunArgs};
+use emulator::analyze::Analysis;
+use emulator::browser::Browser;
+use emulator::config::Config;
+use emulator::coverage::Coverage;
+use emulator::debugger::Debugger;
+use emulator::framebuffer::{H, W};
+use emulator::isa::OpCode;
+use emulator::observe::Observer;
+use emulator::profile::Profiler;
+use emulator::romdb::RomDatabase;
+use emulator::settings::Settings;
+use emulator::tui::{App, Exit};
+use emulator::{asm, bench, dap, disasm, gdb, headless, rom, symbols};
+use ratatui::DefaultTerminal;
+
+fn fail(message: String) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(1);
+}
 
-    if args.len() < 2 || args.len() > 3 {
-        eprintln!("Usage: {} [--debug] <rom_file>", args[0]);
-        process::exit(1);
+fn read_rom(path: &Path) -> Vec<u8> {
+    let rom = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) => fail(format!(
+            "failed to read ROM file '{}': {}",
+            path.display(),
+            e
+        )),
+    };
+    if rom.len() > rom::MAX_SIZE {
+        fail(format!(
+            "ROM file is too large ({} bytes). Maximum size is {} bytes.",
+            rom.len(),
+            rom::MAX_SIZE
+        ));
     }
+    rom
+}
 
-    let debug = args.len() == 3 && args[1] == "--debug";
-    let rom_path = if args.len() == 3 { &args[2] } else { &args[1] };
+fn load_config(path: Option<&Path>) -> Config {
+    match path {
+        Some(path) => Config::load(path),
+        None => Config::load_default(),
+    }
+    .unwrap_or_else(|e| fail(e.to_string()))
+}
 
-    let rom = match fs::read(rom_path) {
-        Ok(data) => data,
-        Err(e) => {
-            eprintln!("Failed to read ROM file '{}': {}", rom_path, e);
-            process::exit(1);
-        }
+// Lives as long as the process, like the bundled one
+fn load_database(config: &Config) -> &'static RomDatabase {
+    match &config.database {
+        Some(dir) => Box::leak(Box::new(RomDatabase::load(dir).unwrap_or_else(|e| fail(e)))),
+        None => RomDatabase::bundled(),
+    }
+}
+
+// Generated labels with any symbols on top, given or from next to the ROM
+fn rom_labels(path: &Path, rom: &[u8], symbol_file: Option<&Path>) -> BTreeMap<u16, String> {
+    let mut labels = disasm::labels(rom);
+    if let Some(file) = symbol_file
+        .map(Path::to_path_buf)
+        .or_else(|| symbols::sidecar(path))
+    {
+        labels.extend(symbols::load(&file).unwrap_or_else(|e| fail(e)));
+    }
+    labels
+}
+
+// What watches the run of the ROM given on the command line
+fn observer(args: &RunArgs, path: &Path, rom: &[u8]) -> Option<Box<dyn Observer>> {
+    let mut observers: Vec<Box<dyn Observer>> = Vec::new();
+    let labels = || rom_labels(path, rom, args.symbols.as_deref());
+    if args.profile.is_some() || args.folded.is_some() {
+        observers.push(Box::new(Profiler::new(
+            rom,
+            labels(),
+            args.profile.clone(),
+            args.folded.clone(),
+        )));
+    }
+    if let Some(file) = &args.coverage {
+        observers.push(Box::new(Coverage::new(rom, labels(), file.clone())));
+    }
+    match observers.len() {
+        0 => None,
+        1 => observers.pop(),
+        _ => Some(Box::new(observers)),
+    }
+}
+
+fn run(args: &RunArgs) -> io::Result<()> {
+    let config = load_config(args.config.as_deref());
+    let database = load_database(&config);
+    let settings_for = |rom: &[u8]| {
+        let mut settings = Settings::for_rom(rom, database, &config);
+        args.apply(&mut settings);
+        settings
     };
+    let rom = args.rom.clone().map(|path| {
+        let rom = read_rom(&path);
+        (path, rom)
+    });
+    // Check it now rather than once the terminal is taken over
+    if let Some(path) = &args.symbols {
+        symbols::load(path).unwrap_or_else(|e| fail(e));
+    }
+
+    if args.headless {
+        let Some((path, rom)) = rom else {
+            fail("--headless needs a ROM file".to_string());
+        };
+        let settings = settings_for(&rom);
+        let mut chip8 = settings
+            .build_chip8(&rom)
+            .unwrap_or_else(|e| fail(e.to_string()));
+        let result = match observer(args, &path, &rom) {
+            Some(mut observer) => {
+                let result = headless::run_frames_observed(
+                    &mut chip8,
+                    args.frames,
+                    settings.ipf,
+                    &mut *observer,
+                );
+                observer.finish()?;
+                result
+            }
+            None => headless::run_frames(&mut chip8, args.frames, settings.ipf).map(|_| ()),
+        };
+        for warning in chip8.take_warnings() {
+            eprintln!("warning: {}", warning);
+        }
+        if let Err(e) = result {
+            fail(format!("{} at PC 0x{:03X}", e, chip8.pc));
+        }
+        for y in 0..H {
+            let line: String = (0..W)
+                .map(|x| if chip8.fb.get(x, y) { '█' } else { ' ' })
+                .collect();
+            println!("{}", line.trim_end());
+        }
+        return Ok(());
+    }
 
-    if rom.len() > 3584 {
-        eprintln!("ROM file is too large ({} bytes). Maximum size is 3584 bytes.", rom.len());
-        process::exit(1);
+    if let Some(port) = args.gdb {
+        let Some((path, rom)) = rom else {
+            fail("--gdb needs a ROM file".to_string());
+        };
+        let settings = settings_for(&rom);
+        let chip8 = settings
+            .build_chip8(&rom)
+            .unwrap_or_else(|e| fail(e.to_string()));
+        // For breakpoints and log messages by name
+        let labels = rom_labels(&path, &rom, args.symbols.as_deref());
+        return gdb::serve(port, &mut Debugger::new(chip8, settings.ipf), labels);
     }
 
+    let rom_dir = config
+        .rom_dir
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("roms"));
+    let mut browser = Browser::new(&rom_dir, database, &settings_for);
     let mut terminal = ratatui::init();
-    let app_result = App::new(&rom, debug).run(&mut terminal);
+    let watch = args.watch.then_some(args.keep_state);
+    let observer = rom
+        .as_ref()
+        .and_then(|(path, rom)| observer(args, path, rom));
+    let app_result = play(
+        &mut terminal,
+        rom,
+        watch,
+        args.symbols.clone(),
+        observer,
+        &mut browser,
+        &settings_for,
+    );
     ratatui::restore();
     app_result
 }
+
+// Goes back and forth between games and the ROM browser until the user quits
+fn play(
+    terminal: &mut DefaultTerminal,
+    mut rom: Option<(PathBuf, Vec<u8>)>,
+    // Only the ROM from the command line gets watched, profiled, or the --symbols file
+    mut watch: Option<bool>,
+    mut symbols: Option<PathBuf>,
+    mut observer: Option<Box<dyn Observer>>,
+    browser: &mut Browser,
+    settings_for: &dyn Fn(&[u8]) -> Settings,
+) -> io::Result<()> {
+    loop {
+        let (path, game) = match rom.take() {
+            Some(rom) => rom,
+            None => match browser.run(terminal)? {
+                Some(entry) => (entry.path.clone(), entry.rom.clone()),
+                None => return Ok(()),
+            },
+        };
+        let mut settings = settings_for(&game);
+        if settings.title.is_none() {
+            settings.title = path.file_name().map(|n| n.to_string_lossy().into_owned());
+        }
+        // A fresh App means a freshly reset Chip8 every time
+        let mut app = App::new(&game, &settings);
+        if let Some(keep_state) = watch.take() {
+            app.watch(&path, keep_state);
+        }
+        if let Some(symbols) = symbols.take().or_else(|| symbols::sidecar(&path)) {
+            app.load_symbols(&symbols);
+        }
+        if let Some(observer) = observer.take() {
+            app.observe(observer);
+        }
+        let exit = app.run(terminal)?;
+        if let Some(mut observer) = app.take_observer() {
+            observer.finish()?;
+        }
+        if exit == Exit::Quit {
+            return Ok(());
+        }
+    }
+}
+
+fn serve_dap(port: Option<u16>, config: Option<&Path>) -> io::Result<()> {
+    let config = load_config(config);
+    let database = load_database(&config);
+    let settings_for = |rom: &[u8]| Settings::for_rom(rom, database, &config);
+    match port {
+        Some(port) => dap::serve_tcp(port, &settings_for),
+        None => dap::serve_stdio(&settings_for),
+    }
+}
+
+fn assemble(
+    source: &Path,
+    output: Option<PathBuf>,
+    symbol_file: Option<PathBuf>,
+) -> io::Result<()> {
+    let text = fs::read_to_string(source)?;
+    let (rom, map) = asm::assemble_with_map(&text)
+        .unwrap_or_else(|e| fail(format!("{}:{}", source.display(), e)));
+    let output = output.unwrap_or_else(|| source.with_extension("ch8"));
+    fs::write(&output, &rom)?;
+    println!("Wrote {} bytes to {}", rom.len(), output.display());
+    if let Some(path) = symbol_file {
+        fs::write(&path, symbols::to_text(&map.labels))?;
+        println!("Wrote {} symbols to {}", map.labels.len(), path.display());
+    }
+    Ok(())
+}
+
+fn disassemble(path: &Path, symbol_file: Option<PathBuf>) {
+    let rom = read_rom(path);
+    let symbols = match symbol_file.or_else(|| symbols::sidecar(path)) {
+        Some(file) => symbols::load(&file).unwrap_or_else(|e| fail(e)),
+        None => Default::default(),
+    };
+    print!("{}", disasm::listing(&rom, &symbols));
+}
+
+fn info(path: &Path) {
+    let rom = read_rom(path);
+    let instructions = disasm::disassemble(&rom);
+    let decoded = instructions.iter().filter(|i| i.op.is_some()).count();
+    let labels = disasm::labels(&rom);
+
+    println!("File:         {}", path.display());
+    println!("SHA-1:        {}", rom::sha1(&rom));
+    println!(
+        "Size:         {} bytes ({} free)",
+        rom.len(),
+        rom::MAX_SIZE - rom.len()
+    );
+    println!(
+        "Words:        {} decode as instructions, {} don't",
+        decoded,
+        instructions.len() - decoded
+    );
+    println!(
+        "Subroutines:  {}",
+        labels.values().filter(|l| l.starts_with("sub_")).count()
+    );
+
+    match load_database(&load_config(None)).lookup(&rom) {
+        Some(game) => {
+            println!("Title:        {}", game.program.title);
+            if !game.program.authors.is_empty() {
+                println!("Authors:      {}", game.program.authors.join(", "));
+            }
+            if let Some(release) = &game.program.release {
+                println!("Released:     {}", release);
+            }
+            if let Some(platform) = game.platform {
+                println!("Platform:     {}", platform.name);
+            }
+            if let Some(ipf) = game.ipf() {
+                println!("Speed:        {} instructions per frame", ipf);
+            }
+            if let Some(quirks) = game.quirks() {
+                println!(
+                    "Profile:      {} ({})",
+                    quirks.name(),
+                    quirks.enabled().join(", ")
+                );
+            }
+            let keys: Vec<String> = game
+                .key_bindings()
+                .iter()
+                .map(|(code, key)| format!("{} -> {:X}", code, key))
+                .collect();
+            if !keys.is_empty() {
+                println!("Keys:         {}", keys.join(", "));
+            }
+        }
+        None => println!("Title:        not in the ROM database"),
+    }
+
+    let mut quirky: Vec<&str> = instructions
+        .iter()
+        .filter_map(|i| match i.op? {
+            OpCode::SHR_vx_vy { .. } | OpCode::SHL_vx_vy { .. } => Some("shift"),
+            OpCode::OR_vx_vy { .. } | OpCode::AND_vx_vy { .. } | OpCode::XOR_vx_vy { .. } => {
+                Some("vf reset")
+            }
+            OpCode::LD_I_vx { .. } | OpCode::LD_vx_I { .. } => Some("memory increment"),
+            OpCode::JP_v0_addr { .. } => Some("jump"),
+            OpCode::DRW_x_y_nibble { .. } => Some("display wait, clipping"),
+            _ => None,
+        })
+        .collect();
+    quirky.sort();
+    quirky.dedup();
+    println!(
+        "Quirks used:  {}",
+        if quirky.is_empty() {
+            "none".to_string()
+        } else {
+            quirky.join(", ")
+        }
+    );
+}
+
+fn analyze(path: &Path, symbol_file: Option<&Path>, dot: Option<&Path>) -> io::Result<()> {
+    let rom = read_rom(path);
+    let labels = rom_labels(path, &rom, symbol_file);
+    let analysis = Analysis::new(&rom);
+    println!("{}", path.display());
+    print!("{}", analysis.report(&labels));
+    // What the database says settles it, where there's an entry
+    if let Some(quirks) = load_database(&load_config(None))
+        .lookup(&rom)
+        .and_then(|game| game.quirks())
+    {
+        println!(
+            "  The ROM database runs it with {} ({})",
+            quirks.name(),
+            quirks.enabled().join(", ")
+        );
+    }
+    if let Some(file) = dot {
+        fs::write(file, analysis.dot(&labels))?;
+    }
+    Ok(())
+}
+
+fn main() -> io::Result<()> {
+    let cli = Cli::parse();
+
+    match cli.command {
+        None => run(&cli.run),
+        Some(Command::Run(args)) => run(&args),
+        Some(Command::Disasm { rom, symbols }) => {
+            disassemble(&rom, symbols);
+            Ok(())
+        }
+        Some(Command::Asm {
+            source,
+            output,
+            symbols,
+        }) => assemble(&source, output, symbols),
+        Some(Command::Bench(BenchArgs {
+            path,
+            frames,
+            ipf,
+            decode_cache,
+        })) => bench::run(&path, frames, ipf, decode_cache),
+        Some(Command::Info { rom }) => {
+            info(&rom);
+            Ok(())
+        }
+        Some(Command::Analyze { rom, symbols, dot }) => {
+            analyze(&rom, symbols.as_deref(), dot.as_deref())
+        }
+        Some(Command::Dap { port, config }) => serve_dap(port, config.as_deref()),
+    }
+}
@@ -0,0 +1,39 @@
+use std::io;
+
+use crate::chip8::Chip8;
+use crate::isa::OpCode;
+
+// Watches every instruction a run executes, for the profiler and coverage. Told about each one just
+// before it runs, so `chip8` is the state the instruction sees.
+pub trait Observer {
+    fn instruction(&mut self, chip8: &Chip8, op: OpCode);
+
+    fn vblank(&mut self) {}
+
+    // The run is over; write out whatever was collected
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Several at once, like --profile with --coverage
+impl Observer for Vec<Box<dyn Observer>> {
+    fn instruction(&mut self, chip8: &Chip8, op: OpCode) {
+        for observer in self.iter_mut() {
+            observer.instruction(chip8, op);
+        }
+    }
+
+    fn vblank(&mut self) {
+        for observer in self.iter_mut() {
+            observer.vblank();
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        for observer in self.iter_mut() {
+            observer.finish()?;
+        }
+        Ok(())
+    }
+}
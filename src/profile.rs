@@ -0,0 +1,282 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::mem::{self, Discriminant};
+use std::path::PathBuf;
+
+use crate::chip8::{Chip8, ROM_START};
+use crate::disasm;
+use crate::isa::OpCode;
+use crate::observe::Observer;
+
+// How many addresses the hot spot list shows
+const HOT_SPOTS: usize = 20;
+
+// Counts what a run executes: how often each address and each kind of instruction runs,
+// and where the time goes by subroutine. Every instruction counts as one cycle, since
+// instructions per frame is the budget a game has to fit in.
+pub struct Profiler {
+    rom: Vec<u8>,
+    labels: BTreeMap<u16, String>,
+    // Where finish writes the report and the folded stacks
+    report: Option<PathBuf>,
+    folded: Option<PathBuf>,
+    instructions: u64,
+    frames: u64,
+    by_address: Vec<u64>,
+    // By variant, with the first instruction of each kind to name it by
+    by_op: HashMap<Discriminant<OpCode>, (OpCode, u64)>,
+    calls: BTreeMap<u16, u64>,
+    // Subroutines entered through CALL, outermost first
+    stack: Vec<u16>,
+    // Instructions run with each call stack. They're added up in `pending` until the stack
+    // changes, rather than looking the stack up for every instruction.
+    stacks: HashMap<Vec<u16>, u64>,
+    pending: u64,
+}
+
+impl Profiler {
+    pub fn new(
+        rom: &[u8],
+        labels: BTreeMap<u16, String>,
+        report: Option<PathBuf>,
+        folded: Option<PathBuf>,
+    ) -> Self {
+        Self {
+            rom: rom.to_vec(),
+            labels,
+            report,
+            folded,
+            instructions: 0,
+            frames: 0,
+            by_address: vec![0; 4096],
+            by_op: HashMap::new(),
+            calls: BTreeMap::new(),
+            stack: Vec::new(),
+            stacks: HashMap::new(),
+            pending: 0,
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.pending > 0 {
+            *self.stacks.entry(self.stack.clone()).or_default() += mem::take(&mut self.pending);
+        }
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        100.0 * count as f64 / self.instructions.max(1) as f64
+    }
+
+    fn per_frame(&self, count: u64) -> f64 {
+        count as f64 / self.frames.max(1) as f64
+    }
+
+    fn name(&self, addr: u16) -> String {
+        disasm::symbolize(&self.labels, addr)
+    }
+
+    // What the ROM has at an address, which is what ran there unless the code modified itself
+    fn op_at(&self, addr: u16) -> String {
+        let offset = addr.wrapping_sub(ROM_START) as usize;
+        match self.rom.get(offset..offset + 2) {
+            Some(&[hi, lo]) if addr >= ROM_START => OpCode::decode(u16::from_be_bytes([hi, lo]))
+                .map(|op| disasm::format_op(op, &self.labels))
+                .unwrap_or_default(),
+            _ => String::new(),
+        }
+    }
+
+    // Instructions run in each subroutine, including the ones it calls, and in it alone
+    fn subroutine_cycles(&self) -> BTreeMap<u16, (u64, u64)> {
+        let mut cycles: BTreeMap<u16, (u64, u64)> = BTreeMap::new();
+        for (stack, &count) in &self.stacks {
+            let mut seen: Vec<u16> = stack.clone();
+            seen.sort();
+            seen.dedup();
+            // Counted once per stack, so recursion doesn't count twice
+            for addr in seen {
+                cycles.entry(addr).or_default().0 += count;
+            }
+            if let Some(&top) = stack.last() {
+                cycles.entry(top).or_default().1 += count;
+            }
+        }
+        cycles
+    }
+
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        writeln!(
+            out,
+            "{} instructions in {} frames, {:.1} per frame",
+            self.instructions,
+            self.frames,
+            self.per_frame(self.instructions)
+        )
+        .unwrap();
+
+        writeln!(out, "\nHot spots\n{:>10} {:>7}  address", "count", "%").unwrap();
+        let mut hot: Vec<(u16, u64)> = (0..self.by_address.len() as u16)
+            .map(|addr| (addr, self.by_address[addr as usize]))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for &(addr, count) in hot.iter().take(HOT_SPOTS) {
+            writeln!(
+                out,
+                "{:>10} {:>6.1}%  0x{:03X}  {:<20} {}",
+                count,
+                self.percent(count),
+                addr,
+                self.name(addr),
+                self.op_at(addr)
+            )
+            .unwrap();
+        }
+
+        writeln!(
+            out,
+            "\nInstructions\n{:<16} {:>10} {:>7} {:>10}",
+            "", "count", "%", "per frame"
+        )
+        .unwrap();
+        let mut ops: Vec<&(OpCode, u64)> = self.by_op.values().collect();
+        ops.sort_by_key(|&&(_, count)| std::cmp::Reverse(count));
+        for &(op, count) in ops {
+            let debug = format!("{:?}", op);
+            let variant = debug.split(' ').next().unwrap_or_default();
+            writeln!(
+                out,
+                "{:<16} {:>10} {:>6.1}% {:>10.1}",
+                variant,
+                count,
+                self.percent(count),
+                self.per_frame(count)
+            )
+            .unwrap();
+        }
+
+        writeln!(
+            out,
+            "\nSubroutines\n{:>8} {:>10} {:>7} {:>10} {:>10}  name",
+            "calls", "total", "%", "self", "per call"
+        )
+        .unwrap();
+        let mut subroutines: Vec<(u16, (u64, u64))> =
+            self.subroutine_cycles().into_iter().collect();
+        subroutines.sort_by_key(|&(_, (total, _))| std::cmp::Reverse(total));
+        for (addr, (total, own)) in subroutines {
+            let calls = self.calls.get(&addr).copied().unwrap_or(0);
+            writeln!(
+                out,
+                "{:>8} {:>10} {:>6.1}% {:>10} {:>10.1}  {}",
+                calls,
+                total,
+                self.percent(total),
+                own,
+                total as f64 / calls.max(1) as f64,
+                self.name(addr)
+            )
+            .unwrap();
+        }
+
+        writeln!(out, "\nDisassembly").unwrap();
+        out += &disasm::annotated_listing(&self.rom, &self.labels, |addr| {
+            let count = self.by_address[addr as usize];
+            (count > 0).then(|| format!("{} ({:.1}%)", count, self.percent(count)))
+        });
+        out
+    }
+
+    // One line per call stack with the instructions run in it, the input flamegraph.pl and
+    // inferno expect
+    pub fn folded(&self) -> String {
+        let mut lines: Vec<String> = self
+            .stacks
+            .iter()
+            .map(|(stack, count)| {
+                let mut frames = vec!["main".to_string()];
+                frames.extend(stack.iter().map(|&addr| self.name(addr)));
+                format!("{} {}\n", frames.join(";"), count)
+            })
+            .collect();
+        lines.sort();
+        lines.concat()
+    }
+}
+
+impl Observer for Profiler {
+    fn instruction(&mut self, chip8: &Chip8, op: OpCode) {
+        // Stay in step with the real stack, whatever it's been through
+        if chip8.stack().len() < self.stack.len() {
+            self.flush();
+            self.stack.truncate(chip8.stack().len());
+        }
+        self.instructions += 1;
+        self.by_address[chip8.pc as usize] += 1;
+        self.by_op
+            .entry(mem::discriminant(&op))
+            .or_insert((op, 0))
+            .1 += 1;
+        // The CALL or RET itself counts towards the caller and the subroutine respectively
+        self.pending += 1;
+        match op {
+            OpCode::Call { addr } => {
+                self.flush();
+                self.stack.push(addr);
+                *self.calls.entry(addr).or_default() += 1;
+            }
+            OpCode::Ret => {
+                self.flush();
+                self.stack.pop();
+            }
+            _ => {}
+        }
+    }
+
+    fn vblank(&mut self) {
+        self.frames += 1;
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush();
+        if let Some(path) = &self.report {
+            fs::write(path, self.report())?;
+        }
+        if let Some(path) = &self.folded {
+            fs::write(path, self.folded())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless;
+
+    #[test]
+    fn counts_subroutines_and_folds_stacks() {
+        // 0x200: CALL 0x206  0x202: CALL 0x206  0x204: JP 0x204
+        // 0x206: ADD V0, 1   0x208: RET
+        let rom = [0x22, 0x06, 0x22, 0x06, 0x12, 0x04, 0x70, 0x01, 0x00, 0xEE];
+        let mut chip8 = Chip8::new();
+        chip8.ROM_loader(&rom).unwrap();
+        let mut profiler = Profiler::new(&rom, disasm::labels(&rom), None, None);
+        headless::run_frames_observed(&mut chip8, 1, 10, &mut profiler).unwrap();
+        profiler.flush();
+
+        assert_eq!(profiler.instructions, 10);
+        assert_eq!(profiler.by_address[0x204], 4);
+        assert_eq!(profiler.calls[&0x206], 2);
+        assert_eq!(profiler.subroutine_cycles()[&0x206], (4, 4));
+        assert_eq!(profiler.folded(), "main 6\nmain;sub_206 4\n");
+        assert!(
+            profiler
+                .report()
+                .contains("    0x204  1204  JP label_204           ; 4 (40.0%)")
+        );
+    }
+}
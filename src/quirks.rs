@@ -0,0 +1,88 @@
+// Behaviours that differ between CHIP-8 interpreters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    // COSMAC VIP: DRW waits for the next vertical blank, so at most one sprite per frame
+    pub display_wait: bool,
+    // Wrap sprite pixels that go past the screen edge around to the other side instead of
+    // clipping them. The starting coordinate always wraps.
+    pub wrap_sprites: bool,
+    // OR, AND and XOR reset VF to 0
+    pub vf_reset: bool,
+    // SHR and SHL shift VY into VX instead of shifting VX in place
+    pub shift_vy: bool,
+    // LD [I], Vx and LD Vx, [I] leave I pointing past the last register
+    pub memory_increment: bool,
+    // JP V0, addr jumps to addr + VX where X is the high nibble of addr (SCHIP's BXNN)
+    pub jump_vx: bool,
+}
+
+// The profile the emulator has always behaved like
+const DEFAULT: Quirks = Quirks {
+    display_wait: false,
+    wrap_sprites: false,
+    vf_reset: true,
+    shift_vy: false,
+    memory_increment: true,
+    jump_vx: false,
+};
+
+const VIP: Quirks = Quirks {
+    display_wait: true,
+    wrap_sprites: false,
+    vf_reset: true,
+    shift_vy: true,
+    memory_increment: true,
+    jump_vx: false,
+};
+
+const SCHIP: Quirks = Quirks {
+    display_wait: false,
+    wrap_sprites: false,
+    vf_reset: false,
+    shift_vy: false,
+    memory_increment: false,
+    jump_vx: true,
+};
+
+pub const PROFILES: [(&str, Quirks); 3] = [("default", DEFAULT), ("vip", VIP), ("schip", SCHIP)];
+
+impl Default for Quirks {
+    fn default() -> Self {
+        DEFAULT
+    }
+}
+
+impl Quirks {
+    pub fn profile(name: &str) -> Option<Quirks> {
+        PROFILES.iter().find(|(n, _)| *n == name).map(|(_, q)| *q)
+    }
+
+    pub fn profile_names() -> Vec<&'static str> {
+        PROFILES.iter().map(|(n, _)| *n).collect()
+    }
+
+    // Name of the profile these quirks match, or "custom" if they were tweaked
+    pub fn name(&self) -> &'static str {
+        PROFILES
+            .iter()
+            .find(|(_, q)| q == self)
+            .map(|(n, _)| *n)
+            .unwrap_or("custom")
+    }
+
+    // Names of the quirks that are switched on
+    pub fn enabled(&self) -> Vec<&'static str> {
+        [
+            (self.display_wait, "display wait"),
+            (self.wrap_sprites, "wrap sprites"),
+            (self.vf_reset, "vf reset"),
+            (self.shift_vy, "shift vy"),
+            (self.memory_increment, "memory increment"),
+            (self.jump_vx, "jump vx"),
+        ]
+        .iter()
+        .filter(|(on, _)| *on)
+        .map(|(_, name)| *name)
+        .collect()
+    }
+}
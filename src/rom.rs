@@ -0,0 +1,7 @@
+// Everything from ROM_START to the end of the 4 KiB address space
+pub const MAX_SIZE: usize = 3584;
+
+// SHA-1 of the ROM as lowercase hex. The config file and the ROM database both key games by it.
+pub fn sha1(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crossterm::event::KeyCode;
+use serde::{Deserialize, Deserializer};
+
+use crate::quirks::Quirks;
+use crate::rom;
+use crate::settings::Settings;
+
+// Reads the format of the community CHIP-8 database (github.com/chip-8/chip-8-database):
+// sha1-hashes.json maps ROM hashes to an index into programs.json, and platforms.json has
+// the quirks and speed of each platform a ROM can target. A small database covering the
+// ROMs in roms/ is built in; point `database` in the config file at a checkout of the real
+// one's database/ directory to use that instead.
+pub struct RomDatabase {
+    hashes: HashMap<String, usize>,
+    programs: Vec<Program>,
+    platforms: Vec<Platform>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Program {
+    pub title: String,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    #[serde(default, deserialize_with = "string_or_number")]
+    pub release: Option<String>,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub roms: HashMap<String, RomInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RomInfo {
+    pub file: Option<String>,
+    #[serde(default)]
+    pub platforms: Vec<String>,
+    pub tickrate: Option<u32>,
+    // Per-platform changes to the platform's usual quirks
+    #[serde(default)]
+    pub quirky_platforms: HashMap<String, PlatformQuirks>,
+    // "up", "left", "a" and so on to CHIP-8 keys
+    #[serde(default)]
+    pub keys: HashMap<String, u8>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Platform {
+    pub id: String,
+    pub name: String,
+    pub default_tickrate: Option<u32>,
+    #[serde(default)]
+    pub quirks: PlatformQuirks,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlatformQuirks {
+    pub shift: Option<bool>,
+    pub memory_increment_by_x: Option<bool>,
+    pub memory_leave_i_unchanged: Option<bool>,
+    pub wrap: Option<bool>,
+    pub jump: Option<bool>,
+    pub vblank: Option<bool>,
+    pub logic: Option<bool>,
+}
+
+// What the database knows about one ROM
+pub struct Match<'a> {
+    pub hash: String,
+    pub program: &'a Program,
+    pub rom: &'a RomInfo,
+    // The first platform the ROM lists that the database describes
+    pub platform: Option<&'a Platform>,
+}
+
+impl RomDatabase {
+    pub fn bundled() -> &'static RomDatabase {
+        static BUNDLED: OnceLock<RomDatabase> = OnceLock::new();
+        BUNDLED.get_or_init(|| {
+            RomDatabase::parse(
+                include_str!("../data/chip8-database/sha1-hashes.json"),
+                include_str!("../data/chip8-database/programs.json"),
+                include_str!("../data/chip8-database/platforms.json"),
+            )
+            .expect("bundled ROM database is valid")
+        })
+    }
+
+    // Loads the three JSON files from a directory
+    pub fn load(dir: &Path) -> Result<RomDatabase, String> {
+        let read = |name: &str| {
+            let path = dir.join(name);
+            fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))
+        };
+        RomDatabase::parse(
+            &read("sha1-hashes.json")?,
+            &read("programs.json")?,
+            &read("platforms.json")?,
+        )
+        .map_err(|e| format!("in ROM database {}: {}", dir.display(), e))
+    }
+
+    pub fn parse(hashes: &str, programs: &str, platforms: &str) -> Result<RomDatabase, String> {
+        let hashes: HashMap<String, usize> =
+            serde_json::from_str(hashes).map_err(|e| format!("sha1-hashes.json: {}", e))?;
+        let programs: Vec<Program> =
+            serde_json::from_str(programs).map_err(|e| format!("programs.json: {}", e))?;
+        let platforms: Vec<Platform> =
+            serde_json::from_str(platforms).map_err(|e| format!("platforms.json: {}", e))?;
+        if let Some((hash, _)) = hashes.iter().find(|&(_, &i)| i >= programs.len()) {
+            return Err(format!(
+                "sha1-hashes.json: {} points past the end of programs.json",
+                hash
+            ));
+        }
+        Ok(RomDatabase {
+            hashes,
+            programs,
+            platforms,
+        })
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<Match<'_>> {
+        let hash = rom::sha1(rom);
+        let program = &self.programs[*self.hashes.get(&hash)?];
+        let info = program.roms.get(&hash)?;
+        let platform = info
+            .platforms
+            .iter()
+            .find_map(|id| self.platforms.iter().find(|p| &p.id == id));
+        Some(Match {
+            hash,
+            program,
+            rom: info,
+            platform,
+        })
+    }
+}
+
+impl Match<'_> {
+    pub fn ipf(&self) -> Option<u32> {
+        self.rom
+            .tickrate
+            .or(self.platform?.default_tickrate)
+            .filter(|&ipf| ipf > 0)
+    }
+
+    // The platform's quirks with any changes the ROM asks for on top
+    pub fn quirks(&self) -> Option<Quirks> {
+        let platform = self.platform?;
+        let mut quirks = Quirks::default();
+        // The database files most games under "Modern CHIP-8", meaning whatever interpreters
+        // do today. That's our default profile, rather than a mix of quirks of its own that
+        // would quietly change how those games have always run here.
+        if platform.id != "modernChip8" {
+            platform.quirks.apply(&mut quirks);
+        }
+        if let Some(changes) = self.rom.quirky_platforms.get(&platform.id) {
+            changes.apply(&mut quirks);
+        }
+        Some(quirks)
+    }
+
+    // Arrow keys, space and enter for the first player's controls
+    pub fn key_bindings(&self) -> Vec<(KeyCode, u8)> {
+        let buttons = [
+            ("up", KeyCode::Up),
+            ("down", KeyCode::Down),
+            ("left", KeyCode::Left),
+            ("right", KeyCode::Right),
+            ("a", KeyCode::Char(' ')),
+            ("b", KeyCode::Enter),
+        ];
+        buttons
+            .iter()
+            .filter_map(|(name, code)| Some((*code, *self.rom.keys.get(*name)?)))
+            .collect()
+    }
+
+    pub fn apply(&self, settings: &mut Settings) {
+        settings.title = Some(self.program.title.clone());
+        if let Some(ipf) = self.ipf() {
+            settings.ipf = ipf;
+        }
+        if let Some(quirks) = self.quirks() {
+            settings.quirks = quirks;
+        }
+        for (code, key) in self.key_bindings() {
+            settings.keymap.bind(code, key);
+        }
+    }
+}
+
+impl PlatformQuirks {
+    fn apply(&self, quirks: &mut Quirks) {
+        if let Some(shift) = self.shift {
+            quirks.shift_vy = !shift;
+        }
+        // We only model the VIP's I += X + 1 and leaving I alone, so "by X" counts as the latter
+        let by_x = self.memory_increment_by_x;
+        let unchanged = self.memory_leave_i_unchanged;
+        if by_x.is_some() || unchanged.is_some() {
+            quirks.memory_increment = !(by_x.unwrap_or(false) || unchanged.unwrap_or(false));
+        }
+        if let Some(wrap) = self.wrap {
+            quirks.wrap_sprites = wrap;
+        }
+        if let Some(jump) = self.jump {
+            quirks.jump_vx = jump;
+        }
+        if let Some(vblank) = self.vblank {
+            quirks.display_wait = vblank;
+        }
+        if let Some(logic) = self.logic {
+            quirks.vf_reset = logic;
+        }
+    }
+}
+
+// Release years turn up both as "1990" and 1990
+fn string_or_number<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Release {
+        Text(String),
+        Number(u64),
+    }
+    Ok(
+        Option::<Release>::deserialize(deserializer)?.map(|release| match release {
+            Release::Text(text) => text,
+            Release::Number(n) => n.to_string(),
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looks_up_roms_by_hash() {
+        let rom = [0x12, 0x00];
+        let database = RomDatabase::parse(
+            &format!(r#"{{ "{}": 0 }}"#, rom::sha1(&rom)),
+            &format!(
+                r#"[{{ "title": "Loop", "release": 1978, "roms": {{ "{}": {{ "platforms": ["vip"] }} }} }}]"#,
+                rom::sha1(&rom)
+            ),
+            r#"[{ "id": "vip", "name": "COSMAC VIP", "defaultTickrate": 15 }]"#,
+        )
+        .unwrap();
+        let game = database.lookup(&rom).unwrap();
+        assert_eq!(game.program.title, "Loop");
+        assert_eq!(game.program.release.as_deref(), Some("1978"));
+        assert_eq!(game.ipf(), Some(15));
+        assert!(database.lookup(&[0x00, 0xE0]).is_none());
+
+        // The bundled one covers roms/
+        let breakout = RomDatabase::bundled()
+            .lookup(include_bytes!("../roms/BREAKOUT"))
+            .unwrap();
+        assert_eq!(breakout.quirks().unwrap().name(), "vip");
+    }
+
+    #[test]
+    fn modern_chip8_games_keep_the_default_quirks() {
+        let database = RomDatabase::bundled();
+        let pong = database.lookup(include_bytes!("../roms/PONG")).unwrap();
+        assert_eq!(pong.platform.unwrap().id, "modernChip8");
+        assert_eq!(pong.quirks(), Some(Quirks::default()));
+        let blitz = database.lookup(include_bytes!("../roms/BLITZ")).unwrap();
+        assert_eq!(blitz.quirks().unwrap().name(), "default");
+    }
+
+    #[test]
+    fn maps_platform_quirks_to_profiles() {
+        let quirks = |platform: PlatformQuirks| {
+            let mut quirks = Quirks::default();
+            platform.apply(&mut quirks);
+            quirks.name()
+        };
+        assert_eq!(quirks(PlatformQuirks::default()), "default");
+        let vip = PlatformQuirks {
+            shift: Some(false),
+            memory_increment_by_x: Some(false),
+            memory_leave_i_unchanged: Some(false),
+            wrap: Some(false),
+            jump: Some(false),
+            vblank: Some(true),
+            logic: Some(true),
+        };
+        assert_eq!(quirks(vip), "vip");
+        let schip = PlatformQuirks {
+            shift: Some(true),
+            memory_increment_by_x: Some(false),
+            memory_leave_i_unchanged: Some(true),
+            wrap: Some(false),
+            jump: Some(true),
+            vblank: Some(false),
+            logic: Some(false),
+        };
+        assert_eq!(quirks(schip), "schip");
+    }
+}
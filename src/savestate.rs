@@ -0,0 +1,147 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::chip8::Chip8;
+use crate::rom;
+
+pub const SLOTS: u8 = 9;
+
+// Numbered save slots for one ROM, stored as <save_state_dir>/<rom sha1>-<slot>.state
+pub struct SaveSlots {
+    dir: PathBuf,
+    hash: String,
+    slot: u8,
+    // Whether the current slot has a save, so drawing the status bar doesn't hit the disk
+    occupied: bool,
+}
+
+impl SaveSlots {
+    pub fn new(dir: PathBuf, rom: &[u8]) -> Self {
+        let mut slots = Self {
+            dir,
+            hash: rom::sha1(rom),
+            slot: 1,
+            occupied: false,
+        };
+        slots.occupied = slots.path().exists();
+        slots
+    }
+
+    pub fn slot(&self) -> u8 {
+        self.slot
+    }
+
+    pub fn occupied(&self) -> bool {
+        self.occupied
+    }
+
+    // 1 -> 2 -> ... -> 9 -> 1
+    pub fn next_slot(&mut self) {
+        self.slot = self.slot % SLOTS + 1;
+        self.occupied = self.path().exists();
+    }
+
+    pub fn save(&mut self, chip8: &Chip8) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.path(), chip8.save_state())?;
+        self.occupied = true;
+        Ok(())
+    }
+
+    pub fn load(&self, chip8: &mut Chip8) -> io::Result<()> {
+        let state = fs::read(self.path())?;
+        chip8
+            .load_state(&state)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn path(&self) -> PathBuf {
+        self.dir.join(format!("{}-{}.state", self.hash, self.slot))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: &[u8] = &[0x60, 0x2A, 0x12, 0x02];
+
+    // A directory of its own for each test, since they run in parallel
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn running() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.ROM_loader(ROM).unwrap();
+        chip8.step().unwrap();
+        chip8
+    }
+
+    #[test]
+    fn saves_and_loads_slots() {
+        let dir = dir("savestate-slots");
+        let mut slots = SaveSlots::new(dir.clone(), ROM);
+        assert!(!slots.occupied());
+        slots.save(&running()).unwrap();
+        assert!(slots.occupied());
+
+        let mut chip8 = Chip8::new();
+        slots.load(&mut chip8).unwrap();
+        assert_eq!((chip8.registers[0], chip8.pc), (0x2A, 0x202));
+        assert_eq!(chip8.save_state(), running().save_state());
+
+        slots.next_slot();
+        assert_eq!(slots.slot(), 2);
+        assert!(!slots.occupied());
+        let error = slots.load(&mut chip8).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        for _ in 0..8 {
+            slots.next_slot();
+        }
+        assert_eq!(slots.slot(), 1);
+        assert!(slots.occupied());
+        // Picked up by a new session too
+        assert!(SaveSlots::new(dir.clone(), ROM).occupied());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn saves_belong_to_one_rom() {
+        let dir = dir("savestate-roms");
+        SaveSlots::new(dir.clone(), ROM).save(&running()).unwrap();
+        let other = SaveSlots::new(dir.clone(), &[0x00, 0xE0]);
+        assert!(!other.occupied());
+        let error = other.load(&mut Chip8::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_corrupt_and_foreign_files() {
+        let dir = dir("savestate-corrupt");
+        let mut slots = SaveSlots::new(dir.clone(), ROM);
+        slots.save(&running()).unwrap();
+        let path = slots.path();
+        let state = fs::read(&path).unwrap();
+
+        let mut truncated = state.clone();
+        truncated.pop();
+        let mut other_version = state.clone();
+        other_version[3] = 0x02;
+        for bad in [truncated, other_version, b"not a save state".to_vec()] {
+            fs::write(&path, bad).unwrap();
+            let mut chip8 = Chip8::new();
+            chip8.registers[5] = 7;
+            let error = slots.load(&mut chip8).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(error.to_string(), "Not a save state from this version");
+            // Left alone
+            assert_eq!((chip8.registers[5], chip8.pc), (7, 0x200));
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,294 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const FRAME_DURATION: Duration = Duration::from_micros(16_667);
+pub const DEFAULT_IPF: u32 = 12;
+
+// Slow motion steps F8 cycles through
+const SLOW_MOTION: [f32; 3] = [1.0, 0.5, 0.25];
+
+// How fast emulated time runs compared to real time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Speed {
+    Multiplier(f32),
+    // As many frames as the machine can manage
+    Uncapped,
+}
+
+impl Speed {
+    pub fn from_name(name: &str) -> Option<Speed> {
+        if name == "uncapped" {
+            return Some(Speed::Uncapped);
+        }
+        let n: f32 = name.trim_end_matches(['x', '×']).parse().ok()?;
+        (n > 0.0 && n.is_finite()).then_some(Speed::Multiplier(n))
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            Speed::Multiplier(n) => format!("{}×", n),
+            Speed::Uncapped => "uncapped".to_string(),
+        }
+    }
+}
+
+// Paces emulation to 60 Hz frames, or faster or slower than that, and handles pausing
+pub struct FrameScheduler {
+    next_frame: Instant,
+    speed: Speed,
+    // What fast-forward switches to
+    fast_forward: Speed,
+    paused: bool,
+    // A single frame was requested while paused
+    advance: bool,
+    // Emulated frames per second of real time, measured over the last second
+    fps: f32,
+    frames_counted: u32,
+    counting_since: Instant,
+}
+
+impl Default for FrameScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameScheduler {
+    pub fn new() -> Self {
+        Self {
+            next_frame: Instant::now() + FRAME_DURATION,
+            speed: Speed::Multiplier(1.0),
+            fast_forward: Speed::Uncapped,
+            paused: false,
+            advance: false,
+            fps: 0.0,
+            frames_counted: 0,
+            counting_since: Instant::now(),
+        }
+    }
+
+    pub fn with_fast_forward(mut self, speed: Speed) -> Self {
+        self.fast_forward = speed;
+        self
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    // Pauses if running, then lets exactly one frame through
+    pub fn advance_frame(&mut self) {
+        self.paused = true;
+        self.advance = true;
+    }
+
+    pub fn toggle_fast_forward(&mut self) {
+        self.speed = if self.speed == self.fast_forward {
+            Speed::Multiplier(1.0)
+        } else {
+            self.fast_forward
+        };
+    }
+
+    // 1x -> 0.5x -> 0.25x -> 1x. Leaves fast-forward if it was on.
+    pub fn cycle_slow_motion(&mut self) {
+        let next = SLOW_MOTION
+            .iter()
+            .position(|&n| self.speed == Speed::Multiplier(n))
+            .map_or(1, |i| (i + 1) % SLOW_MOTION.len());
+        self.speed = Speed::Multiplier(SLOW_MOTION[next]);
+    }
+
+    // Whether the frame loop should emulate a frame this time round
+    pub fn frame_due(&mut self) -> bool {
+        let due = !self.paused || std::mem::take(&mut self.advance);
+        self.frames_counted += due as u32;
+        due
+    }
+
+    // Refreshes the measured frame rate once a second. Returns true when it changed.
+    pub fn update_fps(&mut self) -> bool {
+        let elapsed = self.counting_since.elapsed();
+        if elapsed < Duration::from_secs(1) {
+            return false;
+        }
+        self.fps = self.frames_counted as f32 / elapsed.as_secs_f32();
+        self.frames_counted = 0;
+        self.counting_since = Instant::now();
+        true
+    }
+
+    pub fn fps(&self) -> f32 {
+        self.fps
+    }
+
+    // The frame rate we're aiming for, or None when uncapped
+    pub fn target_fps(&self) -> Option<f32> {
+        let fps = FRAME_DURATION.as_secs_f32().recip();
+        match self.speed {
+            _ if self.paused => Some(0.0),
+            Speed::Multiplier(n) => Some(fps * n),
+            Speed::Uncapped => None,
+        }
+    }
+
+    // Sleeps until the next frame boundary. While paused the loop still ticks at 60 Hz so
+    // input keeps being handled.
+    pub fn wait_for_next_frame(&mut self) {
+        let duration = match self.speed {
+            _ if self.paused => FRAME_DURATION,
+            Speed::Multiplier(n) => FRAME_DURATION.div_f32(n),
+            Speed::Uncapped => Duration::ZERO,
+        };
+        let now = Instant::now();
+        if now < self.next_frame {
+            thread::sleep(self.next_frame - now);
+            self.next_frame += duration;
+        } else {
+            // We fell behind (slow terminal, suspended process). Don't try to catch up.
+            self.next_frame = now + duration;
+        }
+    }
+}
+
+// Caps how often the screen is drawn, independently of how fast frames are emulated. The
+// terminal can't show more than this anyway, and drawing is far slower than executing.
+pub struct RenderThrottle {
+    last_draw: Instant,
+    pending: bool,
+}
+
+impl Default for RenderThrottle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RenderThrottle {
+    pub fn new() -> Self {
+        Self {
+            last_draw: Instant::now() - FRAME_DURATION,
+            pending: true,
+        }
+    }
+
+    // Something changed on screen
+    pub fn request(&mut self) {
+        self.pending = true;
+    }
+
+    // True if there's something to draw and the last draw was long enough ago. The caller is
+    // expected to draw when this returns true.
+    pub fn due(&mut self) -> bool {
+        if !self.pending || self.last_draw.elapsed() < FRAME_DURATION {
+            return false;
+        }
+        self.pending = false;
+        self.last_draw = Instant::now();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pausing_lets_single_frames_through() {
+        let mut scheduler = FrameScheduler::new();
+        assert!(scheduler.frame_due());
+        scheduler.toggle_pause();
+        assert!(!scheduler.frame_due());
+        assert_eq!(scheduler.target_fps(), Some(0.0));
+
+        scheduler.advance_frame();
+        assert!(scheduler.frame_due());
+        assert!(!scheduler.frame_due());
+        scheduler.toggle_pause();
+        assert!(scheduler.frame_due());
+
+        // Advancing while running pauses first
+        scheduler.advance_frame();
+        assert!(scheduler.is_paused());
+        assert!(scheduler.frame_due());
+        assert!(!scheduler.frame_due());
+    }
+
+    #[test]
+    fn slow_motion_cycles_and_leaves_fast_forward() {
+        let mut scheduler = FrameScheduler::new().with_fast_forward(Speed::Multiplier(4.0));
+        let speeds: Vec<Speed> = (0..4)
+            .map(|_| {
+                scheduler.cycle_slow_motion();
+                scheduler.speed()
+            })
+            .collect();
+        assert_eq!(
+            speeds,
+            [0.5, 0.25, 1.0, 0.5].map(Speed::Multiplier).to_vec()
+        );
+
+        scheduler.toggle_fast_forward();
+        assert_eq!(scheduler.speed(), Speed::Multiplier(4.0));
+        scheduler.cycle_slow_motion();
+        assert_eq!(scheduler.speed(), Speed::Multiplier(0.5));
+        scheduler.toggle_fast_forward();
+        scheduler.toggle_fast_forward();
+        assert_eq!(scheduler.speed(), Speed::Multiplier(1.0));
+    }
+
+    #[test]
+    fn frames_are_spaced_by_the_speed() {
+        let mut scheduler = FrameScheduler::new().with_fast_forward(Speed::Multiplier(2.0));
+        // Rounded to whole microseconds, since speeds are floats
+        let spacing = |scheduler: &mut FrameScheduler| {
+            let due = Instant::now() + Duration::from_millis(1);
+            scheduler.next_frame = due;
+            scheduler.wait_for_next_frame();
+            (scheduler.next_frame - due).as_secs_f64() * 1e6
+        };
+        let frame = FRAME_DURATION.as_secs_f64() * 1e6;
+        assert_eq!(spacing(&mut scheduler).round(), frame.round());
+        scheduler.toggle_fast_forward();
+        assert_eq!(spacing(&mut scheduler).round(), (frame / 2.0).round());
+        assert_eq!(
+            scheduler.target_fps(),
+            Some(2.0 / FRAME_DURATION.as_secs_f32())
+        );
+
+        scheduler.cycle_slow_motion();
+        scheduler.cycle_slow_motion();
+        assert_eq!(spacing(&mut scheduler).round(), (frame * 4.0).round());
+
+        // Paused frames still tick at 60 Hz to keep handling input
+        scheduler.toggle_pause();
+        assert_eq!(spacing(&mut scheduler).round(), frame.round());
+
+        let mut scheduler = FrameScheduler::new();
+        scheduler.toggle_fast_forward();
+        assert_eq!(scheduler.target_fps(), None);
+        assert_eq!(spacing(&mut scheduler), 0.0);
+    }
+
+    #[test]
+    fn draws_at_most_once_a_frame_and_only_when_asked() {
+        let mut render = RenderThrottle::new();
+        assert!(render.due());
+        render.request();
+        assert!(!render.due());
+
+        // The request waits for the frame to be over rather than getting lost
+        render.last_draw -= FRAME_DURATION;
+        assert!(render.due());
+        render.last_draw -= FRAME_DURATION;
+        assert!(!render.due());
+    }
+}
@@ -0,0 +1,129 @@
+use std::path::PathBuf;
+
+use crate::chip8::Chip8;
+use crate::config::Config;
+use crate::flicker::FlickerMode;
+use crate::keymap::Keymap;
+use crate::quirks::Quirks;
+use crate::romdb::RomDatabase;
+use crate::scheduler::{DEFAULT_IPF, Speed};
+use crate::theme::{ColorMode, THEMES, Theme};
+
+// Everything that configures a session
+#[derive(Debug, Clone)]
+pub struct Settings {
+    // The game's name from the ROM database, or else the file name
+    pub title: Option<String>,
+    pub debug: bool,
+    pub ipf: u32,
+    pub quirks: Quirks,
+    pub seed: Option<u64>,
+    pub theme: Theme,
+    pub color_mode: ColorMode,
+    pub flicker: FlickerMode,
+    pub keymap: Keymap,
+    // Terminal rows per CHIP-8 pixel; pixels are always twice as wide
+    pub scale: u16,
+    pub decode_cache: bool,
+    // What F9 switches to
+    pub fast_forward: Speed,
+    // Ring the terminal bell when the sound timer starts
+    pub audio: bool,
+    pub save_state_dir: PathBuf,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            title: None,
+            debug: false,
+            ipf: DEFAULT_IPF,
+            quirks: Quirks::default(),
+            seed: None,
+            theme: THEMES[0],
+            color_mode: ColorMode::detect(),
+            flicker: FlickerMode::Off,
+            keymap: Keymap::default(),
+            scale: 1,
+            decode_cache: false,
+            fast_forward: Speed::Uncapped,
+            audio: true,
+            save_state_dir: default_save_state_dir(),
+        }
+    }
+}
+
+impl Settings {
+    // Settings for a ROM before any command line flags: the defaults, then the config
+    // file's top level, then whatever the ROM database knows about the game, then the
+    // game's own section of the config file
+    pub fn for_rom(rom: &[u8], database: &RomDatabase, config: &Config) -> Settings {
+        let mut settings = Settings::default();
+        config.apply(&mut settings);
+        if let Some(game) = database.lookup(rom) {
+            game.apply(&mut settings);
+        }
+        config.apply_rom(&mut settings, rom);
+        settings
+    }
+
+    // A core with the ROM loaded and these settings applied
+    pub fn build_chip8(&self, rom: &[u8]) -> Result<Chip8, &'static str> {
+        let mut chip8 = Chip8::new();
+        chip8.quirks = self.quirks;
+        chip8.set_decode_cache(self.decode_cache);
+        if let Some(seed) = self.seed {
+            chip8.seed_rng(seed);
+        }
+        chip8.ROM_loader(rom)?;
+        Ok(chip8)
+    }
+}
+
+// $XDG_DATA_HOME/chip8-emulator/states, or ./states if there's no home directory
+fn default_save_state_dir() -> PathBuf {
+    dirs::data_dir()
+        .map(|dir| dir.join("chip8-emulator"))
+        .unwrap_or_default()
+        .join("states")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom;
+
+    #[test]
+    fn database_goes_between_config_and_rom_section() {
+        let rom = [0x12, 0x00];
+        let hash = rom::sha1(&rom);
+        let database = RomDatabase::parse(
+            &format!(r#"{{ "{}": 0 }}"#, hash),
+            &format!(
+                r#"[{{ "title": "Loop", "roms": {{ "{}": {{ "platforms": ["vip"], "tickrate": 20 }} }} }}]"#,
+                hash
+            ),
+            r#"[{ "id": "vip", "name": "COSMAC VIP", "quirks": { "shift": false, "vblank": true } }]"#,
+        )
+        .unwrap();
+        let config = |rom_section: &str| {
+            Config::parse(&format!(
+                "ipf = 15\nquirks = \"schip\"\ntheme = \"amber\"\n[rom.{}]\n{}",
+                hash, rom_section
+            ))
+            .unwrap()
+        };
+
+        // The database beats the top level, and leaves alone what it doesn't know about
+        let settings = Settings::for_rom(&rom, &database, &config(""));
+        assert_eq!((settings.ipf, settings.quirks.name()), (20, "vip"));
+        assert_eq!(settings.theme.name, "amber");
+
+        let settings = Settings::for_rom(&rom, &database, &config("ipf = 30\nquirks = \"schip\""));
+        assert_eq!((settings.ipf, settings.quirks.name()), (30, "schip"));
+
+        // Unknown ROMs just get the top level
+        let settings = Settings::for_rom(&[0x00, 0xE0], &database, &config(""));
+        assert_eq!((settings.ipf, settings.quirks.name()), (15, "schip"));
+    }
+}
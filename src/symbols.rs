@@ -0,0 +1,143 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+// Symbol files name addresses in a ROM, so disassembly and the debuggers can say
+// `CALL draw_paddle` instead of `CALL 0x2A4`. One symbol per line, in either order:
+//
+//   0x2A4 draw_paddle     what `asm --symbols` writes; bare addresses are hex too
+//   draw_paddle 0x2A4     label lists, also `draw_paddle = 0x2A4` and `: draw_paddle 0x2A4`
+//
+// A JSON object of names to addresses works as well. # and ; start comments.
+
+// A ROM's symbols are picked up automatically from a file next to it, like PONG.sym
+pub fn sidecar(rom: &Path) -> Option<PathBuf> {
+    let path = rom.with_extension("sym");
+    path.is_file().then_some(path)
+}
+
+pub fn load(path: &Path) -> Result<BTreeMap<u16, String>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse(&text).map_err(|e| format!("in symbol file {}: {}", path.display(), e))
+}
+
+// When several names share an address the first one wins
+pub fn parse(text: &str) -> Result<BTreeMap<u16, String>, String> {
+    if text.trim_start().starts_with('{') {
+        return parse_json(text);
+    }
+    let mut symbols = BTreeMap::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split(['#', ';']).next().unwrap();
+        let tokens: Vec<&str> = line
+            .split_whitespace()
+            .map(|token| token.trim_matches(':'))
+            .filter(|token| !token.is_empty() && *token != "=")
+            .collect();
+        let (addr, name) = match tokens[..] {
+            [] => continue,
+            // A prefixed number is the address even when the name happens to be hex too
+            [first, second] if has_prefix(second) && !has_prefix(first) => (second, first),
+            [first, second] if parse_address(first).is_some() => (first, second),
+            [first, second] => (second, first),
+            _ => return Err(format!("line {}: expected an address and a name", n + 1)),
+        };
+        let addr = parse_address(addr).ok_or_else(|| format!("line {}: no address", n + 1))?;
+        symbols.entry(addr).or_insert_with(|| name.to_string());
+    }
+    Ok(symbols)
+}
+
+fn parse_json(text: &str) -> Result<BTreeMap<u16, String>, String> {
+    let object: HashMap<String, Value> = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    let mut symbols = BTreeMap::new();
+    for (name, value) in object {
+        let addr = match &value {
+            Value::Number(n) => n.as_u64().and_then(|n| u16::try_from(n).ok()),
+            Value::String(s) => parse_address(s),
+            _ => None,
+        }
+        .filter(|&addr| addr < 0x1000)
+        .ok_or_else(|| format!("{} isn't an address", name))?;
+        symbols.entry(addr).or_insert(name);
+    }
+    Ok(symbols)
+}
+
+fn has_prefix(s: &str) -> bool {
+    s.starts_with("0x") || s.starts_with("0X") || s.starts_with('$')
+}
+
+// Hex, with or without 0x or $, inside the 4K address space
+fn parse_address(s: &str) -> Option<u16> {
+    let hex = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .or_else(|| s.strip_prefix('$'))
+        .unwrap_or(s);
+    u16::from_str_radix(hex, 16)
+        .ok()
+        .filter(|&addr| addr < 0x1000)
+}
+
+// The other way round, for the assembler's labels
+pub fn to_text(labels: &HashMap<String, u16>) -> String {
+    let mut sorted: Vec<(&u16, &String)> = labels.iter().map(|(name, addr)| (addr, name)).collect();
+    sorted.sort();
+    let mut out = String::new();
+    for (addr, name) in sorted {
+        writeln!(out, "0x{:03X} {}", addr, name).unwrap();
+    }
+    out
+}
+
+// The address of a name, for breakpoints by symbol
+pub fn address_of(symbols: &BTreeMap<u16, String>, name: &str) -> Option<u16> {
+    symbols
+        .iter()
+        .find(|(_, symbol)| symbol.as_str() == name)
+        .map(|(&addr, _)| addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_either_order() {
+        let symbols = parse(
+            "# made by hand\n\
+             0x2A4 draw_paddle\n\
+             2B0 score\n\
+             : main 0x200\n\
+             ball_x = $300 ; data\n\
+             add 0x310\n\
+             \n\
+             0x200 start\n",
+        )
+        .unwrap();
+        assert_eq!(symbols[&0x2A4], "draw_paddle");
+        assert_eq!(symbols[&0x2B0], "score");
+        assert_eq!(symbols[&0x200], "main");
+        assert_eq!(symbols[&0x300], "ball_x");
+        assert_eq!(symbols[&0x310], "add");
+        assert_eq!(address_of(&symbols, "score"), Some(0x2B0));
+
+        assert!(parse("draw_paddle\n").is_err());
+        assert!(parse("draw paddle\n").is_err());
+    }
+
+    #[test]
+    fn parses_json_and_round_trips() {
+        let symbols = parse(r#"{ "draw_paddle": 676, "score": "0x2B0" }"#).unwrap();
+        assert_eq!(symbols[&0x2A4], "draw_paddle");
+        assert_eq!(symbols[&0x2B0], "score");
+
+        let labels = HashMap::from([("loop".to_string(), 0x202), ("start".to_string(), 0x200)]);
+        assert_eq!(to_text(&labels), "0x200 start\n0x202 loop\n");
+        assert_eq!(parse(&to_text(&labels)).unwrap()[&0x202], "loop");
+    }
+}
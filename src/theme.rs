@@ -0,0 +1,259 @@
+use ratatui::style::Color;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    TrueColor,
+    Ansi256,
+}
+
+impl ColorMode {
+    // Terminals that support 24-bit colour advertise it through COLORTERM
+    pub fn detect() -> Self {
+        match std::env::var("COLORTERM") {
+            Ok(v) if v == "truecolor" || v == "24bit" => ColorMode::TrueColor,
+            _ => ColorMode::Ansi256,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "truecolor" | "24bit" => Some(ColorMode::TrueColor),
+            "256" => Some(ColorMode::Ansi256),
+            _ => None,
+        }
+    }
+}
+
+pub type Rgb = (u8, u8, u8);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Theme {
+    pub name: &'static str,
+    // Indexed by XO-CHIP plane bits: 0 = background, 1 = plane 1, 2 = plane 2, 3 = both.
+    // None leaves the cell in the terminal's default colour.
+    pub planes: [Option<Rgb>; 4],
+    pub off_glyph: char,
+}
+
+pub const THEMES: [Theme; 4] = [
+    Theme {
+        name: "default",
+        planes: [None, None, None, None],
+        off_glyph: '░',
+    },
+    Theme {
+        name: "green",
+        planes: [
+            Some((0x0A, 0x1A, 0x0A)),
+            Some((0x33, 0xFF, 0x33)),
+            Some((0x1A, 0x8F, 0x1A)),
+            Some((0xAA, 0xFF, 0xAA)),
+        ],
+        off_glyph: '█',
+    },
+    Theme {
+        name: "amber",
+        planes: [
+            Some((0x1A, 0x10, 0x00)),
+            Some((0xFF, 0xB0, 0x00)),
+            Some((0x99, 0x6A, 0x00)),
+            Some((0xFF, 0xD0, 0x80)),
+        ],
+        off_glyph: '█',
+    },
+    // Octo's default palette: background, fill, fill2, blend
+    Theme {
+        name: "octo",
+        planes: [
+            Some((0x99, 0x66, 0x00)),
+            Some((0xFF, 0xCC, 0x00)),
+            Some((0xFF, 0x66, 0x00)),
+            Some((0x66, 0x22, 0x00)),
+        ],
+        off_glyph: '█',
+    },
+];
+
+impl Theme {
+    pub fn by_name(name: &str) -> Option<Theme> {
+        THEMES.iter().find(|t| t.name == name).copied()
+    }
+
+    pub fn names() -> Vec<&'static str> {
+        THEMES.iter().map(|t| t.name).collect()
+    }
+
+    // Cycles through the built-in themes. Custom palettes go back to the first one.
+    pub fn next(&self) -> Theme {
+        let idx = THEMES.iter().position(|t| t.name == self.name);
+        match idx {
+            Some(i) => THEMES[(i + 1) % THEMES.len()],
+            None => THEMES[0],
+        }
+    }
+
+    pub fn with_fg(mut self, fg: Rgb) -> Theme {
+        self.name = "custom";
+        self.planes[1] = Some(fg);
+        self.off_glyph = '█';
+        self
+    }
+
+    pub fn with_bg(mut self, bg: Rgb) -> Theme {
+        self.name = "custom";
+        self.planes[0] = Some(bg);
+        self.off_glyph = '█';
+        self
+    }
+
+    // Replaces the palette from the background up, so two colours set the background and
+    // plane 1 and four set all of them
+    pub fn with_palette(mut self, colors: &[Rgb]) -> Theme {
+        self.name = "custom";
+        for (plane, &rgb) in self.planes.iter_mut().zip(colors) {
+            *plane = Some(rgb);
+        }
+        self.off_glyph = '█';
+        self
+    }
+
+    pub fn color(&self, plane: usize, mode: ColorMode) -> Color {
+        match self.planes[plane & 3] {
+            None => Color::Reset,
+            Some((r, g, b)) => match mode {
+                ColorMode::TrueColor => Color::Rgb(r, g, b),
+                ColorMode::Ansi256 => Color::Indexed(rgb_to_ansi256(r, g, b)),
+            },
+        }
+    }
+
+    // Glyph and colour for a pixel faded to `level` out of `max`. Themes that use the
+    // terminal's default colours can't blend, so they fall back to shading glyphs.
+    pub fn shade(&self, level: u8, max: u8, mode: ColorMode) -> (char, Color) {
+        if level == 0 {
+            return (self.off_glyph, self.color(0, mode));
+        }
+        if level >= max {
+            return ('█', self.color(1, mode));
+        }
+        match (self.planes[0], self.planes[1]) {
+            (Some(bg), Some(fg)) => {
+                let lerp = |a: u8, b: u8| -> u8 {
+                    (a as i32 + (b as i32 - a as i32) * level as i32 / max as i32) as u8
+                };
+                let (r, g, b) = (lerp(bg.0, fg.0), lerp(bg.1, fg.1), lerp(bg.2, fg.2));
+                let color = match mode {
+                    ColorMode::TrueColor => Color::Rgb(r, g, b),
+                    ColorMode::Ansi256 => Color::Indexed(rgb_to_ansi256(r, g, b)),
+                };
+                ('█', color)
+            }
+            _ => {
+                const SHADES: [char; 2] = ['▒', '▓'];
+                let i = (level as usize * SHADES.len() / max as usize).min(SHADES.len() - 1);
+                (SHADES[i], self.color(1, mode))
+            }
+        }
+    }
+}
+
+// Parses "#RRGGBB" or "RRGGBB"
+pub fn parse_hex_color(s: &str) -> Option<Rgb> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if hex.len() != 6 {
+        return None;
+    }
+    let v = u32::from_str_radix(hex, 16).ok()?;
+    Some(((v >> 16) as u8, (v >> 8) as u8, v as u8))
+}
+
+// Maps to the closest entry of the xterm 6x6x6 colour cube or the 24-step grey ramp
+fn rgb_to_ansi256(r: u8, g: u8, b: u8) -> u8 {
+    const STEPS: [u8; 6] = [0x00, 0x5F, 0x87, 0xAF, 0xD7, 0xFF];
+    let cube_index = |v: u8| -> usize {
+        STEPS
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, s)| (v as i32 - **s as i32).abs())
+            .map(|(i, _)| i)
+            .unwrap()
+    };
+    let dist = |a: Rgb, b: Rgb| -> i32 {
+        let dr = a.0 as i32 - b.0 as i32;
+        let dg = a.1 as i32 - b.1 as i32;
+        let db = a.2 as i32 - b.2 as i32;
+        dr * dr + dg * dg + db * db
+    };
+
+    let (ri, gi, bi) = (cube_index(r), cube_index(g), cube_index(b));
+    let cube = (STEPS[ri], STEPS[gi], STEPS[bi]);
+
+    let avg = (r as u32 + g as u32 + b as u32) / 3;
+    let grey_index = if avg < 8 {
+        0
+    } else {
+        ((avg - 8) / 10).min(23) as u8
+    };
+    let grey_level = 8 + grey_index * 10;
+    let grey = (grey_level, grey_level, grey_level);
+
+    if dist((r, g, b), grey) < dist((r, g, b), cube) {
+        232 + grey_index
+    } else {
+        16 + (36 * ri + 6 * gi + bi) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_colours() {
+        assert_eq!(parse_hex_color("#33ff33"), Some((0x33, 0xFF, 0x33)));
+        assert_eq!(parse_hex_color("1A1000"), Some((0x1A, 0x10, 0x00)));
+        assert_eq!(parse_hex_color("#33ff3"), None);
+        assert_eq!(parse_hex_color("#33ff33ff"), None);
+        assert_eq!(parse_hex_color("#gg0000"), None);
+    }
+
+    #[test]
+    fn maps_to_the_closest_256_colour() {
+        // Corners of the colour cube
+        assert_eq!(rgb_to_ansi256(0x00, 0x00, 0x00), 16);
+        assert_eq!(rgb_to_ansi256(0xFF, 0xFF, 0xFF), 231);
+        assert_eq!(rgb_to_ansi256(0xFF, 0x00, 0x00), 196);
+        // Close to a cube step rather than exactly on it
+        assert_eq!(rgb_to_ansi256(0x30, 0xFC, 0x30), 16 + 36 + 6 * 5 + 1);
+        // Greys between cube steps go to the grey ramp
+        assert_eq!(rgb_to_ansi256(0x80, 0x80, 0x80), 232 + 12);
+        assert_eq!(rgb_to_ansi256(0x12, 0x12, 0x12), 233);
+    }
+
+    #[test]
+    fn palettes_set_every_plane() {
+        let amber = Theme::by_name("amber").unwrap();
+        assert!(
+            THEMES[1..]
+                .iter()
+                .all(|t| t.planes.iter().all(Option::is_some))
+        );
+
+        let theme = amber.with_palette(&[(1, 1, 1), (2, 2, 2), (3, 3, 3)]);
+        assert_eq!(theme.name, "custom");
+        assert_eq!(
+            theme.planes,
+            [
+                Some((1, 1, 1)),
+                Some((2, 2, 2)),
+                Some((3, 3, 3)),
+                amber.planes[3]
+            ]
+        );
+        assert_eq!(
+            theme.color(3, ColorMode::TrueColor),
+            Color::Rgb(0xFF, 0xD0, 0x80)
+        );
+        assert_eq!(THEMES[0].color(1, ColorMode::TrueColor), Color::Reset);
+    }
+}
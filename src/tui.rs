@@ -1,117 +1,389 @@
-use std::io;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 
-use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
 use std::time::{Duration, Instant};
 
 use ratatui::{
     DefaultTerminal, Frame,
     buffer::Buffer,
-    layout::{Constraint, Direction, Layout, Rect},
-    style::Stylize,
+    layout::{Constraint, Layout, Rect},
+    style::{Style, Stylize},
     symbols::border,
-    text::{Line, Text},
+    text::{Line, Span, Text},
     widgets::{Block, Paragraph, Widget},
 };
 
 use crate::chip8::Chip8;
+use crate::disasm;
+use crate::flicker::{FlickerMode, MAX_LEVEL, Persistence};
+use crate::framebuffer::{H, W};
 use crate::isa::OpCode;
+use crate::keymap::Keymap;
+use crate::observe::Observer;
+use crate::savestate::SaveSlots;
+use crate::scheduler::{FrameScheduler, RenderThrottle, Speed};
+use crate::settings::Settings;
+use crate::theme::{ColorMode, Theme};
+use crate::watch::RomWatcher;
+
+const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+
+// Why App::run returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    Quit,
+    Browser,
+}
 
 pub struct App {
     chip8: Chip8,
-    instruction_history: Vec<OpCode>,
-    exit: bool,
+    // Kept around so the game can be reset or reloaded
+    rom: Vec<u8>,
+    settings: Settings,
+    watcher: Option<RomWatcher>,
+    // Names for the stack pane
+    labels: BTreeMap<u16, String>,
+    // Symbol file whose names go on top of the generated labels, read again on reload
+    symbols: Option<PathBuf>,
+    scheduler: FrameScheduler,
+    render: RenderThrottle,
+    slots: SaveSlots,
+    // Shown in the status bar for a couple of seconds, e.g. "saved slot 2"
+    message: Option<(String, Instant)>,
+    // What stopped the game, e.g. an unknown opcode, shown until it runs again
+    error: Option<String>,
+    title: String,
+    instruction_history: VecDeque<OpCode>,
+    exit: Option<Exit>,
     debug: bool,
-}
-
-fn map_key_to_chip8(code: KeyCode) -> Option<usize> {
-    match code {
-        KeyCode::Char('1') => Some(0x1),
-        KeyCode::Char('2') => Some(0x2),
-        KeyCode::Char('3') => Some(0x3),
-        KeyCode::Char('4') => Some(0xC),
-
-        KeyCode::Char('q') | KeyCode::Char('Q') => Some(0x4),
-        KeyCode::Char('w') | KeyCode::Char('W') => Some(0x5),
-        KeyCode::Char('e') | KeyCode::Char('E') => Some(0x6),
-        KeyCode::Char('r') | KeyCode::Char('R') => Some(0xD),
-
-        KeyCode::Char('a') | KeyCode::Char('A') => Some(0x7),
-        KeyCode::Char('s') | KeyCode::Char('S') => Some(0x8),
-        KeyCode::Char('d') | KeyCode::Char('D') => Some(0x9),
-        KeyCode::Char('f') | KeyCode::Char('F') => Some(0xE),
-
-        KeyCode::Char('z') | KeyCode::Char('Z') => Some(0xA),
-        KeyCode::Char('x') | KeyCode::Char('X') => Some(0x0),
-        KeyCode::Char('c') | KeyCode::Char('C') => Some(0xB),
-        KeyCode::Char('v') | KeyCode::Char('V') => Some(0xF),
-        _ => None,
-    }
+    theme: Theme,
+    color_mode: ColorMode,
+    persistence: Persistence,
+    ipf: u32,
+    keymap: Keymap,
+    scale: u16,
+    audio: bool,
+    sounding: bool,
+    // Rendered display rows, only rebuilt when the framebuffer marks them dirty
+    display_rows: Vec<Line<'static>>,
+    // Sees every instruction, for --profile
+    observer: Option<Box<dyn Observer>>,
 }
 
 impl App {
-    pub fn new(rom: &[u8], debug: bool) -> Self {
-        let mut chip8 = Chip8::new();
-        chip8.ROM_loader(rom).expect("Invalid ROM");
+    pub fn new(rom: &[u8], settings: &Settings) -> Self {
+        let chip8 = settings.build_chip8(rom).expect("Invalid ROM");
+        let title = match &settings.title {
+            Some(name) => format!(" Chip8 - {} ", name),
+            None => " Chip8 ".to_string(),
+        };
         Self {
             chip8,
-            instruction_history: Vec::new(),
-            exit: false,
-            debug,
+            rom: rom.to_vec(),
+            settings: settings.clone(),
+            watcher: None,
+            labels: disasm::labels(rom),
+            symbols: None,
+            scheduler: FrameScheduler::new().with_fast_forward(settings.fast_forward),
+            slots: SaveSlots::new(settings.save_state_dir.clone(), rom),
+            message: None,
+            error: None,
+            render: RenderThrottle::new(),
+            title,
+            instruction_history: VecDeque::new(),
+            exit: None,
+            debug: settings.debug,
+            theme: settings.theme,
+            color_mode: settings.color_mode,
+            persistence: Persistence::new(settings.flicker),
+            ipf: settings.ipf,
+            keymap: settings.keymap,
+            scale: settings.scale.max(1),
+            audio: settings.audio,
+            sounding: false,
+            display_rows: Vec::new(),
+            observer: None,
         }
     }
 
-    pub fn add_instruction(&mut self, opcode: OpCode) {
-        self.instruction_history.push(opcode);
-        if self.instruction_history.len() > 15 {
-            self.instruction_history.remove(0);
+    // Reloads the ROM whenever the file changes on disk
+    pub fn watch(&mut self, path: &Path, keep_state: bool) {
+        self.watcher = Some(RomWatcher::new(path, keep_state));
+    }
+
+    pub fn observe(&mut self, observer: Box<dyn Observer>) {
+        self.observer = Some(observer);
+    }
+
+    pub fn take_observer(&mut self) -> Option<Box<dyn Observer>> {
+        self.observer.take()
+    }
+
+    // Names addresses in the debug view. A bad file is reported in the status bar.
+    pub fn load_symbols(&mut self, path: &Path) {
+        self.symbols = Some(path.to_path_buf());
+        match crate::symbols::load(path) {
+            Ok(symbols) => self.labels.extend(symbols),
+            Err(e) => self.show_message(e),
         }
     }
 
-    pub fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
-        let mut last_timer = Instant::now();
+    // Starts the game over as if it had just been loaded
+    pub fn reset(&mut self) {
+        self.chip8 = self.settings.build_chip8(&self.rom).expect("Invalid ROM");
+        self.instruction_history.clear();
+        self.persistence = Persistence::new(self.persistence.mode);
+        self.display_rows.clear();
+    }
 
-        while !self.exit {
-            let instruction = OpCode::from_u16(self.chip8.fetch());
-            self.add_instruction(instruction);
-            self.chip8.execute(instruction).unwrap();
+    fn reload(&mut self, rom: Vec<u8>) {
+        let keep_state = self.watcher.as_ref().is_some_and(|w| w.keep_state);
+        let old_len = self.rom.len();
+        self.rom = rom;
+        self.labels = disasm::labels(&self.rom);
+        if let Some(path) = self.symbols.clone() {
+            self.load_symbols(&path);
+        }
+        // Saves from the old build are unlikely to work with the new one
+        self.slots = SaveSlots::new(self.settings.save_state_dir.clone(), &self.rom);
+        if !keep_state || self.chip8.swap_rom(old_len, &self.rom).is_err() {
+            self.reset();
+        }
+    }
+
+    fn save_state(&mut self) {
+        let slot = self.slots.slot();
+        let message = match self.slots.save(&self.chip8) {
+            Ok(()) => format!("saved slot {}", slot),
+            Err(e) => format!("save failed: {}", e),
+        };
+        self.show_message(message);
+    }
+
+    fn load_state(&mut self) {
+        let slot = self.slots.slot();
+        let message = match self.slots.load(&mut self.chip8) {
+            Ok(()) => format!("loaded slot {}", slot),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => format!("slot {} is empty", slot),
+            Err(e) => format!("load failed: {}", e),
+        };
+        self.display_rows.clear();
+        self.show_message(message);
+    }
+
+    fn show_message(&mut self, message: String) {
+        self.message = Some((message, Instant::now()));
+    }
+
+    pub fn add_instruction(&mut self, opcode: OpCode) {
+        if self.instruction_history.len() == 15 {
+            self.instruction_history.pop_front();
+        }
+        self.instruction_history.push_back(opcode);
+    }
+
+    pub fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<Exit> {
+        while self.exit.is_none() {
+            if let Some(rom) = self.watcher.as_mut().and_then(RomWatcher::poll) {
+                self.reload(rom);
+            }
+            self.handle_events()?;
+            if self.scheduler.frame_due() {
+                self.run_frame(terminal)?;
+            }
+            // Keeps the FPS in the status bar fresh even when the game isn't drawing
+            if self.scheduler.update_fps() {
+                self.render.request();
+            }
+            if self.render.due() {
+                self.draw(terminal)?;
+            }
+            self.scheduler.wait_for_next_frame();
+        }
+        Ok(self.exit.unwrap())
+    }
 
-            if last_timer.elapsed() >= Duration::from_micros(16_667) {
-                self.chip8.tick_timers();
-                last_timer = Instant::now();
+    // Executes one 60 Hz frame worth of instructions followed by the vblank. Instructions
+    // run in a tight batch; drawing is left to the render throttle.
+    fn run_frame(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        self.error = None;
+        for _ in 0..self.ipf {
+            if self.chip8.waiting_for_vblank() {
+                break;
             }
+            if let Some(observer) = &mut self.observer
+                && let Some(op) = self.chip8.current_op()
+            {
+                observer.instruction(&self.chip8, op);
+            }
+            let instruction = match self.chip8.step() {
+                Ok(instruction) => instruction,
+                // Pause where it went wrong, so it can be looked at in the debugger
+                Err(e) => {
+                    self.error = Some(format!("{} at PC 0x{:03X}", e, self.chip8.pc));
+                    if !self.scheduler.is_paused() {
+                        self.scheduler.toggle_pause();
+                    }
+                    self.render.request();
+                    break;
+                }
+            };
+            self.add_instruction(instruction);
 
+            // Without flicker reduction, draws show up mid-frame as they happen, as often
+            // as the throttle allows
             let is_display_instruction =
                 matches!(instruction, OpCode::DRW_x_y_nibble { .. } | OpCode::Cls);
-            if self.debug || is_display_instruction {
-                terminal.draw(|frame| {
-                    if self.debug {
-                        self.draw_debug(frame);
-                    } else {
-                        self.draw_simple(frame);
-                    }
-                })?;
+            if is_display_instruction && self.persistence.mode == FlickerMode::Off {
+                self.render.request();
+                if self.render.due() {
+                    self.draw(terminal)?;
+                }
             }
+        }
 
-            self.handle_events()?;
+        // Self-modifying code shows up in the status bar while debugging
+        let warnings = self.chip8.take_warnings();
+        if let Some(warning) = warnings.last().filter(|_| self.debug) {
+            self.show_message(format!("warning: {}", warning));
+        }
+
+        self.chip8.vblank();
+        if let Some(observer) = &mut self.observer {
+            observer.vblank();
+        }
+        self.persistence.vblank(&self.chip8.fb);
+        self.update_sound()?;
+        // The debug panes change with every instruction, and with flicker reduction on the
+        // screen only changes at vblank
+        if self.debug || self.persistence.mode != FlickerMode::Off {
+            self.render.request();
         }
         Ok(())
     }
 
+    // Terminals can't hold a tone, so ring the bell once each time the buzzer starts
+    fn update_sound(&mut self) -> io::Result<()> {
+        let sounding = self.chip8.sound_active();
+        if self.audio && sounding && !self.sounding {
+            let mut stdout = io::stdout();
+            stdout.write_all(b"\x07")?;
+            stdout.flush()?;
+        }
+        self.sounding = sounding;
+        Ok(())
+    }
+
+    fn draw(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        self.refresh_display();
+        terminal.draw(|frame| {
+            if self.debug {
+                self.draw_debug(frame);
+            } else {
+                self.draw_simple(frame);
+            }
+        })?;
+        Ok(())
+    }
+
     fn handle_events(&mut self) -> io::Result<()> {
-        if event::poll(std::time::Duration::from_millis(1))? {
+        while event::poll(Duration::ZERO)? {
             self.chip8.keypad = [false; 16];
+            // Hotkeys change what's shown even while paused
+            self.render.request();
             match event::read()? {
                 Event::Key(KeyEvent {
                     code: KeyCode::Esc, ..
                 }) => {
-                    self.exit = true;
+                    self.exit = Some(Exit::Quit);
+                }
+                Event::Key(KeyEvent {
+                    code: KeyCode::F(5),
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => {
+                    self.reset();
+                }
+                Event::Key(KeyEvent {
+                    code: KeyCode::F(6),
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => {
+                    self.scheduler.toggle_pause();
+                }
+                Event::Key(KeyEvent {
+                    code: KeyCode::F(7),
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => {
+                    self.scheduler.advance_frame();
+                }
+                Event::Key(KeyEvent {
+                    code: KeyCode::F(8),
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => {
+                    self.scheduler.cycle_slow_motion();
+                }
+                Event::Key(KeyEvent {
+                    code: KeyCode::F(9),
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => {
+                    self.scheduler.toggle_fast_forward();
+                }
+                Event::Key(KeyEvent {
+                    code: KeyCode::F(10),
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => {
+                    self.save_state();
+                }
+                Event::Key(KeyEvent {
+                    code: KeyCode::F(11),
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => {
+                    self.load_state();
+                }
+                Event::Key(KeyEvent {
+                    code: KeyCode::F(12),
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => {
+                    self.slots.next_slot();
+                }
+                Event::Key(KeyEvent {
+                    code: KeyCode::F(4),
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => {
+                    self.exit = Some(Exit::Browser);
+                }
+                Event::Key(KeyEvent {
+                    code: KeyCode::F(2),
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => {
+                    self.theme = self.theme.next();
+                    self.display_rows.clear();
+                }
+                Event::Key(KeyEvent {
+                    code: KeyCode::F(3),
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => {
+                    self.persistence.mode = self.persistence.mode.next();
+                    self.display_rows.clear();
                 }
                 Event::Key(KeyEvent {
                     code,
                     kind: KeyEventKind::Press,
                     ..
                 }) => {
-                    if let Some(k) = map_key_to_chip8(code) {
+                    if let Some(k) = self.keymap.map(code) {
                         self.chip8.keypad[k] = true;
                     }
                 }
@@ -121,13 +393,53 @@ impl App {
         Ok(())
     }
 
+    // Only built when drawing, so it costs nothing between frames
+    fn status_line(&self) -> Line<'static> {
+        let state = if let Some(error) = &self.error {
+            error
+        } else if self.scheduler.is_paused() {
+            "paused"
+        } else if self.chip8.waiting_for_key() {
+            "waiting for key"
+        } else {
+            "running"
+        };
+        let target = match self.scheduler.target_fps() {
+            Some(fps) => format!("{:.0}", fps),
+            None => "max".to_string(),
+        };
+        let mut parts = vec![
+            self.settings.title.clone().unwrap_or_default(),
+            format!("{:.0}/{} fps", self.scheduler.fps(), target),
+            format!("{} ipf", self.ipf),
+            state.to_string(),
+        ];
+        let speed = self.scheduler.speed();
+        if speed != Speed::Multiplier(1.0) {
+            parts.push(speed.label());
+        }
+        if self.chip8.sound_active() {
+            parts.push("♪".to_string());
+        }
+        parts.push(format!("quirks: {}", self.chip8.quirks.name()));
+        let saved = if self.slots.occupied() { "●" } else { "○" };
+        parts.push(format!("slot {} {}", self.slots.slot(), saved));
+        if let Some((message, at)) = &self.message
+            && at.elapsed() < MESSAGE_DURATION
+        {
+            parts.push(message.clone());
+        }
+        Line::from(format!(" {} ", parts.join(" | ")))
+    }
+
     fn draw_debug(&self, frame: &mut Frame) {
         frame.render_widget(self, frame.area());
     }
 
     fn draw_simple(&self, frame: &mut Frame) {
         let main_block = Block::bordered()
-            .title(Line::from(" Chip8 ".bold()).centered())
+            .title(Line::from(self.title.clone().bold()).centered())
+            .title_bottom(self.status_line().centered())
             .border_set(border::THICK);
         let inner_area = main_block.inner(frame.area());
         main_block.render(frame.area(), frame.buffer_mut());
@@ -138,7 +450,8 @@ impl App {
 impl Widget for &App {
     fn render(self, area: Rect, buf: &mut Buffer) {
         let main_block = Block::bordered()
-            .title(Line::from(" Chip8 ".bold()).centered())
+            .title(Line::from(self.title.clone().bold()).centered())
+            .title_bottom(self.status_line().centered())
             .border_set(border::THICK);
         let inner_area = main_block.inner(area);
         main_block.render(area, buf);
@@ -152,9 +465,13 @@ impl Widget for &App {
         self.render_pixel_display(horizontal_layout[0], buf);
 
         // Right side layout
-        let right_layout =
-            Layout::vertical([Constraint::Percentage(50), Constraint::Percentage(50)])
-                .split(horizontal_layout[1]);
+        let right_layout = Layout::vertical([
+            Constraint::Length(7),
+            Constraint::Length(8),
+            Constraint::Fill(1),
+            Constraint::Fill(1),
+        ])
+        .split(horizontal_layout[1]);
 
         // Registers
         let register_text = Text::from(vec![
@@ -199,18 +516,72 @@ impl Widget for &App {
             .centered();
         register_paragraph.render(right_layout[0], buf);
 
+        // Timers and the keypad, laid out like the real 4x4 keypad
+        let mut timer_lines = vec![
+            Line::from(format!(
+                "DT:{:02X} ST:{:02X}",
+                self.chip8.delay_timer(),
+                self.chip8.sound_timer()
+            )),
+            Line::default(),
+        ];
+        for row in [
+            [1, 2, 3, 0xC],
+            [4, 5, 6, 0xD],
+            [7, 8, 9, 0xE],
+            [0xA, 0, 0xB, 0xF],
+        ] {
+            let keys = row.iter().map(|&k| {
+                let key = Span::from(format!(" {:X} ", k));
+                if self.chip8.keypad[k] {
+                    key.reversed()
+                } else {
+                    key
+                }
+            });
+            timer_lines.push(Line::from_iter(keys));
+        }
+        let timer_block = Block::bordered()
+            .title(Line::from(" Timers & Keys ".bold()).centered())
+            .border_set(border::THICK);
+        Paragraph::new(timer_lines)
+            .block(timer_block)
+            .centered()
+            .render(right_layout[1], buf);
+
+        // Call stack, innermost frame first
+        let mut stack_lines = vec![Line::from(format!(
+            "PC {:03X} {}",
+            self.chip8.pc,
+            disasm::symbolize(&self.labels, self.chip8.pc)
+        ))];
+        for &ret in self.chip8.stack().iter().rev() {
+            stack_lines.push(Line::from(format!(
+                "ret {:03X} {}",
+                ret,
+                disasm::symbolize(&self.labels, ret)
+            )));
+        }
+        let stack_block = Block::bordered()
+            .title(Line::from(" Stack ".bold()).centered())
+            .border_set(border::THICK);
+        Paragraph::new(stack_lines)
+            .block(stack_block)
+            .centered()
+            .render(right_layout[2], buf);
+
         // Instruction history
         let history_lines: Vec<Line> = self
             .instruction_history
             .iter()
-            .map(|opcode| Line::from(opcode.to_string()))
+            .map(|&opcode| Line::from(disasm::format_op(opcode, &self.labels)))
             .collect();
         let history_text = Text::from(history_lines);
         let history_block = Block::bordered()
             .title(Line::from(" History ".bold()).centered())
             .border_set(border::THICK);
         let history_paragraph = Paragraph::new(history_text).block(history_block).centered();
-        history_paragraph.render(right_layout[1], buf);
+        history_paragraph.render(right_layout[3], buf);
     }
 }
 
@@ -224,60 +595,58 @@ impl App {
         pixel_paragraph.render(area, buf);
     }
 
-    fn frame_buffer_to_text(&self) -> Text {
-        let mut lines = Vec::new();
-        for y in 0..32 {
-            let mut line_chars = String::new();
-            for x in 0..64 {
-                let pixel = if self.chip8.fb[y][x] { '█' } else { '░' };
-                // 2:1 scaling looks better imo
-                line_chars.push(pixel);
-                line_chars.push(pixel);
+    // Re-renders the display rows that changed since the last draw
+    fn refresh_display(&mut self) {
+        let fb_dirty = self.chip8.fb.take_dirty();
+        let persistence_dirty = self.persistence.take_dirty();
+        let mut dirty = if self.persistence.mode == FlickerMode::Off {
+            fb_dirty
+        } else {
+            persistence_dirty
+        };
+        if self.display_rows.len() != H {
+            self.display_rows = vec![Line::default(); H];
+            dirty = u64::MAX;
+        }
+        for y in 0..H {
+            if (dirty >> y) & 1 == 1 {
+                self.display_rows[y] = self.render_row(y);
             }
-            lines.push(Line::from(line_chars));
         }
+    }
 
-        Text::from(lines)
+    fn frame_buffer_to_text(&self) -> Text<'_> {
+        let lines = self
+            .display_rows
+            .iter()
+            .flat_map(|line| std::iter::repeat_n(line.clone(), self.scale as usize));
+        Text::from_iter(lines)
     }
-}
 
-impl OpCode {
-    fn to_string(&self) -> String {
-        match self {
-            OpCode::Cls => "CLS".to_string(),
-            OpCode::Ret => "RET".to_string(),
-            OpCode::Jp { addr } => format!("JP 0x{:03X}", addr),
-            OpCode::Call { addr } => format!("CALL 0x{:03X}", addr),
-            OpCode::SE_vx_byte { x, kk } => format!("SE V{}, 0x{:02X}", x, kk),
-            OpCode::SNE_vx_byte { x, kk } => format!("SNE V{}, 0x{:02X}", x, kk),
-            OpCode::SE_vx_vy { x, y } => format!("SE V{}, V{}", x, y),
-            OpCode::LD_vx_byte { x, kk } => format!("LD V{}, 0x{:02X}", x, kk),
-            OpCode::ADD_vx_byte { x, kk } => format!("ADD V{}, 0x{:02X}", x, kk),
-            OpCode::LD_vx_vy { x, y } => format!("LD V{}, V{}", x, y),
-            OpCode::OR_vx_vy { x, y } => format!("OR V{}, V{}", x, y),
-            OpCode::AND_vx_vy { x, y } => format!("AND V{}, V{}", x, y),
-            OpCode::XOR_vx_vy { x, y } => format!("XOR V{}, V{}", x, y),
-            OpCode::ADD_vx_vy { x, y } => format!("ADD V{}, V{}", x, y),
-            OpCode::SUB_vx_vy { x, y } => format!("SUB V{}, V{}", x, y),
-            OpCode::SHR_vx_vy { x, y } => format!("SHR V{}, V{}", x, y),
-            OpCode::SUBN_vx_vy { x, y } => format!("SUBN V{}, V{}", x, y),
-            OpCode::SHL_vx_vy { x, y } => format!("SHL V{}, V{}", x, y),
-            OpCode::SNE_vx_vy { x, y } => format!("SNE V{}, V{}", x, y),
-            OpCode::LD_I_addr { addr } => format!("LD I, 0x{:03X}", addr),
-            OpCode::JP_v0_addr { addr } => format!("JP V0, 0x{:03X}", addr),
-            OpCode::RND_vx_byte { x, kk } => format!("RND V{}, 0x{:02X}", x, kk),
-            OpCode::DRW_x_y_nibble { x, y, n } => format!("DRW V{}, V{}, {}", x, y, n),
-            OpCode::SKP_vx { x } => format!("SKP V{}", x),
-            OpCode::SKNP_vx { x } => format!("SKNP V{}", x),
-            OpCode::LD_vx_dt { x } => format!("LD V{}, DT", x),
-            OpCode::LD_vx_k { x } => format!("LD V{}, K", x),
-            OpCode::LD_dt_vx { x } => format!("LD DT, V{}", x),
-            OpCode::LD_st_vx { x } => format!("LD ST, V{}", x),
-            OpCode::ADD_I_vx { x } => format!("ADD I, V{}", x),
-            OpCode::LD_F_vx { x } => format!("LD F, V{}", x),
-            OpCode::LD_B_vx { x } => format!("LD B, V{}", x),
-            OpCode::LD_I_vx { x } => format!("LD I, V{}", x),
-            OpCode::LD_vx_I { x } => format!("LD V{}, I", x),
+    fn render_row(&self, y: usize) -> Line<'static> {
+        // Group runs of equally bright pixels into one span each
+        let mut spans = Vec::new();
+        let mut run = String::new();
+        let mut run_level = 0;
+        for x in 0..W {
+            let level = self.persistence.level(&self.chip8.fb, x, y);
+            if level != run_level && !run.is_empty() {
+                spans.push(self.pixel_span(std::mem::take(&mut run), run_level));
+            }
+            run_level = level;
+            let (pixel, _) = self.theme.shade(level, MAX_LEVEL, self.color_mode);
+            // 2:1 scaling looks better imo
+            for _ in 0..2 * self.scale {
+                run.push(pixel);
+            }
         }
+        spans.push(self.pixel_span(run, run_level));
+        Line::from(spans)
+    }
+
+    fn pixel_span(&self, text: String, level: u8) -> Span<'static> {
+        let (_, fg) = self.theme.shade(level, MAX_LEVEL, self.color_mode);
+        let style = Style::new().fg(fg).bg(self.theme.color(0, self.color_mode));
+        Span::styled(text, style)
     }
 }
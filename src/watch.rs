@@ -0,0 +1,53 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::rom;
+
+// Checking the file every frame is wasteful, twice a second is plenty
+const POLL_FRAMES: u32 = 30;
+
+// Polls a ROM file for changes so it can be reloaded after each build
+pub struct RomWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    frames: u32,
+    // Keep the machine state when reloading instead of starting over
+    pub keep_state: bool,
+}
+
+impl RomWatcher {
+    pub fn new(path: &Path, keep_state: bool) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            modified: modified(path),
+            frames: 0,
+            keep_state,
+        }
+    }
+
+    // Called once per frame. Returns the new ROM when the file has changed and can be read.
+    // Half-written files (empty or too big) are skipped and tried again next time.
+    pub fn poll(&mut self) -> Option<Vec<u8>> {
+        self.frames += 1;
+        if self.frames < POLL_FRAMES {
+            return None;
+        }
+        self.frames = 0;
+
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        let rom = fs::read(&self.path).ok()?;
+        if rom.is_empty() || rom.len() > rom::MAX_SIZE {
+            return None;
+        }
+        self.modified = modified;
+        Some(rom)
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}